@@ -47,6 +47,8 @@ use bytes::Bytes;
 use futures_util::{Stream, StreamExt};
 use serde::Deserialize;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, info};
 
 /// A client for interacting with DoorBird devices via their HTTP API.
@@ -63,6 +65,21 @@ pub struct Client {
     password: String,
     /// Internal HTTP client
     client: reqwest::Client,
+    /// Notified about every request (e.g. for metrics)
+    observer: Option<Arc<dyn RequestObserver>>,
+}
+
+/// Receives the outcome of every request a `Client` makes
+///
+/// Useful for exporting request counts, status codes and latencies.
+pub trait RequestObserver: Send + Sync {
+    /// Called once the response headers arrive or the request fails
+    ///
+    /// * `endpoint` - CGI name, e.g. `"info.cgi"`
+    /// * `status` - HTTP status code, or `None` if no response was received
+    /// * `elapsed` - Time until the response headers arrived. For streaming uploads
+    ///   (`audio-transmit.cgi`) this covers the whole transmission.
+    fn on_request(&self, endpoint: &'static str, status: Option<u16>, elapsed: Duration);
 }
 
 /// Video quality/resolution options for RTSP streaming
@@ -222,9 +239,31 @@ impl Client {
             username,
             password,
             client,
+            observer: None,
         }
     }
 
+    /// Reports the outcome of every request to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Sends a request, reporting its outcome to the observer
+    async fn send(
+        &self,
+        endpoint: &'static str,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let started = Instant::now();
+        let result = request.send().await;
+        if let Some(observer) = &self.observer {
+            let status = result.as_ref().ok().map(|r| r.status().as_u16());
+            observer.on_request(endpoint, status, started.elapsed());
+        }
+        result
+    }
+
     /// Retrieves device information from the DoorBird.
     ///
     /// **API Endpoint:** `GET /bha-api/info.cgi`
@@ -254,10 +293,12 @@ impl Client {
         debug!("Fetching device info from {}", url);
 
         let response = self
-            .client
-            .get(&url)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .send(
+                "info.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password)),
+            )
             .await
             .context("Failed to send info request")?;
 
@@ -327,11 +368,14 @@ impl Client {
         info!("Connecting to DoorBird audio stream at {}", url);
 
         let response = self
-            .client
-            .get(&url)
-            .basic_auth(&self.username, Some(&self.password))
-            .timeout(std::time::Duration::from_secs(3600)) // 1 hour timeout for streaming
-            .send()
+            .send(
+                "audio-receive.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password))
+                    // 1 hour timeout for streaming
+                    .timeout(std::time::Duration::from_secs(3600)),
+            )
             .await
             .context("Failed to send audio receive request")?;
 
@@ -403,15 +447,17 @@ impl Client {
         let body = reqwest::Body::wrap_stream(audio_stream);
 
         let response = self
-            .client
-            .post(&url)
-            .basic_auth(&self.username, Some(&self.password))
-            .header("Content-Type", "audio/basic")
-            .header("Content-Length", "9999999")
-            .header("Connection", "Keep-Alive")
-            .header("Cache-Control", "no-cache")
-            .body(body)
-            .send()
+            .send(
+                "audio-transmit.cgi",
+                self.client
+                    .post(&url)
+                    .basic_auth(&self.username, Some(&self.password))
+                    .header("Content-Type", "audio/basic")
+                    .header("Content-Length", "9999999")
+                    .header("Connection", "Keep-Alive")
+                    .header("Cache-Control", "no-cache")
+                    .body(body),
+            )
             .await
             .context("Failed to send audio transmit request")?;
 
@@ -531,10 +577,12 @@ impl Client {
         debug!("Opening door/gate via {}", url);
 
         let response = self
-            .client
-            .get(&url)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
+            .send(
+                "open-door.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password)),
+            )
             .await
             .context("Failed to send open door request")?;
 
@@ -552,6 +600,111 @@ impl Client {
         }
     }
 
+    /// Switches on the infrared light of the DoorBird device (night vision).
+    ///
+    /// **API Endpoint:** `GET /bha-api/light-on.cgi`
+    ///
+    /// **Required Permission:** Valid user with "watch always" permission or
+    /// ring event in the past 5 minutes
+    ///
+    /// The light switches itself off again after a few minutes.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` on success, or an error if the request fails.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use doorbird::Client;
+    /// # async fn example() -> anyhow::Result<()> {
+    /// # let client = Client::new("http://192.168.1.100".into(), "user".into(), "pass".into());
+    /// client.light_on().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn light_on(&self) -> Result<()> {
+        let url = format!("{}/bha-api/light-on.cgi", self.base_url);
+        debug!("Switching on light via {}", url);
+
+        let response = self
+            .send(
+                "light-on.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password)),
+            )
+            .await
+            .context("Failed to send light on request")?;
+
+        let status = response.status();
+        if status.is_success() {
+            info!("Light switched on");
+            Ok(())
+        } else if status.as_u16() == 204 {
+            anyhow::bail!(
+                "Light on request rejected: no permission (204 No Content). \
+                User may not have 'watch always' permission or no recent ring event."
+            )
+        } else {
+            anyhow::bail!("Light on request failed with status: {}", status)
+        }
+    }
+
+    /// Captures a live image from the DoorBird camera.
+    ///
+    /// **API Endpoint:** `GET /bha-api/image.cgi`
+    ///
+    /// **Required Permission:** Valid user with "watch always" permission or
+    /// ring event in the past 5 minutes
+    ///
+    /// **Image Format:** JPEG at the device default resolution.
+    ///
+    /// # Returns
+    ///
+    /// The JPEG image data.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use doorbird::Client;
+    /// # async fn example() -> anyhow::Result<()> {
+    /// # let client = Client::new("http://192.168.1.100".into(), "user".into(), "pass".into());
+    /// let jpeg = client.image().await?;
+    /// std::fs::write("snapshot.jpg", &jpeg)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn image(&self) -> Result<Bytes> {
+        let url = format!("{}/bha-api/image.cgi", self.base_url);
+        debug!("Fetching live image from {}", url);
+
+        let response = self
+            .send(
+                "image.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password)),
+            )
+            .await
+            .context("Failed to send image request")?;
+
+        let status = response.status();
+        if status.as_u16() == 204 {
+            anyhow::bail!(
+                "Image request rejected: no permission (204 No Content). \
+                User may not have 'watch always' permission or no recent ring event."
+            )
+        } else if !status.is_success() {
+            anyhow::bail!("Image request failed with status: {}", status);
+        }
+
+        response
+            .bytes()
+            .await
+            .context("Failed to read image response")
+    }
+
     /// Monitors for doorbell and motion sensor events from the DoorBird device.
     ///
     /// **API Endpoint:** `GET /bha-api/monitor.cgi?ring=doorbell,motionsensor`
@@ -606,11 +759,14 @@ impl Client {
         info!("Connecting to DoorBird event monitor at {}", url);
 
         let response = self
-            .client
-            .get(&url)
-            .basic_auth(&self.username, Some(&self.password))
-            .timeout(std::time::Duration::from_secs(3600)) // 1 hour timeout for streaming
-            .send()
+            .send(
+                "monitor.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password))
+                    // 1 hour timeout for streaming
+                    .timeout(std::time::Duration::from_secs(3600)),
+            )
             .await
             .context("Failed to send monitor request")?;
 
@@ -631,6 +787,72 @@ impl Client {
 
         Ok(Box::pin(event_stream))
     }
+
+    /// Starts receiving the live MJPEG video stream from the DoorBird device.
+    ///
+    /// **API Endpoint:** `GET /bha-api/video.cgi`
+    ///
+    /// **Required Permission:** Valid user with "watch always" permission or
+    /// ring event in the past 5 minutes
+    ///
+    /// **Video Format:** A `multipart/x-mixed-replace` stream of JPEG images at the
+    /// device default resolution, at up to 8fps.
+    ///
+    /// **Note:** The DoorBird device serves only one video stream consumer at a time.
+    /// The connection can be interrupted if the official DoorBird app requests the stream,
+    /// as it has precedence over LAN API users.
+    ///
+    /// # Returns
+    ///
+    /// A stream of `Bytes`, each containing one complete JPEG image.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use doorbird::Client;
+    /// # use futures_util::StreamExt;
+    /// # async fn example() -> anyhow::Result<()> {
+    /// # let client = Client::new("http://192.168.1.100".into(), "user".into(), "pass".into());
+    /// let mut frames = client.mjpeg_stream().await?;
+    ///
+    /// while let Some(frame) = frames.next().await {
+    ///     println!("Received JPEG frame of {} bytes", frame?.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn mjpeg_stream(&self) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>> {
+        let url = format!("{}/bha-api/video.cgi", self.base_url);
+        info!("Connecting to DoorBird MJPEG stream at {}", url);
+
+        let response = self
+            .send(
+                "video.cgi",
+                self.client
+                    .get(&url)
+                    .basic_auth(&self.username, Some(&self.password))
+                    // 1 hour timeout for streaming
+                    .timeout(std::time::Duration::from_secs(3600)),
+            )
+            .await
+            .context("Failed to send MJPEG stream request")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("MJPEG stream request failed with status: {}", status);
+        }
+
+        // e.g. "multipart/x-mixed-replace; boundary=ioboundary"
+        let boundary = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(multipart_boundary);
+        let byte_stream = response.bytes_stream();
+        let frame_stream = parse_mjpeg_stream(byte_stream, boundary);
+
+        Ok(Box::pin(frame_stream))
+    }
 }
 
 /// Parses the multipart monitor stream into individual events.
@@ -732,3 +954,389 @@ fn extract_event_from_buffer(buffer: &mut Vec<u8>) -> Option<MonitorEvent> {
 
     None
 }
+
+/// Largest JPEG accepted from the MJPEG stream; anything bigger is skipped
+const MAX_FRAME_BYTES: usize = 4 * 1024 * 1024;
+
+/// Largest multipart part header accepted before the stream is resynchronized
+const MAX_PART_HEADER_BYTES: usize = 4096;
+
+/// Parses a multipart MJPEG stream into individual JPEG images.
+///
+/// The stream format is:
+/// ```text
+/// --ioboundary\r\n
+/// Content-Type: image/jpeg\r\n
+/// Content-Length: 52341\r\n
+/// \r\n
+/// <JPEG data>\r\n
+/// --ioboundary\r\n
+/// ...
+/// ```
+///
+/// `boundary` comes from the response's `Content-Type`; without one, the first
+/// delimiter line of the body is used. Parts without a `Content-Length` end at the
+/// next delimiter.
+fn parse_mjpeg_stream(
+    byte_stream: impl Stream<Item = std::result::Result<Bytes, reqwest::Error>> + Send + 'static,
+    boundary: Option<String>,
+) -> impl Stream<Item = Result<Bytes>> + Send {
+    let pinned_stream = Box::pin(byte_stream);
+
+    futures_util::stream::try_unfold(
+        (pinned_stream, MjpegParser::new(boundary)),
+        |(mut stream, mut parser)| async move {
+            loop {
+                if let Some(frame) = parser.next_frame() {
+                    return Ok(Some((frame, (stream, parser))));
+                }
+
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        parser.buffer.extend_from_slice(&chunk);
+                    }
+                    Some(Err(e)) => {
+                        return Err(anyhow::anyhow!("Stream error: {}", e));
+                    }
+                    None => {
+                        return Ok(None);
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Extracts the `boundary` parameter of a multipart `Content-Type` header value.
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Incremental parser for the parts of a multipart MJPEG stream.
+struct MjpegParser {
+    /// Delimiter line (`--` followed by the boundary), once known
+    delimiter: Option<Vec<u8>>,
+    /// Received bytes not yet consumed
+    buffer: Vec<u8>,
+    /// Largest part accepted
+    max_frame_bytes: usize,
+}
+
+impl MjpegParser {
+    fn new(boundary: Option<String>) -> Self {
+        Self {
+            delimiter: boundary.map(|b| format!("--{}", b).into_bytes()),
+            buffer: Vec::new(),
+            max_frame_bytes: MAX_FRAME_BYTES,
+        }
+    }
+
+    /// Extracts the next complete JPEG image from the buffer, removing consumed bytes.
+    ///
+    /// Returns None if no complete image is available yet.
+    fn next_frame(&mut self) -> Option<Bytes> {
+        loop {
+            let delimiter = match &self.delimiter {
+                Some(delimiter) => delimiter.clone(),
+                None => {
+                    let delimiter = self.find_delimiter_line()?;
+                    self.delimiter = Some(delimiter.clone());
+                    delimiter
+                }
+            };
+
+            let Some(start) = find(&self.buffer, &delimiter) else {
+                // Keep enough bytes for a delimiter split across chunks
+                let keep = delimiter.len() - 1;
+                if self.buffer.len() > keep {
+                    self.buffer.drain(0..self.buffer.len() - keep);
+                }
+                return None;
+            };
+            self.buffer.drain(0..start);
+
+            let headers_start = delimiter.len();
+            let Some(headers_len) = find(&self.buffer[headers_start..], b"\r\n\r\n") else {
+                if self.buffer.len() > headers_start + MAX_PART_HEADER_BYTES {
+                    debug!("MJPEG part header too long, skipping part");
+                    self.buffer.drain(0..headers_start);
+                    continue;
+                }
+                return None;
+            };
+            let body_start = headers_start + headers_len + 4;
+            let content_length = content_length(&self.buffer[headers_start..body_start]);
+
+            let body_end = match content_length {
+                Some(len) if len > self.max_frame_bytes => {
+                    debug!("Skipping MJPEG part of {} bytes", len);
+                    self.buffer.drain(0..headers_start);
+                    continue;
+                }
+                Some(len) => {
+                    if self.buffer.len() < body_start + len {
+                        return None;
+                    }
+                    body_start + len
+                }
+                None => match find(&self.buffer[body_start..], &delimiter) {
+                    // The CRLF before the next delimiter belongs to the delimiter
+                    Some(pos) => {
+                        let end = body_start + pos;
+                        if self.buffer[body_start..end].ends_with(b"\r\n") {
+                            end - 2
+                        } else {
+                            end
+                        }
+                    }
+                    None => {
+                        if self.buffer.len() > body_start + self.max_frame_bytes {
+                            debug!(
+                                "MJPEG part without end exceeds {} bytes, skipping",
+                                self.max_frame_bytes
+                            );
+                            self.buffer.drain(0..headers_start);
+                            continue;
+                        }
+                        return None;
+                    }
+                },
+            };
+
+            let frame = Bytes::copy_from_slice(&self.buffer[body_start..body_end]);
+            self.buffer.drain(0..body_end);
+            if !frame.starts_with(&[0xFF, 0xD8]) {
+                debug!(
+                    "Skipping MJPEG part that is not a JPEG ({} bytes)",
+                    frame.len()
+                );
+                continue;
+            }
+            debug!("Extracted MJPEG frame: {} bytes", frame.len());
+            return Some(frame);
+        }
+    }
+
+    /// Finds the first line starting with `--` (the delimiter, when the response
+    /// header named no boundary), discarding anything before it.
+    fn find_delimiter_line(&mut self) -> Option<Vec<u8>> {
+        let start = (0..self.buffer.len().saturating_sub(1)).find(|&i| {
+            self.buffer[i..].starts_with(b"--") && (i == 0 || self.buffer[i - 1] == b'\n')
+        });
+        let Some(start) = start else {
+            if self.buffer.len() > MAX_PART_HEADER_BYTES {
+                self.buffer.drain(0..self.buffer.len() - 1);
+            }
+            return None;
+        };
+        self.buffer.drain(0..start);
+        let Some(end) = find(&self.buffer, b"\r\n") else {
+            if self.buffer.len() > MAX_PART_HEADER_BYTES {
+                self.buffer.drain(0..2);
+            }
+            return None;
+        };
+        let line = self.buffer[..end].trim_ascii_end().to_vec();
+        (line.len() > 2).then_some(line)
+    }
+}
+
+/// Position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Value of the `Content-Length` header in a multipart part header block.
+fn content_length(headers: &[u8]) -> Option<usize> {
+    String::from_utf8_lossy(headers).lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("content-length") {
+            return None;
+        }
+        value.trim().parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A JPEG with an embedded EXIF thumbnail, so it contains an EOI before its end
+    const JPEG: &[u8] = &[
+        0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xD8, 0xFF, 0xD9, 0x12, 0x34, 0xFF, 0xD9,
+    ];
+
+    fn part(jpeg: &[u8], with_length: bool) -> Vec<u8> {
+        let mut part = b"--ioboundary\r\nContent-Type: image/jpeg\r\n".to_vec();
+        if with_length {
+            part.extend_from_slice(format!("Content-Length: {}\r\n", jpeg.len()).as_bytes());
+        }
+        part.extend_from_slice(b"\r\n");
+        part.extend_from_slice(jpeg);
+        part.extend_from_slice(b"\r\n");
+        part
+    }
+
+    /// Feeds `data` in chunks of `chunk_size` and collects the frames
+    fn parse(parser: &mut MjpegParser, data: &[u8], chunk_size: usize) -> Vec<Bytes> {
+        let mut frames = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            parser.buffer.extend_from_slice(chunk);
+            while let Some(frame) = parser.next_frame() {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_frames_with_content_length() {
+        let data = [part(JPEG, true), part(JPEG, true)].concat();
+        let mut parser = MjpegParser::new(Some("ioboundary".into()));
+        let frames = parse(&mut parser, &data, data.len());
+        // The thumbnail's EOI does not cut the frame short
+        assert_eq!(frames, vec![JPEG, JPEG]);
+    }
+
+    #[test]
+    fn test_frames_split_across_chunks() {
+        for with_length in [true, false] {
+            let data = [
+                part(JPEG, with_length),
+                part(JPEG, with_length),
+                part(JPEG, with_length),
+            ]
+            .concat();
+            let mut parser = MjpegParser::new(Some("ioboundary".into()));
+            let frames = parse(&mut parser, &data, 1);
+            // Without a length, a part ends when the next delimiter arrives
+            let expected = if with_length { 3 } else { 2 };
+            assert_eq!(frames, vec![JPEG; expected], "with_length: {}", with_length);
+        }
+    }
+
+    #[test]
+    fn test_garbage_before_first_boundary() {
+        let data = [&[0xFF, 0xD8, 0x00, 0x2D, 0x2D][..], &part(JPEG, true)].concat();
+        let mut parser = MjpegParser::new(Some("ioboundary".into()));
+        assert_eq!(parse(&mut parser, &data, 3), vec![JPEG]);
+
+        // Boundary learned from the body
+        let data = [&b"\r\n"[..], &part(JPEG, true), &part(JPEG, true)].concat();
+        let mut parser = MjpegParser::new(None);
+        assert_eq!(parse(&mut parser, &data, 5), vec![JPEG, JPEG]);
+    }
+
+    #[test]
+    fn test_parts_that_are_not_jpeg_are_skipped() {
+        let data = [part(b"not a jpeg", true), part(JPEG, true)].concat();
+        let mut parser = MjpegParser::new(Some("ioboundary".into()));
+        assert_eq!(parse(&mut parser, &data, 7), vec![JPEG]);
+    }
+
+    #[test]
+    fn test_part_without_end_is_bounded() {
+        let mut parser = MjpegParser::new(Some("ioboundary".into()));
+        parser.max_frame_bytes = 64;
+
+        // A JPEG that never ends: no EOI, no Content-Length, no next delimiter
+        let mut data = b"--ioboundary\r\n\r\n\xFF\xD8".to_vec();
+        data.extend_from_slice(&[0x55; 1000]);
+        for chunk in data.chunks(16) {
+            parser.buffer.extend_from_slice(chunk);
+            assert!(parser.next_frame().is_none());
+            assert!(
+                parser.buffer.len() <= 128,
+                "buffer grew to {}",
+                parser.buffer.len()
+            );
+        }
+
+        // The parser resynchronizes on the next part
+        let data = [part(JPEG, true)].concat();
+        assert_eq!(parse(&mut parser, &data, 16), vec![JPEG]);
+
+        // Oversized parts with a length are skipped as well
+        let mut big = vec![0xFF, 0xD8];
+        big.resize(100, 0x55);
+        let data = [part(&big, true), part(JPEG, true)].concat();
+        assert_eq!(parse(&mut parser, &data, 16), vec![JPEG]);
+    }
+
+    /// Remembers every reported request
+    #[derive(Default)]
+    struct RecordingObserver(std::sync::Mutex<Vec<(&'static str, Option<u16>)>>);
+
+    impl RequestObserver for RecordingObserver {
+        fn on_request(&self, endpoint: &'static str, status: Option<u16>, _elapsed: Duration) {
+            self.0.lock().unwrap().push((endpoint, status));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_every_request() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answers every request with 401, like a DoorBird given wrong credentials
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+
+        let observer = Arc::new(RecordingObserver::default());
+        let client = Client::new(format!("http://{}", addr), "user".into(), "pass".into())
+            .with_observer(observer.clone());
+        assert!(client.info().await.is_err());
+        assert!(client.open_door(None).await.is_err());
+
+        // Nothing listens on a port the OS just released
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let unreachable = Client::new(
+            format!("http://{}", closed_addr),
+            "user".into(),
+            "pass".into(),
+        )
+        .with_observer(observer.clone());
+        assert!(unreachable.info().await.is_err());
+
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec![
+                ("info.cgi", Some(401)),
+                ("open-door.cgi", Some(401)),
+                ("info.cgi", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multipart_boundary() {
+        assert_eq!(
+            multipart_boundary("multipart/x-mixed-replace; boundary=ioboundary"),
+            Some("ioboundary".to_string())
+        );
+        assert_eq!(
+            multipart_boundary("multipart/x-mixed-replace;Boundary=\"frame\""),
+            Some("frame".to_string())
+        );
+        assert_eq!(multipart_boundary("multipart/x-mixed-replace"), None);
+    }
+}
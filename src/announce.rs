@@ -0,0 +1,293 @@
+//! Announcements through the doorbell speaker
+//!
+//! `POST /api/announce` plays an audio file at the door, e.g. "please leave the
+//! parcel by the door" when nobody is home:
+//! - `POST /api/announce?clip=parcel` plays `parcel.<ext>` from `announce.dir`
+//! - `POST /api/announce` with a WAV, Opus or MP3 file as the body plays the upload
+//!
+//! The audio is decoded with ffmpeg and μ-law encoded up front (`audio_file`), then
+//! streamed to `Client::audio_transmit` at playback speed. An announcement holds
+//! the push-to-talk lock like a talking user, so it gets `409 Conflict` while
+//! someone is talking and browser viewers see the line as busy while it plays.
+//! The request returns `202 Accepted` as soon as playback starts.
+//! `GET /api/announce/clips` lists the configured clips.
+
+use crate::audio_file;
+use crate::webrtc::ActiveTransmission;
+use crate::AppState;
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Largest accepted upload (several minutes of MP3 or WAV)
+pub const MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+/// What to play
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// File in `announce.dir`, by name without extension
+    Clip(String),
+    /// Uploaded audio file
+    Upload(Bytes),
+}
+
+/// Why an announcement could not be played
+#[derive(Debug)]
+pub enum AnnounceError {
+    ShuttingDown,
+    /// Someone is talking through the doorbell
+    Busy,
+    /// `announce.dir` is not configured
+    NoClipDir,
+    UnknownClip(String),
+    /// The audio could not be decoded
+    Decode(anyhow::Error),
+}
+
+impl AnnounceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AnnounceError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            AnnounceError::Busy => StatusCode::CONFLICT,
+            AnnounceError::NoClipDir | AnnounceError::UnknownClip(_) => StatusCode::NOT_FOUND,
+            AnnounceError::Decode(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl fmt::Display for AnnounceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnounceError::ShuttingDown => write!(f, "server shutting down"),
+            AnnounceError::Busy => write!(f, "another user is already talking"),
+            AnnounceError::NoClipDir => write!(f, "no announcement directory configured"),
+            AnnounceError::UnknownClip(name) => write!(f, "unknown clip '{}'", name),
+            AnnounceError::Decode(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for AnnounceError {}
+
+/// An announcement that started playing
+#[derive(Debug, Serialize)]
+pub struct Announcement {
+    pub id: Uuid,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Decode `source` and start playing it at the door
+///
+/// Returns once playback has started; the push-to-talk lock is held until it ends.
+pub async fn play(state: &AppState, source: Source) -> Result<Announcement, AnnounceError> {
+    if state.shutdown.is_cancelled() {
+        return Err(AnnounceError::ShuttingDown);
+    }
+    let id = Uuid::new_v4();
+
+    // Decode before taking the lock, so a bad file doesn't interrupt anyone
+    let (label, ulaw) = match source {
+        Source::Clip(name) => {
+            let dir = state
+                .announce_dir
+                .as_deref()
+                .ok_or(AnnounceError::NoClipDir)?;
+            let path =
+                clip_path(dir, &name).ok_or_else(|| AnnounceError::UnknownClip(name.clone()))?;
+            let ulaw = tokio::task::spawn_blocking(move || audio_file::decode_ulaw(&path))
+                .await
+                .map_err(|e| AnnounceError::Decode(e.into()))?
+                .map_err(AnnounceError::Decode)?;
+            (format!("clip '{}'", name), ulaw)
+        }
+        Source::Upload(body) => {
+            let ulaw = decode_upload(id, body)
+                .await
+                .map_err(AnnounceError::Decode)?;
+            ("upload".to_string(), ulaw)
+        }
+    };
+    let duration = audio_file::duration(&ulaw);
+
+    if !state.ptt_state.try_acquire(id).await {
+        return Err(AnnounceError::Busy);
+    }
+    info!(
+        "📢 Playing announcement {} ({}, {:.1}s)",
+        id,
+        label,
+        duration.as_secs_f32()
+    );
+
+    let doorbird_client = state.doorbird_client.clone();
+    let ptt_state = state.ptt_state.clone();
+    let shutdown = state.shutdown.clone();
+    tokio::spawn(async move {
+        let _active = ActiveTransmission::start();
+        // Stopping the stream on shutdown ends the request body cleanly
+        let audio =
+            audio_file::ulaw_stream(Bytes::from(ulaw)).take_until(shutdown.cancelled_owned());
+        match doorbird_client.audio_transmit(audio).await {
+            Ok(()) => info!("Announcement {} finished", id),
+            Err(e) => error!("Announcement {} failed: {:#}", id, e),
+        }
+        ptt_state.release(id).await;
+    });
+
+    Ok(Announcement { id, duration })
+}
+
+/// Decode an uploaded file (ffmpeg reads from a path, so it goes through a temp file)
+async fn decode_upload(id: Uuid, body: Bytes) -> anyhow::Result<Vec<u8>> {
+    let path = std::env::temp_dir().join(format!("birdbox-announce-{}", id));
+    tokio::fs::write(&path, &body).await?;
+    let file = path.clone();
+    let ulaw = tokio::task::spawn_blocking(move || audio_file::decode_ulaw(&file)).await;
+    let _ = tokio::fs::remove_file(&path).await;
+    ulaw?
+}
+
+/// Clip names are file names without extension; anything else is rejected
+fn is_valid_clip_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// File in `dir` named `name` with any extension
+fn clip_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if !is_valid_clip_name(name) {
+        return None;
+    }
+    let mut matches: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.file_stem().is_some_and(|stem| stem == name))
+        .collect();
+    matches.sort();
+    matches.into_iter().next()
+}
+
+/// Names of the clips in `dir`, sorted
+fn clip_names(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .filter(|name| is_valid_clip_name(name))
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Query parameters of `POST /api/announce`
+#[derive(Debug, Deserialize)]
+pub struct AnnounceParams {
+    clip: Option<String>,
+}
+
+/// `POST /api/announce` - play a clip or an uploaded file at the door
+pub async fn announce(
+    State(state): State<AppState>,
+    Query(params): Query<AnnounceParams>,
+    body: Bytes,
+) -> Response {
+    let source = match (params.clip, body.is_empty()) {
+        (Some(name), true) => Source::Clip(name),
+        (None, false) => Source::Upload(body),
+        (Some(_), false) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "pass either ?clip=<name> or an audio file, not both",
+            )
+                .into_response()
+        }
+        (None, true) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "pass ?clip=<name> or an audio file as the request body",
+            )
+                .into_response()
+        }
+    };
+
+    match play(&state, source).await {
+        Ok(announcement) => (StatusCode::ACCEPTED, Json(announcement)).into_response(),
+        Err(e) => {
+            if let AnnounceError::Decode(_) = e {
+                error!("Announcement rejected: {}", e);
+            }
+            (e.status(), e.to_string()).into_response()
+        }
+    }
+}
+
+/// `GET /api/announce/clips` - names accepted by `?clip=`
+pub async fn list_clips(State(state): State<AppState>) -> Response {
+    let Some(dir) = state.announce_dir.as_deref() else {
+        return Json(Vec::<String>::new()).into_response();
+    };
+    match clip_names(dir) {
+        Ok(names) => Json(names).into_response(),
+        Err(e) => {
+            error!("Failed to read {}: {}", dir.display(), e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read the announcement directory",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_names() {
+        assert!(is_valid_clip_name("parcel"));
+        assert!(is_valid_clip_name("leave-it_2"));
+        assert!(!is_valid_clip_name(""));
+        assert!(!is_valid_clip_name("../secret"));
+        assert!(!is_valid_clip_name("parcel.mp3"));
+        assert!(!is_valid_clip_name("a/b"));
+    }
+
+    #[test]
+    fn test_clip_lookup() {
+        let dir = std::env::temp_dir().join(format!("birdbox-clips-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("parcel.mp3"), b"").unwrap();
+        std::fs::write(dir.join("parcel.wav"), b"").unwrap();
+        std::fs::write(dir.join("away.opus"), b"").unwrap();
+
+        assert_eq!(clip_path(&dir, "parcel"), Some(dir.join("parcel.mp3")));
+        assert_eq!(clip_path(&dir, "away"), Some(dir.join("away.opus")));
+        assert_eq!(clip_path(&dir, "nested"), None);
+        assert_eq!(clip_path(&dir, "missing"), None);
+        assert_eq!(clip_path(&dir, "../parcel"), None);
+        assert_eq!(clip_names(&dir).unwrap(), vec!["away", "parcel"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
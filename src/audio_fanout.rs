@@ -7,6 +7,7 @@
 //! - Handles transcoding from G.711 μ-law to Opus
 
 use crate::audio_transcode::AudioTranscoder;
+use crate::metrics::METRICS;
 use anyhow::{Context, Result};
 use bytes::Bytes;
 use doorbird::Client as DoorBirdClient;
@@ -133,6 +134,7 @@ impl AudioFanout {
 
             // Connect and stream
             info!("Connecting to DoorBird audio stream...");
+            METRICS.fanout_connects.inc(&[("fanout", "audio")]);
             {
                 let mut state = self.state.write().await;
                 state.connection_state = ConnectionState::Connecting;
@@ -144,6 +146,7 @@ impl AudioFanout {
                 }
                 Err(e) => {
                     error!("DoorBird audio stream error: {:#}", e);
+                    METRICS.fanout_errors.inc(&[("fanout", "audio")]);
                     // Wait before retry
                     sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
                 }
@@ -261,7 +264,6 @@ impl AudioFanout {
     /// Get current subscriber count
     ///
     /// Useful for debugging, monitoring endpoints, or metrics collection.
-    #[allow(dead_code)]
     pub async fn subscriber_count(&self) -> usize {
         let state = self.state.read().await;
         state.subscriber_count
@@ -270,7 +272,6 @@ impl AudioFanout {
     /// Check if currently connected to DoorBird
     ///
     /// Useful for debugging, monitoring endpoints, or health checks.
-    #[allow(dead_code)]
     pub async fn is_connected(&self) -> bool {
         let state = self.state.read().await;
         state.connection_state == ConnectionState::Connected
@@ -0,0 +1,196 @@
+//! Audio files for the DoorBird speaker and microphone
+//!
+//! The DoorBird talks G.711 μ-law at 8kHz mono. `decode_ulaw` turns any audio
+//! file ffmpeg can read (WAV, MP3, Opus, ...) into that format, and `ulaw_stream`
+//! paces it for `Client::audio_transmit` in real time, since the device plays
+//! audio as it arrives. Recordings are written as 16-bit PCM WAV (`wav_header`).
+
+use crate::g711;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use ffmpeg_next as ffmpeg;
+use futures_util::Stream;
+use std::path::Path;
+use std::time::Duration;
+
+/// DoorBird audio sample rate (G.711 μ-law, one byte per sample)
+pub const SAMPLE_RATE: u32 = 8000;
+
+/// Audio sent to the DoorBird per chunk
+const CHUNK_DURATION: Duration = Duration::from_millis(20);
+
+/// μ-law bytes per chunk
+const CHUNK_BYTES: usize = (SAMPLE_RATE / 50) as usize;
+
+/// Decode an audio file to 8kHz mono G.711 μ-law
+pub fn decode_ulaw(path: &Path) -> Result<Vec<u8>> {
+    ffmpeg::init().context("Failed to initialize ffmpeg")?;
+
+    let mut input = ffmpeg::format::input(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let stream = input
+        .streams()
+        .best(ffmpeg::media::Type::Audio)
+        .with_context(|| format!("No audio stream in {}", path.display()))?;
+    let stream_index = stream.index();
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+        .context("Failed to read audio stream parameters")?
+        .decoder()
+        .audio()
+        .context("Failed to open audio decoder")?;
+
+    let mut resampler: Option<ffmpeg::software::resampling::Context> = None;
+    let mut pcm = Vec::new();
+
+    let mut receive_frames = |decoder: &mut ffmpeg::decoder::Audio| -> Result<()> {
+        let mut decoded = ffmpeg::frame::Audio::empty();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            // Created from the first frame, which carries the actual input layout
+            if resampler.is_none() {
+                resampler = Some(
+                    decoded
+                        .resampler(pcm_format(), ffmpeg::ChannelLayout::default(1), SAMPLE_RATE)
+                        .context("Failed to create resampler")?,
+                );
+            }
+            if let Some(resampler) = &mut resampler {
+                let mut resampled = ffmpeg::frame::Audio::empty();
+                resampler
+                    .run(&decoded, &mut resampled)
+                    .context("Failed to resample audio")?;
+                pcm.extend_from_slice(resampled.plane::<i16>(0));
+            }
+        }
+        Ok(())
+    };
+
+    for (stream, packet) in input.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder
+            .send_packet(&packet)
+            .context("Failed to decode audio")?;
+        receive_frames(&mut decoder)?;
+    }
+    decoder.send_eof().context("Failed to decode audio")?;
+    receive_frames(&mut decoder)?;
+
+    // Samples still held by the resampler
+    if let Some(resampler) = &mut resampler {
+        let mut resampled =
+            ffmpeg::frame::Audio::new(pcm_format(), 1024, ffmpeg::ChannelLayout::default(1));
+        if resampler.flush(&mut resampled).is_ok() {
+            pcm.extend_from_slice(resampled.plane::<i16>(0));
+        }
+    }
+
+    if pcm.is_empty() {
+        anyhow::bail!("{} contains no audio", path.display());
+    }
+    Ok(g711::encode_ulaw_buffer(&pcm))
+}
+
+/// Packed signed 16-bit samples
+fn pcm_format() -> ffmpeg::format::Sample {
+    ffmpeg::format::Sample::I16(ffmpeg::format::sample::Type::Packed)
+}
+
+/// Duration of μ-law audio at `SAMPLE_RATE`
+pub fn duration(ulaw: &[u8]) -> Duration {
+    Duration::from_millis(ulaw.len() as u64 * 1000 / SAMPLE_RATE as u64)
+}
+
+/// Stream μ-law audio in 20ms chunks at playback speed
+pub fn ulaw_stream(ulaw: Bytes) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+    let mut interval = tokio::time::interval(CHUNK_DURATION);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    futures_util::stream::unfold((ulaw, interval), |(mut ulaw, mut interval)| async move {
+        if ulaw.is_empty() {
+            return None;
+        }
+        interval.tick().await;
+        let chunk = ulaw.split_to(CHUNK_BYTES.min(ulaw.len()));
+        Some((Ok(chunk), (ulaw, interval)))
+    })
+}
+
+/// Size of the header written by `wav_header`
+pub const WAV_HEADER_LEN: usize = 44;
+
+/// Canonical WAV header for mono 16-bit PCM with `samples` samples
+pub fn wav_header(sample_rate: u32, samples: u32) -> [u8; WAV_HEADER_LEN] {
+    let data_len = samples * 2;
+    let mut header = [0u8; WAV_HEADER_LEN];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(36 + data_len).to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    header[20..22].copy_from_slice(&1u16.to_le_bytes());
+    header[22..24].copy_from_slice(&1u16.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    // Byte rate, block align, bits per sample
+    header[28..32].copy_from_slice(&(sample_rate * 2).to_le_bytes());
+    header[32..34].copy_from_slice(&2u16.to_le_bytes());
+    header[34..36].copy_from_slice(&16u16.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+/// WAV sample data (little-endian) following the header
+pub fn wav_data(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[test]
+    fn test_wav_header() {
+        let header = wav_header(SAMPLE_RATE, 8000);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(header[4..8].try_into().unwrap()),
+            36 + 16000
+        );
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(header[24..28].try_into().unwrap()), 8000);
+        assert_eq!(
+            u32::from_le_bytes(header[28..32].try_into().unwrap()),
+            16000
+        );
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(
+            u32::from_le_bytes(header[40..44].try_into().unwrap()),
+            16000
+        );
+
+        assert_eq!(wav_data(&[1, -2]), vec![1, 0, 0xfe, 0xff]);
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(&[0; 8000]), Duration::from_secs(1));
+        assert_eq!(duration(&[0; CHUNK_BYTES]), CHUNK_DURATION);
+    }
+
+    #[tokio::test]
+    async fn test_ulaw_stream_is_paced() {
+        let start = std::time::Instant::now();
+        let chunks: Vec<Bytes> = ulaw_stream(Bytes::from(vec![0xff; 400]))
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![160, 160, 80]
+        );
+        // The first chunk goes out immediately, the others 20ms apart
+        assert!(start.elapsed() >= 2 * CHUNK_DURATION);
+    }
+}
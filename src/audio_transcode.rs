@@ -9,11 +9,13 @@
 //! 4. Encode to Opus
 //!
 //! Reverse (WebRTC → DoorBird):
-//! 1. Decode Opus to PCM f32
+//! 1. Decode Opus to PCM f32 (a packet the jitter buffer reports lost is recovered
+//!    from the next packet's in-band FEC data, or concealed with PLC)
 //! 2. Resample from 48kHz to 8kHz
 //! 3. Convert PCM f32 to i16
 //! 4. Encode to G.711 μ-law
 
+use crate::metrics::METRICS;
 use anyhow::{Context, Result};
 use audiopus::{Application, Channels, SampleRate, coder::Decoder, coder::Encoder};
 use rubato::{
@@ -140,6 +142,7 @@ impl AudioTranscoder {
             let encoded_len = self
                 .opus_encoder
                 .encode_float(&opus_input, &mut opus_buffer)
+                .inspect_err(|_| METRICS.opus_encode_errors.inc())
                 .context("Opus encoding failed")?;
 
             opus_frames.push(opus_buffer[..encoded_len].to_vec());
@@ -185,11 +188,12 @@ impl AudioTranscoder {
 
             let opus_input: Vec<f32> = self.output_buffer.drain(..self.output_frame_size).collect();
             let mut opus_buffer = vec![0u8; 4000];
-            if let Ok(encoded_len) = self
+            match self
                 .opus_encoder
                 .encode_float(&opus_input, &mut opus_buffer)
             {
-                opus_frames.push(opus_buffer[..encoded_len].to_vec());
+                Ok(encoded_len) => opus_frames.push(opus_buffer[..encoded_len].to_vec()),
+                Err(_) => METRICS.opus_encode_errors.inc(),
             }
         }
 
@@ -203,10 +207,16 @@ pub struct ReverseAudioTranscoder {
     opus_decoder: Decoder,
     /// Resampler for 48kHz -> 8kHz conversion
     resampler: SincFixedIn<f32>,
+    /// Buffer for accumulating decoded samples before resampling (48kHz)
+    input_buffer: Vec<f32>,
     /// Buffer for accumulating resampled output before encoding (8kHz)
     output_buffer: Vec<f32>,
+    /// Number of input samples the resampler takes at a time (48kHz @ 20ms = 960 samples)
+    input_frame_size: usize,
     /// Target number of output samples for G.711 encoding (prefer chunks of ~20ms = 160 samples @ 8kHz)
     output_frame_size: usize,
+    /// Samples in the packet decoded last, the length a lost packet is concealed with
+    packet_samples: usize,
 }
 
 impl ReverseAudioTranscoder {
@@ -244,8 +254,11 @@ impl ReverseAudioTranscoder {
         Ok(Self {
             opus_decoder,
             resampler,
+            input_buffer: Vec::with_capacity(960),
             output_buffer: Vec::with_capacity(160),
+            input_frame_size: 960,  // 20ms @ 48kHz
             output_frame_size: 160, // 20ms @ 8kHz
+            packet_samples: 960,
         })
     }
 
@@ -265,22 +278,77 @@ impl ReverseAudioTranscoder {
         let samples_decoded = self
             .opus_decoder
             .decode_float(Some(opus_data), &mut pcm_buffer, false)
+            .inspect_err(|_| METRICS.opus_decode_errors.inc())
             .context("Opus decoding failed")?;
 
         // Trim to actual decoded size
         pcm_buffer.truncate(samples_decoded);
+        if samples_decoded > 0 {
+            self.packet_samples = samples_decoded;
+        }
+
+        self.resample_and_encode(pcm_buffer)
+    }
+
+    /// Replaces one lost packet, assumed as long as the one decoded last
+    ///
+    /// Browsers send Opus in-band FEC (`useinbandfec=1`) when they see loss: each
+    /// packet carries a low bitrate copy of the previous one. If the packet after the
+    /// lost one is available and carries such a copy, the lost audio is decoded from
+    /// it. Otherwise packet loss concealment extrapolates from the audio decoded last
+    /// (which is also what the decoder does when asked for FEC data that isn't there).
+    /// Either way a lost packet is barely audible instead of a click.
+    ///
+    /// # Arguments
+    /// * `next_packet` - The packet following the lost one, if it has arrived
+    ///
+    /// # Returns
+    /// Vector of G.711 μ-law encoded frames, like `process_chunk`
+    pub fn conceal(&mut self, next_packet: Option<&[u8]>) -> Result<Vec<Vec<u8>>> {
+        // The output length sets how much audio to recover (960 samples = 20ms @ 48kHz)
+        let mut pcm_buffer = vec![0.0f32; self.packet_samples];
+
+        if let Some(next_packet) = next_packet.filter(|packet| has_lbrr(packet)) {
+            match self
+                .opus_decoder
+                .decode_float(Some(next_packet), &mut pcm_buffer, true)
+            {
+                Ok(samples_decoded) => {
+                    METRICS.ptt_concealed_frames.inc(&[("method", "fec")]);
+                    pcm_buffer.truncate(samples_decoded);
+                    return self.resample_and_encode(pcm_buffer);
+                }
+                Err(e) => warn!("Opus FEC decoding failed, using PLC: {}", e),
+            }
+        }
+
+        let samples_decoded = self
+            .opus_decoder
+            .decode_float(None::<&[u8]>, &mut pcm_buffer, false)
+            .inspect_err(|_| METRICS.opus_decode_errors.inc())
+            .context("Opus packet loss concealment failed")?;
+        METRICS.ptt_concealed_frames.inc(&[("method", "plc")]);
+        pcm_buffer.truncate(samples_decoded);
 
-        // Resample 48kHz -> 8kHz
-        let resampled = self
-            .resampler
-            .process(&[pcm_buffer], None)
-            .context("Resampling failed")?;
+        self.resample_and_encode(pcm_buffer)
+    }
 
-        // resampled is Vec<Vec<f32>>, we have mono so take channel 0
-        let resampled_mono = &resampled[0];
+    /// Resamples decoded 48kHz PCM to 8kHz and encodes complete frames to G.711 μ-law
+    fn resample_and_encode(&mut self, pcm_buffer: Vec<f32>) -> Result<Vec<Vec<u8>>> {
+        // The resampler takes 20ms at a time; packets can be 10ms to 120ms long
+        self.input_buffer.extend_from_slice(&pcm_buffer);
+        while self.input_buffer.len() >= self.input_frame_size {
+            let chunk: Vec<f32> = self.input_buffer.drain(..self.input_frame_size).collect();
 
-        // Add resampled data to output buffer
-        self.output_buffer.extend_from_slice(resampled_mono);
+            // Resample 48kHz -> 8kHz
+            let resampled = self
+                .resampler
+                .process(&[chunk], None)
+                .context("Resampling failed")?;
+
+            // resampled is Vec<Vec<f32>>, we have mono so take channel 0
+            self.output_buffer.extend_from_slice(&resampled[0]);
+        }
 
         let mut ulaw_frames = Vec::new();
 
@@ -307,7 +375,13 @@ impl ReverseAudioTranscoder {
     ///
     /// Should be called when the audio stream ends to process any partial frames
     pub fn flush(&mut self) -> Result<Vec<Vec<u8>>> {
-        let mut ulaw_frames = Vec::new();
+        // Resample the last partial chunk, padded with silence
+        let mut ulaw_frames = if self.input_buffer.is_empty() {
+            Vec::new()
+        } else {
+            let padding = vec![0.0f32; self.input_frame_size - self.input_buffer.len()];
+            self.resample_and_encode(padding)?
+        };
 
         // Encode any remaining output samples
         if !self.output_buffer.is_empty() {
@@ -337,6 +411,93 @@ impl ReverseAudioTranscoder {
     }
 }
 
+/// Whether an Opus packet carries in-band FEC (LBRR) data for the packet before it
+///
+/// Same as `opus_packet_has_lbrr` in libopus 1.5, which the bundled libopus predates:
+/// the LBRR flag follows the voice activity flags at the start of the first frame's
+/// SILK layer (one per 20ms, per channel).
+fn has_lbrr(packet: &[u8]) -> bool {
+    let Some(&toc) = packet.first() else {
+        return false;
+    };
+    let config = toc >> 3;
+    let frame_ms = match config {
+        // SILK-only: 10, 20, 40 or 60ms
+        0..=11 => [10, 20, 40, 60][(config & 3) as usize],
+        // Hybrid: 10 or 20ms
+        12..=15 => [10, 20][(config & 1) as usize],
+        // CELT-only packets have no SILK layer
+        _ => return false,
+    };
+    let silk_frames = (frame_ms / 20).max(1);
+    let Some(&first) = first_frame(packet).and_then(|frame| frame.first()) else {
+        return false;
+    };
+
+    let mut lbrr = (first >> (7 - silk_frames)) & 1 == 1;
+    if toc & 0x04 != 0 {
+        // Stereo: the side channel's flags follow
+        lbrr |= (first >> (6 - 2 * silk_frames)) & 1 == 1;
+    }
+    lbrr
+}
+
+/// The first frame of an Opus packet (RFC 6716 section 3.2)
+fn first_frame(packet: &[u8]) -> Option<&[u8]> {
+    let (&toc, data) = packet.split_first()?;
+    match toc & 0x03 {
+        // One frame
+        0 => Some(data),
+        // Two frames of equal size
+        1 => Some(&data[..data.len() / 2]),
+        // Two frames, the first one's length given
+        2 => {
+            let (len, skip) = frame_length(data)?;
+            data.get(skip..skip + len)
+        }
+        // Any number of frames, with optional padding
+        _ => {
+            let (&count, mut data) = data.split_first()?;
+            let frames = (count & 0x3f) as usize;
+            if frames == 0 {
+                return None;
+            }
+            let mut padding = 0;
+            if count & 0x40 != 0 {
+                loop {
+                    let (&byte, rest) = data.split_first()?;
+                    data = rest;
+                    padding += if byte == 255 { 254 } else { byte as usize };
+                    if byte != 255 {
+                        break;
+                    }
+                }
+            }
+            if count & 0x80 != 0 {
+                // Variable sizes: the lengths of all frames but the last come first
+                let (len, mut skip) = frame_length(data)?;
+                for _ in 2..frames {
+                    skip += frame_length(data.get(skip..)?)?.1;
+                }
+                data.get(skip..skip + len)
+            } else {
+                let len = data.len().checked_sub(padding)? / frames;
+                data.get(..len)
+            }
+        }
+    }
+    .filter(|frame| !frame.is_empty())
+}
+
+/// Frame length coded in one or two bytes, and the number of bytes
+fn frame_length(data: &[u8]) -> Option<(usize, usize)> {
+    match *data {
+        [first, ..] if first < 252 => Some((first as usize, 1)),
+        [first, second, ..] => Some((second as usize * 4 + first as usize, 2)),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -396,4 +557,132 @@ mod tests {
             "Expected to produce at least some Opus frames after processing multiple input frames"
         );
     }
+
+    #[test]
+    fn test_reverse_conceal_lost_packets() {
+        // Real Opus packets from the forward path
+        let mut encoder = AudioTranscoder::new().unwrap();
+        let tone: Vec<u8> = (0..160u32)
+            .map(|i| crate::g711::encode_ulaw(((i as f32 * 0.3).sin() * 8000.0) as i16))
+            .collect();
+        let mut packets = Vec::new();
+        for _ in 0..20 {
+            packets.extend(encoder.process_chunk(&tone).unwrap());
+        }
+        assert!(packets.len() > 3);
+
+        let mut transcoder = ReverseAudioTranscoder::new().unwrap();
+        let mut ulaw_frames = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let frames = match i % 4 {
+                // Lost, the next packet arrived (FEC, or PLC without FEC data)
+                1 => transcoder.conceal(packets.get(i + 1).map(Vec::as_slice)),
+                // Lost, nothing after it yet (PLC)
+                3 => transcoder.conceal(None),
+                _ => transcoder.process_chunk(packet),
+            };
+            ulaw_frames.extend(frames.unwrap());
+        }
+
+        assert!(!ulaw_frames.is_empty());
+        assert!(ulaw_frames.iter().all(|frame| frame.len() == 160));
+    }
+
+    #[test]
+    fn test_fec_recovers_more_than_plc() {
+        let mut encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Voip).unwrap();
+        encoder.set_inband_fec(true).unwrap();
+        encoder.set_packet_loss_perc(20).unwrap();
+        encoder.set_bitrate(audiopus::Bitrate::BitsPerSecond(24000)).unwrap();
+        // SILK carries the FEC data, CELT has none
+        encoder.set_max_bandwidth(audiopus::Bandwidth::Wideband).unwrap();
+
+        // Voice-like (the encoder only adds FEC data for voice activity): harmonics
+        // with a wandering pitch and loudness, plus some noise
+        let mut seed = 1u32;
+        let packets: Vec<Vec<u8>> = (0..40)
+            .map(|n| {
+                let pcm: Vec<f32> = (0..960)
+                    .map(|i| {
+                        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                        let noise = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+                        let t = (n * 960 + i) as f32;
+                        let phase = t * (0.015 + 0.01 * (t / 3000.0).sin());
+                        let voice = phase.sin() + 0.5 * (2.0 * phase).sin();
+                        voice * 0.2 * (1.0 + (t / 700.0).sin()) + noise * 0.1
+                    })
+                    .collect();
+                let mut packet = vec![0u8; 4000];
+                let len = encoder.encode_float(&pcm, &mut packet).unwrap();
+                packet.truncate(len);
+                packet
+            })
+            .collect();
+        // Nothing to recover before the first packet
+        assert!(!has_lbrr(&packets[0]));
+        let lost = (5..packets.len() - 1)
+            .find(|&i| has_lbrr(&packets[i + 1]))
+            .expect("no packet with FEC data");
+
+        // One decoder recovers the lost packet from the next one, the other conceals it
+        let decode = |fec: bool| {
+            let mut transcoder = ReverseAudioTranscoder::new().unwrap();
+            let mut ulaw = Vec::new();
+            for (i, packet) in packets.iter().enumerate() {
+                let frames = if i == lost {
+                    transcoder.conceal(fec.then_some(packets[i + 1].as_slice()))
+                } else {
+                    transcoder.process_chunk(packet)
+                };
+                ulaw.extend(frames.unwrap());
+            }
+            ulaw.concat()
+        };
+        assert_ne!(decode(true), decode(false));
+    }
+
+    #[test]
+    fn test_has_lbrr() {
+        // SILK-only 20ms mono (config 1), code 0, LBRR flag after one VAD flag
+        assert!(has_lbrr(&[0x08, 0b1100_0000, 0x12]));
+        assert!(!has_lbrr(&[0x08, 0b1000_0000, 0x12]));
+        // SILK-only 60ms (config 3): three VAD flags first
+        assert!(has_lbrr(&[0x18, 0b0001_0000]));
+        assert!(!has_lbrr(&[0x18, 0b1110_0000]));
+        // CELT-only (config 31) has no LBRR data
+        assert!(!has_lbrr(&[0xF8, 0xFF, 0xFF]));
+        // Code 2: first frame of 1 byte, then the second frame
+        assert!(has_lbrr(&[0x0A, 1, 0b0100_0000, 0x00]));
+        assert!(!has_lbrr(&[0x0A, 1, 0b0000_0000, 0xFF]));
+        // Code 3, VBR with padding: 2 frames, 1 padding byte, first frame of 1 byte
+        assert!(has_lbrr(&[0x0B, 0xC2, 1, 1, 0b0100_0000, 0x00, 0x00]));
+        // Empty or truncated packets
+        assert!(!has_lbrr(&[]));
+        assert!(!has_lbrr(&[0x08]));
+        assert!(!has_lbrr(&[0x0A, 5, 0x40]));
+    }
+
+    #[test]
+    fn test_reverse_longer_packets() {
+        // 40ms packets, as some WHIP clients send
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Voip).unwrap();
+        let tone: Vec<f32> = (0..1920)
+            .map(|i| (i as f32 * 0.05).sin() * 0.25)
+            .collect();
+        let mut transcoder = ReverseAudioTranscoder::new().unwrap();
+        let mut ulaw_frames = Vec::new();
+        for _ in 0..10 {
+            let mut packet = vec![0u8; 4000];
+            let len = encoder.encode_float(&tone, &mut packet).unwrap();
+            ulaw_frames.extend(transcoder.process_chunk(&packet[..len]).unwrap());
+        }
+        // All of the audio (less the resampler's latency), not just the first 20ms
+        // of every packet
+        assert!(ulaw_frames.len() >= 19, "{} frames", ulaw_frames.len());
+
+        // A lost packet is concealed as long as the ones around it
+        assert_eq!(transcoder.conceal(None).unwrap().len(), 2);
+    }
 }
@@ -0,0 +1,131 @@
+//! Users authenticated by a reverse proxy
+//!
+//! Birdbox has no login of its own. A reverse proxy in front of it (Caddy
+//! `basic_auth` or `forward_auth`, oauth2-proxy, Authelia) can authenticate users
+//! and pass the user name in a header (`auth.user_header`). The header is only
+//! believed on connections from `auth.trusted_proxies`, so a client that reaches
+//! birdbox directly cannot claim to be anyone; `auth.users` optionally limits which
+//! of the proxy's users count.
+//!
+//! Sessions record their user for `GET /api/status`. The embedded TURN relay hands
+//! credentials to authenticated sessions only.
+
+use crate::config;
+use axum::http::HeaderMap;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use tracing::debug;
+
+/// Resolves the authenticated user of a request
+pub struct Auth {
+    config: RwLock<config::Auth>,
+}
+
+impl Auth {
+    pub fn new(config: &config::Auth) -> Self {
+        Self {
+            config: RwLock::new(config.clone()),
+        }
+    }
+
+    pub fn reconfigure(&self, config: &config::Auth) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+    }
+
+    /// User the trusted proxy authenticated for a request from `addr`, if any
+    pub fn user(&self, addr: SocketAddr, headers: &HeaderMap) -> Option<String> {
+        authenticated_user(
+            &self.config.read().unwrap_or_else(|e| e.into_inner()),
+            addr,
+            headers,
+        )
+    }
+}
+
+fn authenticated_user(
+    config: &config::Auth,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Option<String> {
+    let header = config.user_header.as_deref()?;
+    let value = headers.get(header)?;
+
+    // IPv4 peers of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+    let ip = addr.ip().to_canonical();
+    if !config
+        .trusted_proxies
+        .iter()
+        .any(|proxy| proxy.to_canonical() == ip)
+    {
+        debug!("Ignoring {} from untrusted address {}", header, addr);
+        return None;
+    }
+
+    let user = value.to_str().ok()?.trim();
+    if user.is_empty() || !(config.users.is_empty() || config.users.iter().any(|u| u == user)) {
+        return None;
+    }
+    Some(user.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(users: &[&str]) -> config::Auth {
+        config::Auth {
+            user_header: Some("Remote-User".to_string()),
+            trusted_proxies: vec!["172.18.0.2".parse().unwrap()],
+            users: users.iter().map(|u| u.to_string()).collect(),
+        }
+    }
+
+    fn headers(user: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("remote-user", user.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_user_from_trusted_proxy() {
+        let proxy: SocketAddr = "172.18.0.2:40000".parse().unwrap();
+        assert_eq!(
+            authenticated_user(&config(&[]), proxy, &headers("alice")),
+            Some("alice".to_string())
+        );
+        let mapped: SocketAddr = "[::ffff:172.18.0.2]:40000".parse().unwrap();
+        assert_eq!(
+            authenticated_user(&config(&[]), mapped, &headers("alice")),
+            Some("alice".to_string())
+        );
+        assert_eq!(
+            authenticated_user(&config(&[]), proxy, &HeaderMap::new()),
+            None
+        );
+        assert_eq!(authenticated_user(&config(&[]), proxy, &headers(" ")), None);
+    }
+
+    #[test]
+    fn test_header_ignored_from_other_addresses() {
+        let client: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        assert_eq!(
+            authenticated_user(&config(&[]), client, &headers("alice")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_users_allowlist() {
+        let proxy: SocketAddr = "172.18.0.2:40000".parse().unwrap();
+        let config = config(&["alice"]);
+        assert!(authenticated_user(&config, proxy, &headers("alice")).is_some());
+        assert!(authenticated_user(&config, proxy, &headers("mallory")).is_none());
+    }
+
+    #[test]
+    fn test_disabled_without_header() {
+        let proxy: SocketAddr = "172.18.0.2:40000".parse().unwrap();
+        let config = config::Auth::default();
+        assert_eq!(authenticated_user(&config, proxy, &headers("alice")), None);
+    }
+}
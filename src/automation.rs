@@ -0,0 +1,609 @@
+//! Automation rules ("away mode")
+//!
+//! `[[automation.rules]]` in the configuration react to DoorBird events. When a
+//! rule's `event` happens and all of its conditions hold, its actions run in order:
+//! - conditions: `away` (the manual away flag, toggled with `POST /api/away`),
+//!   `schedule` (local time of day, `"22:00-06:00"` spans midnight) and
+//!   `nobody_viewing` (no browser, WHEP, HLS or MJPEG viewer is watching)
+//! - actions: play an announcement clip, trigger a relay, switch on the light, POST
+//!   a webhook, record the door
+//!
+//! Motion events repeat, so a rule fires at most once per `cooldown_secs`. With
+//! `automation.dry_run` the actions are only logged. Either way the latest firings
+//! are listed at `GET /api/automation`.
+
+use crate::announce::{self, AnnounceError};
+use crate::config;
+use crate::AppState;
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Firings kept for `GET /api/automation`
+const HISTORY_LEN: usize = 50;
+
+/// Limit on a webhook request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// DoorBird event a rule reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Doorbell,
+    /// Motion detected (motion cleared triggers nothing)
+    Motion,
+}
+
+impl EventKind {
+    pub fn from_monitor(event: &doorbird::MonitorEvent) -> Option<Self> {
+        match event {
+            doorbird::MonitorEvent::Doorbell => Some(EventKind::Doorbell),
+            doorbird::MonitorEvent::MotionSensor { active: true } => Some(EventKind::Motion),
+            doorbird::MonitorEvent::MotionSensor { active: false } => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Doorbell => "doorbell",
+            EventKind::Motion => "motion",
+        }
+    }
+}
+
+/// Local time-of-day window, `HH:MM-HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Schedule {
+    /// Whether `time` is in the window (start inclusive, end exclusive)
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // Spans midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid schedule '{}', expected HH:MM-HH:MM", value);
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let parse = |s: &str| NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|_| invalid());
+        let schedule = Schedule {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if schedule.start == schedule.end {
+            return Err(format!("schedule '{}' is empty", value));
+        }
+        Ok(schedule)
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> Self {
+        format!(
+            "{}-{}",
+            schedule.start.format("%H:%M"),
+            schedule.end.format("%H:%M")
+        )
+    }
+}
+
+/// Something a rule does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    /// Play a clip from `announce.dir`
+    Announce { clip: String },
+    /// Trigger a relay (the device's default relay if not given)
+    Relay {
+        #[serde(default)]
+        relay: Option<String>,
+    },
+    /// Switch on the infrared light
+    Light,
+    /// POST the event as JSON
+    Webhook { url: String },
+    /// Record the door to `recording.dir`
+    Record {
+        #[serde(default = "default_record_secs")]
+        seconds: u64,
+    },
+}
+
+fn default_record_secs() -> u64 {
+    30
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Announce { clip } => write!(f, "announce '{}'", clip),
+            Action::Relay { relay: Some(relay) } => write!(f, "trigger relay {}", relay),
+            Action::Relay { relay: None } => write!(f, "trigger relay"),
+            Action::Light => write!(f, "light on"),
+            Action::Webhook { url } => write!(f, "webhook {}", webhook_origin(url)),
+            Action::Record { seconds } => write!(f, "record {}s", seconds),
+        }
+    }
+}
+
+/// Scheme and host of a webhook URL, for logs and `/api/automation`
+///
+/// The path or query of a webhook URL often carries its token.
+pub fn webhook_origin(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) if url.has_host() => url.origin().ascii_serialization(),
+        _ => "(invalid URL)".to_string(),
+    }
+}
+
+/// Automation rule from the configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Shown in logs, webhooks and `/api/automation`
+    pub name: String,
+    pub event: EventKind,
+    /// Only while away mode is on (`true`) or off (`false`)
+    #[serde(default)]
+    pub away: Option<bool>,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Only when nobody is watching the video
+    #[serde(default)]
+    pub nobody_viewing: bool,
+    /// Minimum time between two firings
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    pub actions: Vec<Action>,
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+/// State rules are evaluated against
+#[derive(Debug, Clone, Copy)]
+pub struct Conditions {
+    pub event: EventKind,
+    pub away: bool,
+    /// Local time of the event
+    pub time: NaiveTime,
+    pub viewers: usize,
+}
+
+impl Rule {
+    /// Whether the rule applies, ignoring its cooldown
+    pub fn matches(&self, conditions: &Conditions) -> bool {
+        self.event == conditions.event
+            && self.away.is_none_or(|away| away == conditions.away)
+            && self
+                .schedule
+                .is_none_or(|schedule| schedule.contains(conditions.time))
+            && (!self.nobody_viewing || conditions.viewers == 0)
+    }
+}
+
+/// A rule that fired (or would have, in dry-run mode)
+#[derive(Debug, Clone, Serialize)]
+pub struct Firing {
+    pub rule: String,
+    pub event: EventKind,
+    /// Unix timestamp (seconds)
+    pub at: u64,
+    pub actions: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Rules engine state
+pub struct Automation {
+    /// Changed by configuration reloads
+    rules: RwLock<Vec<Rule>>,
+    dry_run: AtomicBool,
+    away: AtomicBool,
+    /// Last firing per rule name, for the cooldown
+    last_fired: Mutex<HashMap<String, Instant>>,
+    history: Mutex<VecDeque<Firing>>,
+    http: reqwest::Client,
+}
+
+impl Automation {
+    pub fn new(config: &config::Automation) -> Self {
+        Self {
+            rules: RwLock::new(config.rules.clone()),
+            dry_run: AtomicBool::new(config.dry_run),
+            away: AtomicBool::new(config.away),
+            last_fired: Mutex::new(HashMap::new()),
+            history: Mutex::new(VecDeque::new()),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Apply reloaded rules and dry-run setting (the away flag keeps its value)
+    pub fn reconfigure(&self, config: &config::Automation) {
+        *self.rules.write().unwrap() = config.rules.clone();
+        self.dry_run.store(config.dry_run, Ordering::Relaxed);
+    }
+
+    pub fn is_away(&self) -> bool {
+        self.away.load(Ordering::Relaxed)
+    }
+
+    pub fn set_away(&self, away: bool) {
+        if self.away.swap(away, Ordering::Relaxed) != away {
+            info!("🏠 Away mode {}", if away { "on" } else { "off" });
+        }
+    }
+
+    /// Rules that fire for `conditions`, starting their cooldown
+    fn fire(&self, conditions: &Conditions) -> Vec<Rule> {
+        let now = Instant::now();
+        let mut last_fired = self.last_fired.lock().unwrap();
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rule| rule.matches(conditions))
+            .filter(|rule| {
+                let cooling_down = last_fired.get(&rule.name).is_some_and(|last| {
+                    now.duration_since(*last) < Duration::from_secs(rule.cooldown_secs)
+                });
+                if !cooling_down {
+                    last_fired.insert(rule.name.clone(), now);
+                }
+                !cooling_down
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, firing: Firing) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(firing);
+    }
+}
+
+/// Evaluate the rules for every monitor event until shutdown
+pub async fn run(state: AppState, mut events: mpsc::UnboundedReceiver<doorbird::MonitorEvent>) {
+    loop {
+        let event = tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+        };
+        let Some(event) = EventKind::from_monitor(&event) else {
+            continue;
+        };
+
+        let conditions = Conditions {
+            event,
+            away: state.automation.is_away(),
+            time: chrono::Local::now().time(),
+            viewers: viewers(&state).await,
+        };
+        let dry_run = state.automation.dry_run.load(Ordering::Relaxed);
+        for rule in state.automation.fire(&conditions) {
+            let actions: Vec<String> = rule.actions.iter().map(|a| a.to_string()).collect();
+            if dry_run {
+                info!(
+                    "🧪 Dry run: rule '{}' would run on {}: {}",
+                    rule.name,
+                    event.as_str(),
+                    actions.join(", ")
+                );
+            } else {
+                info!(
+                    "🤖 Rule '{}' fired on {}: {}",
+                    rule.name,
+                    event.as_str(),
+                    actions.join(", ")
+                );
+                let state = state.clone();
+                let rule = rule.clone();
+                tokio::spawn(async move {
+                    for action in &rule.actions {
+                        if let Err(e) = perform(&state, &rule, event, action).await {
+                            error!("Rule '{}': {} failed: {:#}", rule.name, action, e);
+                        }
+                    }
+                });
+            }
+            state.automation.record(Firing {
+                rule: rule.name,
+                event,
+                at: unix_now(),
+                actions,
+                dry_run,
+            });
+        }
+    }
+}
+
+/// Video viewers of any kind, not counting a running recording
+async fn viewers(state: &AppState) -> usize {
+    let mut viewers = state.mjpeg_fanout.subscriber_count().await;
+    for (_, fanout) in state.video_tiers.fanouts() {
+        viewers += fanout.subscriber_count().await;
+    }
+    if state.recorder.as_ref().is_some_and(|r| r.is_recording()) {
+        viewers = viewers.saturating_sub(1);
+    }
+    viewers
+}
+
+async fn perform(state: &AppState, rule: &Rule, event: EventKind, action: &Action) -> Result<()> {
+    match action {
+        Action::Announce { clip } => {
+            match announce::play(state, announce::Source::Clip(clip.clone())).await {
+                Ok(_) => {}
+                // Someone answered the door in the meantime
+                Err(AnnounceError::Busy) => warn!(
+                    "Rule '{}': not announcing '{}', someone is talking",
+                    rule.name, clip
+                ),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Action::Relay { relay } => state.doorbird_client.open_door(relay.as_deref()).await?,
+        Action::Light => state.doorbird_client.light_on().await?,
+        Action::Webhook { url } => {
+            state
+                .automation
+                .http
+                .post(url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .json(&serde_json::json!({
+                    "rule": rule.name,
+                    "event": event,
+                    "at": unix_now(),
+                    "away": state.automation.is_away(),
+                }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                // The error would quote the URL and its token
+                .map_err(|e| e.without_url())?;
+        }
+        Action::Record { seconds } => state
+            .recorder
+            .as_ref()
+            .context("recording.dir is not configured")?
+            .record(Duration::from_secs(*seconds))?,
+    }
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Body of `POST /api/away` and its response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AwayMode {
+    pub away: bool,
+}
+
+/// `POST /api/away` - switch away mode on or off
+pub async fn set_away(
+    State(state): State<AppState>,
+    Json(mode): Json<AwayMode>,
+) -> impl IntoResponse {
+    state.automation.set_away(mode.away);
+    Json(AwayMode {
+        away: state.automation.is_away(),
+    })
+}
+
+/// `GET /api/automation` - away flag, rules and recent firings
+pub async fn status(State(state): State<AppState>) -> impl IntoResponse {
+    let automation = &state.automation;
+    let rules: Vec<String> = automation
+        .rules
+        .read()
+        .unwrap()
+        .iter()
+        .map(|rule| rule.name.clone())
+        .collect();
+    let recent: Vec<Firing> = automation
+        .history
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect();
+    Json(serde_json::json!({
+        "away": automation.is_away(),
+        "dry_run": automation.dry_run.load(Ordering::Relaxed),
+        "rules": rules,
+        "recent": recent,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    fn rule(toml: &str) -> Rule {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_schedule() {
+        let day = Schedule::try_from("08:00-18:00".to_string()).unwrap();
+        assert!(day.contains(time("08:00")));
+        assert!(day.contains(time("12:30")));
+        assert!(!day.contains(time("18:00")));
+        assert!(!day.contains(time("03:00")));
+
+        let night = Schedule::try_from("22:00 - 06:00".to_string()).unwrap();
+        assert!(night.contains(time("23:15")));
+        assert!(night.contains(time("05:59")));
+        assert!(!night.contains(time("06:00")));
+        assert!(!night.contains(time("12:00")));
+        assert_eq!(String::from(night), "22:00-06:00");
+
+        for invalid in ["", "08:00", "8-18", "25:00-06:00", "08:00-08:00"] {
+            assert!(
+                Schedule::try_from(invalid.to_string()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_rule_from_toml() {
+        let rule = rule(
+            r#"
+            name = "parcel"
+            event = "doorbell"
+            away = true
+            schedule = "08:00-18:00"
+            actions = [
+                { type = "announce", clip = "parcel" },
+                { type = "relay", relay = "2" },
+                { type = "light" },
+                { type = "record" },
+            ]
+            "#,
+        );
+        assert_eq!(rule.cooldown_secs, 60);
+        assert!(!rule.nobody_viewing);
+        assert_eq!(
+            rule.actions,
+            vec![
+                Action::Announce {
+                    clip: "parcel".into()
+                },
+                Action::Relay {
+                    relay: Some("2".into())
+                },
+                Action::Light,
+                Action::Record { seconds: 30 },
+            ]
+        );
+
+        assert!(toml::from_str::<Rule>(
+            "name = \"x\"\nevent = \"doorbell\"\nactions = [{ type = \"siren\" }]"
+        )
+        .is_err());
+        assert!(toml::from_str::<Rule>(
+            "name = \"x\"\nevent = \"doorbell\"\nactions = [{ type = \"relay\", number = 2 }]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_webhook_display_hides_path() {
+        let action = Action::Webhook {
+            url: "https://user:pw@hooks.example.com:8443/T0/B1/secret?token=x".into(),
+        };
+        assert_eq!(action.to_string(), "webhook https://hooks.example.com:8443");
+        assert_eq!(
+            webhook_origin("https://hooks.example.com/services/secret"),
+            "https://hooks.example.com"
+        );
+        assert_eq!(webhook_origin("not a url"), "(invalid URL)");
+    }
+
+    #[test]
+    fn test_rule_conditions() {
+        let rule = rule(
+            r#"
+            name = "night"
+            event = "motion"
+            away = true
+            schedule = "22:00-06:00"
+            nobody_viewing = true
+            actions = [{ type = "light" }]
+            "#,
+        );
+        let conditions = Conditions {
+            event: EventKind::Motion,
+            away: true,
+            time: time("23:00"),
+            viewers: 0,
+        };
+        assert!(rule.matches(&conditions));
+        assert!(!rule.matches(&Conditions {
+            event: EventKind::Doorbell,
+            ..conditions
+        }));
+        assert!(!rule.matches(&Conditions {
+            away: false,
+            ..conditions
+        }));
+        assert!(!rule.matches(&Conditions {
+            time: time("12:00"),
+            ..conditions
+        }));
+        assert!(!rule.matches(&Conditions {
+            viewers: 1,
+            ..conditions
+        }));
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut config = config::Automation::default();
+        config.rules.push(rule(
+            r#"
+            name = "ring"
+            event = "doorbell"
+            actions = [{ type = "light" }]
+            "#,
+        ));
+        config.rules.push(rule(
+            r#"
+            name = "always"
+            event = "doorbell"
+            cooldown_secs = 0
+            actions = [{ type = "light" }]
+            "#,
+        ));
+        let automation = Automation::new(&config);
+        let conditions = Conditions {
+            event: EventKind::Doorbell,
+            away: false,
+            time: time("12:00"),
+            viewers: 0,
+        };
+        let names = |rules: Vec<Rule>| rules.into_iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(names(automation.fire(&conditions)), vec!["ring", "always"]);
+        assert_eq!(names(automation.fire(&conditions)), vec!["always"]);
+    }
+}
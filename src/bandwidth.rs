@@ -0,0 +1,493 @@
+//! Receiver-side bandwidth estimation and adaptive video tier selection
+//!
+//! Browsers report how much bandwidth they think is available (REMB) and how many
+//! of our packets they lost (receiver reports, TWCC feedback). This module turns
+//! that RTCP feedback into a decision about what each viewer should receive:
+//! - `High` tier when the estimate comfortably fits the high resolution stream
+//! - `Low` tier when bandwidth is constrained (if a low tier exists)
+//! - Audio only when even the low tier doesn't fit
+//!
+//! Decisions use hysteresis (separate down/up thresholds and hold times) so a
+//! viewer on a flaky link doesn't bounce between tiers every second.
+//!
+//! A viewer below its preferred level receives less than the link might carry, so
+//! the feedback can't show that the link recovered (browsers that use TWCC send no
+//! REMB at all). The controller therefore re-probes: after `PROBE_INTERVAL` without
+//! loss it steps up one level and waits for fresh feedback. A probe that gets
+//! downgraded again doubles the interval, up to `MAX_PROBE_INTERVAL`.
+
+use crate::video_tiers::VideoTier;
+use std::time::{Duration, Instant};
+use webrtc::rtcp;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+};
+
+/// Minimum time between a change and a subsequent downgrade
+const DOWNGRADE_HOLD: Duration = Duration::from_secs(2);
+
+/// Minimum time between a change and a subsequent upgrade
+const UPGRADE_HOLD: Duration = Duration::from_secs(10);
+
+/// Estimate must exceed the threshold by this factor before upgrading
+const UPGRADE_HEADROOM: f64 = 1.25;
+
+/// Loss fraction above which the current level is considered overloaded
+const LOSS_DOWNGRADE: f64 = 0.10;
+
+/// Loss fraction below which an upgrade is allowed
+const LOSS_UPGRADE: f64 = 0.02;
+
+/// EWMA weight of each new sample
+const SMOOTHING: f64 = 0.3;
+
+/// Feedback without a fresh estimate for this long is ignored (browser stopped sending REMB)
+const ESTIMATE_STALE: Duration = Duration::from_secs(10);
+
+/// Time below the preferred level before probing the next level up
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest probe interval after repeated failed probes
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Bandwidth adaptation settings
+#[derive(Debug, Clone, Copy)]
+pub struct BandwidthConfig {
+    /// Whether viewers are moved between tiers automatically
+    pub enabled: bool,
+    /// Minimum estimated bandwidth for the high tier (kbit/s)
+    pub high_min_kbps: u32,
+    /// Minimum estimated bandwidth for any video (kbit/s); below this, audio only
+    pub video_min_kbps: u32,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            high_min_kbps: 1500,
+            video_min_kbps: 250,
+        }
+    }
+}
+
+/// What a viewer should receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QualityLevel {
+    AudioOnly,
+    Low,
+    High,
+}
+
+impl QualityLevel {
+    /// Video tier for this level, or `None` for audio only
+    pub fn video_tier(&self) -> Option<VideoTier> {
+        match self {
+            QualityLevel::AudioOnly => None,
+            QualityLevel::Low => Some(VideoTier::Low),
+            QualityLevel::High => Some(VideoTier::High),
+        }
+    }
+
+    fn from_tier(tier: VideoTier) -> Self {
+        match tier {
+            VideoTier::Low => QualityLevel::Low,
+            VideoTier::High => QualityLevel::High,
+        }
+    }
+}
+
+/// Bandwidth-related information extracted from RTCP
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtcpFeedback {
+    /// Receiver estimated maximum bitrate (bits/s)
+    Remb(f64),
+    /// Fraction of packets lost since the last report (0.0 - 1.0)
+    Loss(f64),
+}
+
+/// Extract bandwidth feedback from a batch of RTCP packets
+pub fn feedback_from_rtcp(
+    packets: &[Box<dyn rtcp::packet::Packet + Send + Sync>],
+) -> Vec<RtcpFeedback> {
+    let mut feedback = Vec::new();
+
+    for packet in packets {
+        let any = packet.as_any();
+        if let Some(remb) = any.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+            feedback.push(RtcpFeedback::Remb(remb.bitrate as f64));
+        } else if let Some(rr) = any.downcast_ref::<ReceiverReport>() {
+            for report in &rr.reports {
+                feedback.push(RtcpFeedback::Loss(report.fraction_lost as f64 / 256.0));
+            }
+        } else if let Some(tcc) = any.downcast_ref::<TransportLayerCc>() {
+            if let Some(loss) = twcc_loss_fraction(tcc) {
+                feedback.push(RtcpFeedback::Loss(loss));
+            }
+        }
+    }
+
+    feedback
+}
+
+/// Fraction of packets reported as not received in a TWCC feedback packet
+fn twcc_loss_fraction(tcc: &TransportLayerCc) -> Option<f64> {
+    let mut total = 0u32;
+    let mut lost = 0u32;
+
+    for chunk in &tcc.packet_chunks {
+        match chunk {
+            PacketStatusChunk::RunLengthChunk(run) => {
+                total += run.run_length as u32;
+                if run.packet_status_symbol == SymbolTypeTcc::PacketNotReceived {
+                    lost += run.run_length as u32;
+                }
+            }
+            PacketStatusChunk::StatusVectorChunk(vector) => {
+                for symbol in &vector.symbol_list {
+                    total += 1;
+                    if *symbol == SymbolTypeTcc::PacketNotReceived {
+                        lost += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Chunks can be padded past the real packet count
+    let total = total.min(tcc.packet_status_count as u32);
+    if total == 0 {
+        return None;
+    }
+    Some(lost.min(total) as f64 / total as f64)
+}
+
+/// Per-session controller deciding the quality level from bandwidth feedback
+pub struct BandwidthController {
+    config: BandwidthConfig,
+    /// Whether a separate low tier exists
+    has_low_tier: bool,
+    /// Highest level the viewer asked for
+    preferred: QualityLevel,
+    /// Level currently chosen
+    level: QualityLevel,
+    last_change: Instant,
+    /// Smoothed bandwidth estimate (bits/s) and when it was last updated
+    estimate_bps: Option<(f64, Instant)>,
+    /// Smoothed loss fraction
+    loss: f64,
+    /// Current wait before the next probe
+    probe_interval: Duration,
+    /// When the running probe started (until it held for `UPGRADE_HOLD` or failed)
+    probing_since: Option<Instant>,
+}
+
+impl BandwidthController {
+    pub fn new(config: BandwidthConfig, has_low_tier: bool, now: Instant) -> Self {
+        Self {
+            config,
+            has_low_tier,
+            preferred: QualityLevel::High,
+            level: QualityLevel::High,
+            last_change: now,
+            estimate_bps: None,
+            loss: 0.0,
+            probe_interval: PROBE_INTERVAL,
+            probing_since: None,
+        }
+    }
+
+    /// Record a piece of RTCP feedback
+    pub fn on_feedback(&mut self, feedback: RtcpFeedback, now: Instant) {
+        match feedback {
+            RtcpFeedback::Remb(bps) => {
+                let smoothed = match self.estimate_bps {
+                    Some((prev, _)) => prev + SMOOTHING * (bps - prev),
+                    None => bps,
+                };
+                self.estimate_bps = Some((smoothed, now));
+            }
+            RtcpFeedback::Loss(fraction) => {
+                self.loss += SMOOTHING * (fraction - self.loss);
+            }
+        }
+    }
+
+    /// Set the tier the viewer asked for; it caps the automatic choice
+    ///
+    /// Returns the new level if it changed as a result.
+    pub fn set_preferred(&mut self, tier: VideoTier, now: Instant) -> Option<QualityLevel> {
+        self.preferred = self.clamp_to_available(QualityLevel::from_tier(tier));
+        // An explicit choice applies immediately; if it's more than the link can
+        // take, the estimator steps down again after the usual hold time
+        self.change_to(self.preferred, now)
+    }
+
+    /// Current smoothed bandwidth estimate in kbit/s (if any)
+    pub fn estimate_kbps(&self) -> Option<u32> {
+        self.estimate_bps.map(|(bps, _)| (bps / 1000.0) as u32)
+    }
+
+    /// Current smoothed loss fraction
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    /// Current level
+    pub fn level(&self) -> QualityLevel {
+        self.level
+    }
+
+    /// Re-evaluate the level; returns the new level if it changed
+    pub fn evaluate(&mut self, now: Instant) -> Option<QualityLevel> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let ideal = self.ideal_level(now);
+        let since_change = now.saturating_duration_since(self.last_change);
+
+        if ideal < self.level && since_change >= DOWNGRADE_HOLD {
+            if self.probing_since.take().is_some() {
+                // The probed level didn't fit: wait longer before the next probe
+                self.probe_interval = (self.probe_interval * 2).min(MAX_PROBE_INTERVAL);
+            }
+            // Step down one level at a time
+            let next = self.step_down(self.level);
+            return self.change_to(next.max(ideal), now);
+        }
+
+        if self
+            .probing_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= UPGRADE_HOLD)
+        {
+            // The probed level held
+            self.probing_since = None;
+            self.probe_interval = PROBE_INTERVAL;
+        }
+
+        if ideal > self.level && since_change >= UPGRADE_HOLD && self.loss <= LOSS_UPGRADE {
+            let next = self.step_up(self.level);
+            return self.change_to(next.min(ideal), now);
+        }
+
+        if self.level < self.preferred
+            && since_change >= self.probe_interval
+            && self.loss <= LOSS_UPGRADE
+        {
+            // The old estimate describes what we sent, not what the link carries:
+            // hold the probed level until fresh feedback arrives
+            let next = self.clamp_to_available(self.step_up(self.level).min(self.preferred));
+            self.estimate_bps = None;
+            self.probing_since = Some(now);
+            return self.change_to(next, now);
+        }
+
+        None
+    }
+
+    /// Level the current estimate supports (capped by preference)
+    fn ideal_level(&self, now: Instant) -> QualityLevel {
+        let estimate = self
+            .estimate_bps
+            .filter(|(_, at)| now.saturating_duration_since(*at) < ESTIMATE_STALE)
+            .map(|(bps, _)| bps);
+
+        let high_min = self.config.high_min_kbps as f64 * 1000.0;
+        let video_min = self.config.video_min_kbps as f64 * 1000.0;
+
+        // Thresholds depend on direction so the level is sticky around the boundary
+        let headroom = |level: QualityLevel| {
+            if level > self.level {
+                UPGRADE_HEADROOM
+            } else {
+                1.0
+            }
+        };
+
+        let mut ideal = match estimate {
+            Some(bps) if bps >= high_min * headroom(QualityLevel::High) => QualityLevel::High,
+            Some(bps) if bps >= video_min * headroom(QualityLevel::Low) => QualityLevel::Low,
+            Some(_) => QualityLevel::AudioOnly,
+            // No estimate yet: keep what we have
+            None => self.level,
+        };
+
+        // Heavy loss means the current level is too much regardless of the estimate
+        if self.loss > LOSS_DOWNGRADE && ideal >= self.level {
+            ideal = self.step_down(self.level);
+        }
+
+        self.clamp_to_available(ideal.min(self.preferred))
+    }
+
+    fn step_down(&self, level: QualityLevel) -> QualityLevel {
+        match level {
+            QualityLevel::High if self.has_low_tier => QualityLevel::Low,
+            QualityLevel::High | QualityLevel::Low | QualityLevel::AudioOnly => {
+                QualityLevel::AudioOnly
+            }
+        }
+    }
+
+    fn step_up(&self, level: QualityLevel) -> QualityLevel {
+        match level {
+            QualityLevel::AudioOnly if self.has_low_tier => QualityLevel::Low,
+            QualityLevel::AudioOnly | QualityLevel::Low | QualityLevel::High => QualityLevel::High,
+        }
+    }
+
+    /// Map `Low` to `High` when there is no separate low tier
+    fn clamp_to_available(&self, level: QualityLevel) -> QualityLevel {
+        match level {
+            QualityLevel::Low if !self.has_low_tier => QualityLevel::High,
+            other => other,
+        }
+    }
+
+    fn change_to(&mut self, level: QualityLevel, now: Instant) -> Option<QualityLevel> {
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        self.last_change = now;
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(has_low_tier: bool) -> (BandwidthController, Instant) {
+        let now = Instant::now();
+        (
+            BandwidthController::new(BandwidthConfig::default(), has_low_tier, now),
+            now,
+        )
+    }
+
+    #[test]
+    fn test_starts_high_without_feedback() {
+        let (mut c, now) = controller(true);
+        assert_eq!(c.evaluate(now + Duration::from_secs(30)), None);
+        assert_eq!(c.level(), QualityLevel::High);
+    }
+
+    #[test]
+    fn test_downgrades_step_by_step() {
+        let (mut c, now) = controller(true);
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), now);
+
+        let t1 = now + DOWNGRADE_HOLD;
+        assert_eq!(c.evaluate(t1), Some(QualityLevel::Low));
+        // Hold time applies between changes
+        assert_eq!(c.evaluate(t1 + Duration::from_millis(500)), None);
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), t1);
+        assert_eq!(
+            c.evaluate(t1 + DOWNGRADE_HOLD),
+            Some(QualityLevel::AudioOnly)
+        );
+    }
+
+    #[test]
+    fn test_skips_missing_low_tier() {
+        let (mut c, now) = controller(false);
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), now);
+        assert_eq!(
+            c.evaluate(now + DOWNGRADE_HOLD),
+            Some(QualityLevel::AudioOnly)
+        );
+    }
+
+    #[test]
+    fn test_upgrade_requires_headroom_and_hold() {
+        let (mut c, now) = controller(true);
+        c.on_feedback(RtcpFeedback::Remb(500_000.0), now);
+        assert_eq!(c.evaluate(now + DOWNGRADE_HOLD), Some(QualityLevel::Low));
+
+        // Just above the high threshold: not enough headroom to go back up
+        let t = now + DOWNGRADE_HOLD + UPGRADE_HOLD;
+        for _ in 0..20 {
+            c.on_feedback(RtcpFeedback::Remb(1_600_000.0), t);
+        }
+        assert_eq!(c.evaluate(t), None);
+
+        for _ in 0..20 {
+            c.on_feedback(RtcpFeedback::Remb(3_000_000.0), t);
+        }
+        assert_eq!(c.evaluate(t), Some(QualityLevel::High));
+    }
+
+    #[test]
+    fn test_probes_up_from_audio_only() {
+        let (mut c, now) = controller(true);
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), now);
+        assert_eq!(c.evaluate(now + DOWNGRADE_HOLD), Some(QualityLevel::Low));
+        let t = now + DOWNGRADE_HOLD * 2;
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), t);
+        assert_eq!(c.evaluate(t), Some(QualityLevel::AudioOnly));
+
+        // Audio only carries too little for the estimate to recover, so probe
+        assert_eq!(c.evaluate(t + PROBE_INTERVAL / 2), None);
+        let probe = t + PROBE_INTERVAL;
+        assert_eq!(c.evaluate(probe), Some(QualityLevel::Low));
+        assert_eq!(c.estimate_kbps(), None);
+
+        // The probe fails: back down, and the next probe waits twice as long
+        c.on_feedback(RtcpFeedback::Remb(100_000.0), probe);
+        let failed = probe + DOWNGRADE_HOLD;
+        assert_eq!(c.evaluate(failed), Some(QualityLevel::AudioOnly));
+        assert_eq!(c.evaluate(failed + PROBE_INTERVAL), None);
+        let probe = failed + PROBE_INTERVAL * 2;
+        assert_eq!(c.evaluate(probe), Some(QualityLevel::Low));
+
+        // This one holds, and the estimate takes it the rest of the way
+        c.on_feedback(RtcpFeedback::Remb(3_000_000.0), probe + UPGRADE_HOLD);
+        assert_eq!(c.evaluate(probe + UPGRADE_HOLD), Some(QualityLevel::High));
+        assert_eq!(c.probe_interval, PROBE_INTERVAL);
+    }
+
+    #[test]
+    fn test_heavy_loss_downgrades() {
+        let (mut c, now) = controller(true);
+        c.on_feedback(RtcpFeedback::Remb(5_000_000.0), now);
+        for _ in 0..10 {
+            c.on_feedback(RtcpFeedback::Loss(0.3), now);
+        }
+        assert_eq!(c.evaluate(now + DOWNGRADE_HOLD), Some(QualityLevel::Low));
+    }
+
+    #[test]
+    fn test_preference_caps_level() {
+        let (mut c, now) = controller(true);
+        assert_eq!(
+            c.set_preferred(VideoTier::Low, now),
+            Some(QualityLevel::Low)
+        );
+        c.on_feedback(RtcpFeedback::Remb(10_000_000.0), now);
+        assert_eq!(c.evaluate(now + UPGRADE_HOLD * 2), None);
+        assert_eq!(
+            c.set_preferred(VideoTier::High, now),
+            Some(QualityLevel::High)
+        );
+    }
+
+    #[test]
+    fn test_disabled_only_follows_preference() {
+        let now = Instant::now();
+        let config = BandwidthConfig {
+            enabled: false,
+            ..BandwidthConfig::default()
+        };
+        let mut c = BandwidthController::new(config, true, now);
+        c.on_feedback(RtcpFeedback::Remb(10_000.0), now);
+        assert_eq!(c.evaluate(now + Duration::from_secs(60)), None);
+        assert_eq!(
+            c.set_preferred(VideoTier::Low, now),
+            Some(QualityLevel::Low)
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Command line interface
+//!
+//! Without a subcommand the server runs. `config check` validates the
+//! configuration and prints the effective settings with secrets redacted.
+//!
+//! The device commands (`info`, `open-door`, `light`, `snapshot`, `monitor`,
+//! `listen`, `say`, `probe`) talk to the configured DoorBird directly, without a
+//! running server.
+
+use crate::audio_file;
+use crate::config::Config;
+use crate::g711;
+use crate::h264_extractor::H264Extractor;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+/// Longest wait for each `probe` check
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Parser)]
+#[command(version, about = "WebRTC gateway for DoorBird smart doorbells")]
+pub struct Cli {
+    /// Configuration file (default: birdbox.toml in the working directory, if present)
+    #[arg(long, short, global = true, env = "BIRDBOX_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    #[command(flatten)]
+    Device(DeviceCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print the effective settings (secrets redacted)
+    Check,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DeviceCommand {
+    /// Print the DoorBird device information
+    Info,
+    /// Trigger a door relay
+    OpenDoor {
+        /// Relay to trigger, e.g. "2" or "gggaaa@1" for a paired DoorController
+        /// (default: relay 1)
+        #[arg(long)]
+        relay: Option<String>,
+    },
+    /// Switch on the infrared light
+    Light,
+    /// Save a live JPEG image
+    Snapshot {
+        /// Output file
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Print doorbell and motion events until Ctrl-C
+    Monitor,
+    /// Record the DoorBird microphone to a WAV file until Ctrl-C
+    Listen {
+        /// Output file (8kHz mono 16-bit WAV)
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Stop after this many seconds
+        #[arg(long)]
+        seconds: Option<u64>,
+    },
+    /// Play an audio file (WAV, MP3, Opus, ...) on the DoorBird speaker
+    Say {
+        /// Audio file
+        file: PathBuf,
+    },
+    /// Check the DoorBird endpoints birdbox uses and report their latency
+    Probe,
+}
+
+/// `config check`
+pub fn config_check(path: Option<&Path>) -> ExitCode {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match toml::to_string(&config.redacted()) {
+        Ok(text) => {
+            match &config.source {
+                Some(source) => println!("# Configuration OK: {} + environment", source.display()),
+                None => println!("# Configuration OK: environment only (no configuration file)"),
+            }
+            println!();
+            print!("{}", text);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to print configuration: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run a device command against the configured DoorBird
+pub async fn device(command: DeviceCommand, path: Option<&Path>) -> ExitCode {
+    let result = match Config::load(path) {
+        Ok(config) => run_device_command(command, &config).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_device_command(command: DeviceCommand, config: &Config) -> Result<()> {
+    let client = doorbird::Client::new(
+        config.doorbird.url.clone(),
+        config.doorbird.user.clone(),
+        config.doorbird.password.clone(),
+    );
+
+    match command {
+        DeviceCommand::Info => {
+            let info = client.info().await?;
+            println!("Firmware:     {}", info.firmware);
+            println!("Build:        {}", info.build_number);
+            if let Some(device_type) = &info.device_type {
+                println!("Device type:  {}", device_type);
+            }
+            if let Some(mac) = &info.primary_mac_addr {
+                println!("MAC address:  {}", mac);
+            }
+            if let Some(relays) = &info.relays {
+                println!("Relays:       {}", relays.join(", "));
+            }
+            let video = if info.supports_1080p() {
+                "1080p"
+            } else if info.supports_720p() {
+                "720p"
+            } else {
+                "default resolution"
+            };
+            println!("Video:        {}", video);
+        }
+        DeviceCommand::OpenDoor { relay } => {
+            client.open_door(relay.as_deref()).await?;
+            println!("Relay {} triggered", relay.as_deref().unwrap_or("1"));
+        }
+        DeviceCommand::Light => {
+            client.light_on().await?;
+            println!("Light switched on");
+        }
+        DeviceCommand::Snapshot { output } => {
+            let jpeg = client.image().await?;
+            tokio::fs::write(&output, &jpeg)
+                .await
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            println!("Saved {} ({} bytes)", output.display(), jpeg.len());
+        }
+        DeviceCommand::Monitor => {
+            let mut events = client.monitor_events().await?;
+            println!("Waiting for events (Ctrl-C to stop)");
+            let started = Instant::now();
+            let stop = interrupted(None);
+            tokio::pin!(stop);
+            loop {
+                let event = tokio::select! {
+                    event = events.next() => event,
+                    _ = &mut stop => break,
+                };
+                let event = match event {
+                    Some(Ok(doorbird::MonitorEvent::Doorbell)) => "doorbell pressed",
+                    Some(Ok(doorbird::MonitorEvent::MotionSensor { active: true })) => {
+                        "motion detected"
+                    }
+                    Some(Ok(doorbird::MonitorEvent::MotionSensor { active: false })) => {
+                        "motion cleared"
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => anyhow::bail!("Event stream ended"),
+                };
+                println!("[{:>8.1}s] {}", started.elapsed().as_secs_f32(), event);
+            }
+        }
+        DeviceCommand::Listen { output, seconds } => {
+            let mut audio = client.audio_receive().await?;
+            println!("Recording to {} (Ctrl-C to stop)", output.display());
+            let mut ulaw = Vec::new();
+            let stop = interrupted(seconds.map(Duration::from_secs));
+            tokio::pin!(stop);
+            loop {
+                let chunk = tokio::select! {
+                    chunk = audio.next() => chunk,
+                    _ = &mut stop => break,
+                };
+                match chunk {
+                    Some(chunk) => ulaw.extend_from_slice(&chunk?),
+                    None => break,
+                }
+            }
+
+            let samples = g711::decode_ulaw_buffer(&ulaw);
+            let mut wav =
+                audio_file::wav_header(audio_file::SAMPLE_RATE, samples.len() as u32).to_vec();
+            wav.extend_from_slice(&audio_file::wav_data(&samples));
+            tokio::fs::write(&output, wav)
+                .await
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            println!(
+                "Saved {:.1}s of audio to {}",
+                audio_file::duration(&ulaw).as_secs_f32(),
+                output.display()
+            );
+        }
+        DeviceCommand::Say { file } => {
+            let ulaw = {
+                let file = file.clone();
+                tokio::task::spawn_blocking(move || audio_file::decode_ulaw(&file)).await??
+            };
+            println!(
+                "Playing {} ({:.1}s)",
+                file.display(),
+                audio_file::duration(&ulaw).as_secs_f32()
+            );
+            client
+                .audio_transmit(audio_file::ulaw_stream(Bytes::from(ulaw)))
+                .await?;
+        }
+        DeviceCommand::Probe => probe(&client, config).await?,
+    }
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or once `limit` has passed
+async fn interrupted(limit: Option<Duration>) {
+    let limit = async {
+        match limit {
+            Some(limit) => tokio::time::sleep(limit).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = limit => {}
+    }
+}
+
+/// `probe`: time the endpoints birdbox depends on
+async fn probe(client: &doorbird::Client, config: &Config) -> Result<()> {
+    let rtsp_url = client.video_receive(doorbird::VideoQuality::Default);
+    let rtsp_transport = config.video.rtsp_transport.as_str();
+
+    let checks = [
+        (
+            "info",
+            timed(async {
+                let info = client.info().await?;
+                Ok(format!(
+                    "firmware {}, build {}",
+                    info.firmware, info.build_number
+                ))
+            })
+            .await,
+        ),
+        (
+            "events",
+            timed(async {
+                client.monitor_events().await?;
+                Ok("monitor stream open".to_string())
+            })
+            .await,
+        ),
+        (
+            "audio",
+            timed(async {
+                let mut audio = client.audio_receive().await?;
+                match audio.next().await {
+                    Some(chunk) => Ok(format!("received {} bytes", chunk?.len())),
+                    None => anyhow::bail!("stream ended without audio"),
+                }
+            })
+            .await,
+        ),
+        (
+            "video",
+            timed(async {
+                let deadline = Instant::now() + PROBE_TIMEOUT;
+                tokio::task::spawn_blocking(move || {
+                    let mut extractor = H264Extractor::new(rtsp_url, rtsp_transport)?;
+                    while Instant::now() < deadline {
+                        if let Some(packet) = extractor.next_packet()? {
+                            let resolution = extractor
+                                .stream_info()
+                                .map(|info| format!(", {}x{}", info.width, info.height))
+                                .unwrap_or_default();
+                            return Ok(format!(
+                                "RTSP ({}) first packet {} bytes{}",
+                                rtsp_transport,
+                                packet.data.len(),
+                                resolution
+                            ));
+                        }
+                    }
+                    anyhow::bail!("no video received")
+                })
+                .await?
+            })
+            .await,
+        ),
+    ];
+
+    let mut failed = 0;
+    for (name, (elapsed, result)) in &checks {
+        let (status, detail) = match result {
+            Ok(detail) => ("ok", detail.clone()),
+            Err(e) => {
+                failed += 1;
+                ("FAIL", format!("{:#}", e))
+            }
+        };
+        println!(
+            "{:<8} {:<4} {:>6} ms  {}",
+            name,
+            status,
+            elapsed.as_millis(),
+            detail
+        );
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
+
+/// Run a `probe` check with `PROBE_TIMEOUT`, measuring how long it took
+async fn timed(check: impl Future<Output = Result<String>>) -> (Duration, Result<String>) {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!(
+            "no response within {}s",
+            PROBE_TIMEOUT.as_secs()
+        )),
+    };
+    (started.elapsed(), result)
+}
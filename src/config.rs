@@ -0,0 +1,1016 @@
+//! Configuration
+//!
+//! Settings are read from an optional TOML file (`--config`, `BIRDBOX_CONFIG`, or
+//! `birdbox.toml` in the working directory if it exists) and then overridden by the
+//! `BIRDBOX_*` environment variables, so existing `.env` files keep working. Empty
+//! variables are ignored. See `birdbox.example.toml` for every setting.
+//!
+//! Invalid values are errors instead of silent fallbacks: startup stops and lists
+//! every problem, naming both the file key and the environment variable.
+//! `birdbox-rs config check` prints the effective configuration with secrets
+//! redacted.
+
+use crate::automation::{self, Action, Rule};
+use crate::bandwidth::BandwidthConfig;
+use crate::host_addrs;
+use crate::ice_servers;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File read when no `--config` is given
+pub const DEFAULT_PATH: &str = "birdbox.toml";
+
+/// Replacement for secrets in `config check` output
+const REDACTED: &str = "<redacted>";
+
+/// Complete configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub doorbird: Doorbird,
+    pub audio: Audio,
+    pub video: Video,
+    pub webrtc: WebRtc,
+    pub http: Http,
+    pub auth: Auth,
+    pub ice: Ice,
+    pub turn: Turn,
+    pub rtsp_server: RtspServer,
+    pub hls: Hls,
+    pub mjpeg: Mjpeg,
+    pub health: Health,
+    pub shutdown: Shutdown,
+    pub announce: Announce,
+    pub recording: Recording,
+    pub automation: Automation,
+    /// File the configuration was read from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// DoorBird device and credentials
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Doorbird {
+    /// Base URL, e.g. `http://192.168.1.100`
+    pub url: String,
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Audio {
+    /// Opus samples (20ms each) buffered per audio subscriber
+    pub fanout_buffer_samples: usize,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            fanout_buffer_samples: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Video {
+    /// H.264 frames buffered per video subscriber
+    pub fanout_buffer_frames: usize,
+    /// Offer the device default resolution as a low tier
+    pub simulcast: bool,
+    /// Transport for the DoorBird RTSP stream
+    pub rtsp_transport: RtspTransport,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self {
+            fanout_buffer_frames: 4,
+            simulcast: true,
+            rtsp_transport: RtspTransport::Udp,
+        }
+    }
+}
+
+/// RTSP transport protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtspTransport {
+    /// Lower latency on simple networks
+    Udp,
+    /// More reliable through VPNs and Docker networking
+    Tcp,
+}
+
+impl RtspTransport {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RtspTransport::Udp => "udp",
+            RtspTransport::Tcp => "tcp",
+        }
+    }
+}
+
+impl FromStr for RtspTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(RtspTransport::Udp),
+            "tcp" => Ok(RtspTransport::Tcp),
+            _ => Err("expected \"udp\" or \"tcp\"".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebRtc {
+    /// UDP port shared by all WebRTC sessions
+    pub udp_port: u16,
+    /// Bind the UDP socket to this address only (default: all interfaces)
+    pub bind_ip: Option<IpAddr>,
+    /// Addresses advertised to clients (detected from the interfaces when empty)
+    pub host_ip: Vec<IpAddr>,
+    /// Additional LAN addresses advertised in dual-network setups
+    pub host_ip_lan: Vec<IpAddr>,
+    /// Sent packets kept per track for NACK retransmission (rounded up to a power of two)
+    pub nack_buffer_packets: u16,
+    /// Move viewers between video tiers based on their bandwidth
+    pub adaptive_video: bool,
+    pub bwe_high_min_kbps: u32,
+    pub bwe_video_min_kbps: u32,
+    /// How long a dropped intercom session can be resumed (0 disables resuming)
+    pub session_resume_secs: u64,
+}
+
+impl Default for WebRtc {
+    fn default() -> Self {
+        let bandwidth = BandwidthConfig::default();
+        Self {
+            udp_port: 50000,
+            bind_ip: None,
+            host_ip: Vec::new(),
+            host_ip_lan: Vec::new(),
+            nack_buffer_packets: crate::webrtc::DEFAULT_NACK_BUFFER_PACKETS,
+            adaptive_video: bandwidth.enabled,
+            bwe_high_min_kbps: bandwidth.high_min_kbps,
+            bwe_video_min_kbps: bandwidth.video_min_kbps,
+            session_resume_secs: crate::resume::DEFAULT_RESUME_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl WebRtc {
+    pub fn bandwidth(&self) -> BandwidthConfig {
+        BandwidthConfig {
+            enabled: self.adaptive_video,
+            high_min_kbps: self.bwe_high_min_kbps,
+            video_min_kbps: self.bwe_video_min_kbps,
+        }
+    }
+}
+
+/// Web server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http {
+    /// Origins allowed to use WHEP/WHIP from other sites, e.g. `https://player.example.com`
+    pub cors_origins: Vec<String>,
+}
+
+/// Users authenticated by a reverse proxy (see `auth.rs`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Auth {
+    /// Header the proxy sets to the authenticated user name, e.g. `Remote-User`
+    pub user_header: Option<String>,
+    /// Addresses of the proxies allowed to set the header
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Users that count as authenticated (any user the proxy signed in when empty)
+    pub users: Vec<String>,
+}
+
+/// STUN/TURN servers offered to clients
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ice {
+    /// `stun:`, `turn:` or `turns:` URLs
+    pub servers: Vec<String>,
+    /// Credentials for the TURN URLs
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+/// Embedded TURN relay, enabled by setting `port`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Turn {
+    pub port: Option<u16>,
+    /// Host name or IP clients use to reach the relay (default: first `webrtc.host_ip`)
+    pub host: Option<String>,
+    /// Address used for relayed traffic (default: first LAN, then host IP)
+    pub relay_ip: Option<IpAddr>,
+    /// Secret for minting credentials (default: random, valid until restart)
+    pub secret: Option<String>,
+    pub credential_ttl_secs: u64,
+    /// Port for TURN over TLS (`turns:`), e.g. 443; needs `tls_cert` and `tls_key`
+    pub tls_port: Option<u16>,
+    /// PEM certificate chain for `tls_port`
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_port`
+    pub tls_key: Option<PathBuf>,
+    /// `turns:` URL of an external TLS proxy in front of the TCP port
+    pub tls_url: Option<String>,
+}
+
+impl Default for Turn {
+    fn default() -> Self {
+        Self {
+            port: None,
+            host: None,
+            relay_ip: None,
+            secret: None,
+            credential_ttl_secs: 12 * 3600,
+            tls_port: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_url: None,
+        }
+    }
+}
+
+/// RTSP re-streaming server, enabled by setting `port`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RtspServer {
+    pub port: Option<u16>,
+    /// Basic auth for RTSP clients (both or neither)
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hls {
+    /// Target segment duration in seconds
+    pub segment_secs: u32,
+    /// Target duration of LL-HLS partial segments in milliseconds
+    pub part_ms: u32,
+}
+
+impl Default for Hls {
+    fn default() -> Self {
+        Self {
+            segment_secs: 2,
+            part_ms: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mjpeg {
+    /// Maximum frame rate of `/api/stream.mjpeg`
+    pub max_fps: u32,
+}
+
+impl Default for Mjpeg {
+    fn default() -> Self {
+        Self { max_fps: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Health {
+    /// `/readyz` fails unless the DoorBird answers within this many seconds
+    pub doorbird_timeout_secs: u64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            doorbird_timeout_secs: crate::health::DEFAULT_DOORBIRD_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shutdown {
+    /// Longest time to wait for sessions and streams to end on SIGTERM/Ctrl-C
+    pub timeout_secs: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            timeout_secs: crate::shutdown::DEFAULT_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Announce {
+    /// Directory of clips for `POST /api/announce?clip=<name>`
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Recording {
+    /// Directory for recordings started by automation rules
+    pub dir: Option<PathBuf>,
+}
+
+/// Automation rules (see `automation.rs`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Automation {
+    /// Away mode at startup; toggled at runtime with `POST /api/away`
+    pub away: bool,
+    /// Log the actions instead of running them
+    pub dry_run: bool,
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    /// Load the file (if any), apply environment overrides and validate
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let default_path = Path::new(DEFAULT_PATH);
+        let path = path.or_else(|| default_path.exists().then_some(default_path));
+
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let mut config: Config = toml::from_str(&text)
+                    .with_context(|| format!("Invalid configuration file {}", path.display()))?;
+                config.source = Some(path.to_path_buf());
+                config
+            }
+            None => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Override settings from `BIRDBOX_*` variables
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let mut env = Env {
+            var,
+            errors: Vec::new(),
+        };
+
+        env.set("BIRDBOX_DOORBIRD_URL", &mut self.doorbird.url);
+        env.set("BIRDBOX_DOORBIRD_USER", &mut self.doorbird.user);
+        env.set("BIRDBOX_DOORBIRD_PASSWORD", &mut self.doorbird.password);
+
+        env.set(
+            "BIRDBOX_AUDIO_FANOUT_BUFFER_SAMPLES",
+            &mut self.audio.fanout_buffer_samples,
+        );
+
+        env.set(
+            "BIRDBOX_VIDEO_FANOUT_BUFFER_FRAMES",
+            &mut self.video.fanout_buffer_frames,
+        );
+        env.set_with(
+            "BIRDBOX_VIDEO_SIMULCAST",
+            &mut self.video.simulcast,
+            parse_bool,
+        );
+        env.set(
+            "BIRDBOX_RTSP_TRANSPORT_PROTOCOL",
+            &mut self.video.rtsp_transport,
+        );
+
+        let webrtc = &mut self.webrtc;
+        env.set("BIRDBOX_UDP_PORT", &mut webrtc.udp_port);
+        env.set_with("BIRDBOX_BIND_IP", &mut webrtc.bind_ip, |s| {
+            parse_ip(s).map(Some)
+        });
+        env.set_with(
+            "BIRDBOX_HOST_IP",
+            &mut webrtc.host_ip,
+            host_addrs::parse_list,
+        );
+        env.set_with(
+            "BIRDBOX_HOST_IP_LAN",
+            &mut webrtc.host_ip_lan,
+            host_addrs::parse_list,
+        );
+        env.set(
+            "BIRDBOX_NACK_BUFFER_PACKETS",
+            &mut webrtc.nack_buffer_packets,
+        );
+        env.set_with(
+            "BIRDBOX_ADAPTIVE_VIDEO",
+            &mut webrtc.adaptive_video,
+            parse_bool,
+        );
+        env.set("BIRDBOX_BWE_HIGH_MIN_KBPS", &mut webrtc.bwe_high_min_kbps);
+        env.set("BIRDBOX_BWE_VIDEO_MIN_KBPS", &mut webrtc.bwe_video_min_kbps);
+        env.set(
+            "BIRDBOX_SESSION_RESUME_SECS",
+            &mut webrtc.session_resume_secs,
+        );
+
+        env.set_with("BIRDBOX_CORS_ORIGINS", &mut self.http.cors_origins, |s| {
+            Ok::<_, std::convert::Infallible>(split_list(s))
+        });
+
+        env.set_opt("BIRDBOX_AUTH_USER_HEADER", &mut self.auth.user_header);
+        env.set_with(
+            "BIRDBOX_AUTH_TRUSTED_PROXIES",
+            &mut self.auth.trusted_proxies,
+            host_addrs::parse_list,
+        );
+        env.set_with("BIRDBOX_AUTH_USERS", &mut self.auth.users, |s| {
+            Ok::<_, std::convert::Infallible>(split_list(s))
+        });
+
+        env.set_with("BIRDBOX_ICE_SERVERS", &mut self.ice.servers, |s| {
+            Ok::<_, std::convert::Infallible>(split_list(s))
+        });
+        env.set_opt("BIRDBOX_TURN_USERNAME", &mut self.ice.username);
+        env.set_opt("BIRDBOX_TURN_CREDENTIAL", &mut self.ice.credential);
+
+        let turn = &mut self.turn;
+        env.set_opt("BIRDBOX_TURN_PORT", &mut turn.port);
+        env.set_opt("BIRDBOX_TURN_HOST", &mut turn.host);
+        env.set_with("BIRDBOX_TURN_RELAY_IP", &mut turn.relay_ip, |s| {
+            parse_ip(s).map(Some)
+        });
+        env.set_opt("BIRDBOX_TURN_SECRET", &mut turn.secret);
+        env.set(
+            "BIRDBOX_TURN_CREDENTIAL_TTL_SECS",
+            &mut turn.credential_ttl_secs,
+        );
+        env.set_opt("BIRDBOX_TURN_TLS_PORT", &mut turn.tls_port);
+        env.set_opt("BIRDBOX_TURN_TLS_CERT", &mut turn.tls_cert);
+        env.set_opt("BIRDBOX_TURN_TLS_KEY", &mut turn.tls_key);
+        env.set_opt("BIRDBOX_TURN_TLS_URL", &mut turn.tls_url);
+
+        env.set_opt("BIRDBOX_RTSP_SERVER_PORT", &mut self.rtsp_server.port);
+        env.set_opt("BIRDBOX_RTSP_SERVER_USER", &mut self.rtsp_server.user);
+        env.set_opt(
+            "BIRDBOX_RTSP_SERVER_PASSWORD",
+            &mut self.rtsp_server.password,
+        );
+
+        env.set("BIRDBOX_HLS_SEGMENT_SECS", &mut self.hls.segment_secs);
+        env.set("BIRDBOX_HLS_PART_MS", &mut self.hls.part_ms);
+
+        env.set("BIRDBOX_MJPEG_MAX_FPS", &mut self.mjpeg.max_fps);
+
+        env.set(
+            "BIRDBOX_READY_DOORBIRD_TIMEOUT_SECS",
+            &mut self.health.doorbird_timeout_secs,
+        );
+
+        env.set(
+            "BIRDBOX_SHUTDOWN_TIMEOUT_SECS",
+            &mut self.shutdown.timeout_secs,
+        );
+
+        env.set_opt("BIRDBOX_ANNOUNCE_DIR", &mut self.announce.dir);
+        env.set_opt("BIRDBOX_RECORDING_DIR", &mut self.recording.dir);
+
+        env.set_with("BIRDBOX_AWAY", &mut self.automation.away, parse_bool);
+        env.set_with(
+            "BIRDBOX_AUTOMATION_DRY_RUN",
+            &mut self.automation.dry_run,
+            parse_bool,
+        );
+
+        if !env.errors.is_empty() {
+            bail!(
+                "Invalid environment variable(s):\n  - {}",
+                env.errors.join("\n  - ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Check values that parse but can't work
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_string());
+            }
+        };
+
+        check(
+            !self.doorbird.url.is_empty(),
+            "doorbird.url (BIRDBOX_DOORBIRD_URL) must be set",
+        );
+        check(
+            self.doorbird.url.is_empty()
+                || self.doorbird.url.starts_with("http://")
+                || self.doorbird.url.starts_with("https://"),
+            "doorbird.url (BIRDBOX_DOORBIRD_URL) must start with http:// or https://",
+        );
+        check(
+            !self.doorbird.user.is_empty(),
+            "doorbird.user (BIRDBOX_DOORBIRD_USER) must be set",
+        );
+        check(
+            !self.doorbird.password.is_empty(),
+            "doorbird.password (BIRDBOX_DOORBIRD_PASSWORD) must be set",
+        );
+
+        check(
+            self.audio.fanout_buffer_samples > 0,
+            "audio.fanout_buffer_samples (BIRDBOX_AUDIO_FANOUT_BUFFER_SAMPLES) must be at least 1",
+        );
+        check(
+            self.video.fanout_buffer_frames > 0,
+            "video.fanout_buffer_frames (BIRDBOX_VIDEO_FANOUT_BUFFER_FRAMES) must be at least 1",
+        );
+
+        let webrtc = &self.webrtc;
+        check(
+            webrtc.udp_port != 0,
+            "webrtc.udp_port (BIRDBOX_UDP_PORT) must not be 0",
+        );
+        check(
+            (1..=crate::webrtc::MAX_NACK_BUFFER_PACKETS).contains(&webrtc.nack_buffer_packets),
+            &format!(
+                "webrtc.nack_buffer_packets (BIRDBOX_NACK_BUFFER_PACKETS) must be between 1 and {}",
+                crate::webrtc::MAX_NACK_BUFFER_PACKETS
+            ),
+        );
+        check(
+            webrtc.bwe_video_min_kbps <= webrtc.bwe_high_min_kbps,
+            "webrtc.bwe_video_min_kbps (BIRDBOX_BWE_VIDEO_MIN_KBPS) must not exceed \
+             webrtc.bwe_high_min_kbps (BIRDBOX_BWE_HIGH_MIN_KBPS)",
+        );
+
+        for origin in &self.http.cors_origins {
+            check(
+                (origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/')
+                    && axum::http::HeaderValue::from_str(origin).is_ok(),
+                &format!(
+                    "http.cors_origins (BIRDBOX_CORS_ORIGINS): '{}' is not an origin like \
+                     https://player.example.com",
+                    origin
+                ),
+            );
+        }
+
+        let auth = &self.auth;
+        if let Some(header) = &auth.user_header {
+            check(
+                axum::http::HeaderName::from_bytes(header.as_bytes()).is_ok(),
+                &format!(
+                    "auth.user_header (BIRDBOX_AUTH_USER_HEADER): '{}' is not a header name",
+                    header
+                ),
+            );
+            check(
+                !auth.trusted_proxies.is_empty(),
+                "auth.trusted_proxies (BIRDBOX_AUTH_TRUSTED_PROXIES) must be set with \
+                 auth.user_header (BIRDBOX_AUTH_USER_HEADER)",
+            );
+        }
+        check(
+            auth.users.is_empty() || auth.user_header.is_some(),
+            "auth.users (BIRDBOX_AUTH_USERS) needs auth.user_header (BIRDBOX_AUTH_USER_HEADER)",
+        );
+
+        for url in &self.ice.servers {
+            check(
+                ice_servers::is_ice_url(url),
+                &format!(
+                    "ice.servers (BIRDBOX_ICE_SERVERS): '{}' is not a stun:, stuns:, turn: or turns: URL",
+                    url
+                ),
+            );
+        }
+
+        if let Some(port) = self.turn.port {
+            check(port != 0, "turn.port (BIRDBOX_TURN_PORT) must not be 0");
+            check(
+                self.turn.host.is_some() || !webrtc.host_ip.is_empty(),
+                "turn.host (BIRDBOX_TURN_HOST) or webrtc.host_ip (BIRDBOX_HOST_IP) must be set \
+                 for the TURN server",
+            );
+            check(
+                self.turn.relay_ip.is_some()
+                    || !webrtc.host_ip_lan.is_empty()
+                    || !webrtc.host_ip.is_empty(),
+                "turn.relay_ip (BIRDBOX_TURN_RELAY_IP) or webrtc.host_ip (BIRDBOX_HOST_IP) must be \
+                 set for the TURN server",
+            );
+            check(
+                self.turn.credential_ttl_secs > 0,
+                "turn.credential_ttl_secs (BIRDBOX_TURN_CREDENTIAL_TTL_SECS) must be at least 1",
+            );
+            check(
+                auth.user_header.is_some(),
+                "auth.user_header (BIRDBOX_AUTH_USER_HEADER) must be set for the TURN server, \
+                 it only hands credentials to authenticated users",
+            );
+            if let Some(tls_port) = self.turn.tls_port {
+                check(
+                    tls_port != 0 && tls_port != port,
+                    "turn.tls_port (BIRDBOX_TURN_TLS_PORT) must not be 0 or turn.port",
+                );
+                check(
+                    self.turn.tls_cert.is_some() && self.turn.tls_key.is_some(),
+                    "turn.tls_cert (BIRDBOX_TURN_TLS_CERT) and turn.tls_key \
+                     (BIRDBOX_TURN_TLS_KEY) must be set with turn.tls_port",
+                );
+            }
+        }
+
+        if let Some(port) = self.rtsp_server.port {
+            check(
+                port != 0,
+                "rtsp_server.port (BIRDBOX_RTSP_SERVER_PORT) must not be 0",
+            );
+        }
+        check(
+            self.rtsp_server.user.is_some() == self.rtsp_server.password.is_some(),
+            "rtsp_server.user (BIRDBOX_RTSP_SERVER_USER) and rtsp_server.password \
+             (BIRDBOX_RTSP_SERVER_PASSWORD) must be set together",
+        );
+
+        check(
+            self.hls.segment_secs > 0,
+            "hls.segment_secs (BIRDBOX_HLS_SEGMENT_SECS) must be at least 1",
+        );
+        check(
+            (100..self.hls.segment_secs.saturating_mul(1000)).contains(&self.hls.part_ms),
+            "hls.part_ms (BIRDBOX_HLS_PART_MS) must be at least 100 and shorter than \
+             hls.segment_secs",
+        );
+        check(
+            self.mjpeg.max_fps > 0,
+            "mjpeg.max_fps (BIRDBOX_MJPEG_MAX_FPS) must be at least 1",
+        );
+        check(
+            self.health.doorbird_timeout_secs > 0,
+            "health.doorbird_timeout_secs (BIRDBOX_READY_DOORBIRD_TIMEOUT_SECS) must be at least 1",
+        );
+        check(
+            self.shutdown.timeout_secs > 0,
+            "shutdown.timeout_secs (BIRDBOX_SHUTDOWN_TIMEOUT_SECS) must be at least 1",
+        );
+        if let Some(dir) = &self.announce.dir {
+            check(
+                dir.is_dir(),
+                &format!(
+                    "announce.dir (BIRDBOX_ANNOUNCE_DIR): {} is not a directory",
+                    dir.display()
+                ),
+            );
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for rule in &self.automation.rules {
+            check(
+                !rule.name.is_empty(),
+                "automation.rules: every rule needs a name",
+            );
+            check(
+                names.insert(rule.name.as_str()),
+                &format!("automation.rules: duplicate rule name '{}'", rule.name),
+            );
+            check(
+                !rule.actions.is_empty(),
+                &format!("automation.rules: rule '{}' has no actions", rule.name),
+            );
+            for action in &rule.actions {
+                match action {
+                    Action::Announce { .. } => check(
+                        self.announce.dir.is_some(),
+                        &format!(
+                            "automation.rules: rule '{}' announces, but announce.dir \
+                             (BIRDBOX_ANNOUNCE_DIR) is not set",
+                            rule.name
+                        ),
+                    ),
+                    Action::Webhook { url } => check(
+                        url.starts_with("http://") || url.starts_with("https://"),
+                        &format!(
+                            "automation.rules: rule '{}' webhook must start with http:// or https://",
+                            rule.name
+                        ),
+                    ),
+                    Action::Record { seconds } => {
+                        check(
+                            self.recording.dir.is_some(),
+                            &format!(
+                                "automation.rules: rule '{}' records, but recording.dir \
+                                 (BIRDBOX_RECORDING_DIR) is not set",
+                                rule.name
+                            ),
+                        );
+                        check(
+                            *seconds > 0,
+                            &format!(
+                                "automation.rules: rule '{}' must record for at least 1 second",
+                                rule.name
+                            ),
+                        );
+                    }
+                    Action::Relay { .. } | Action::Light => {}
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// Copy with passwords and secrets replaced, for display
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if !config.doorbird.password.is_empty() {
+            config.doorbird.password = REDACTED.to_string();
+        }
+        for secret in [
+            &mut config.ice.credential,
+            &mut config.turn.secret,
+            &mut config.rtsp_server.password,
+        ] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        for rule in &mut config.automation.rules {
+            for action in &mut rule.actions {
+                if let Action::Webhook { url } = action {
+                    *url = format!("{}/{}", automation::webhook_origin(url), REDACTED);
+                }
+            }
+        }
+        config
+    }
+}
+
+/// Applies environment overrides, collecting errors so they are reported together
+struct Env<F> {
+    var: F,
+    errors: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn set_with<T, E: Display>(
+        &mut self,
+        name: &str,
+        target: &mut T,
+        parse: impl Fn(&str) -> Result<T, E>,
+    ) {
+        let Some(value) = (self.var)(name) else {
+            return;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+        match parse(value) {
+            Ok(parsed) => *target = parsed,
+            Err(e) => self.errors.push(format!("{}='{}': {:#}", name, value, e)),
+        }
+    }
+
+    fn set<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: Display,
+    {
+        self.set_with(name, target, |s| s.parse());
+    }
+
+    fn set_opt<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: Display,
+    {
+        self.set_with(name, target, |s| s.parse().map(Some));
+    }
+}
+
+/// Parse an on/off value
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err("expected true/false, 1/0, yes/no or on/off".to_string()),
+    }
+}
+
+/// Parse an IP address (IPv6 may be bracketed)
+fn parse_ip(value: &str) -> Result<IpAddr> {
+    value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .context("not an IP address")
+}
+
+/// Split a comma-separated list, dropping empty entries
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn with_env(config: &mut Config, vars: &[(&str, &str)]) -> Result<()> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        config.apply_env(|name| vars.get(name).cloned())
+    }
+
+    fn minimal() -> Config {
+        toml::from_str(
+            r#"
+            [doorbird]
+            url = "http://192.0.2.5"
+            user = "ghxxxx0001"
+            password = "hunter2"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_file_then_env_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [doorbird]
+            url = "http://192.0.2.5"
+            user = "ghxxxx0001"
+            password = "from-file"
+
+            [video]
+            rtsp_transport = "tcp"
+
+            [webrtc]
+            udp_port = 40000
+            host_ip = ["192.0.2.10"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.video.fanout_buffer_frames, 4);
+
+        with_env(
+            &mut config,
+            &[
+                ("BIRDBOX_DOORBIRD_PASSWORD", "from-env"),
+                ("BIRDBOX_HOST_IP", "192.0.2.20, [2001:db8::1]"),
+                ("BIRDBOX_VIDEO_SIMULCAST", "off"),
+                (
+                    "BIRDBOX_ICE_SERVERS",
+                    "stun:stun.example.com, ,turn:turn.example.com",
+                ),
+                ("BIRDBOX_RTSP_SERVER_PORT", ""),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.doorbird.password, "from-env");
+        assert_eq!(config.webrtc.udp_port, 40000);
+        assert_eq!(config.video.rtsp_transport, RtspTransport::Tcp);
+        assert_eq!(
+            config.webrtc.host_ip,
+            vec![
+                "192.0.2.20".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ]
+        );
+        assert!(!config.video.simulcast);
+        assert_eq!(config.ice.servers.len(), 2);
+        assert_eq!(config.rtsp_server.port, None);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_env_values_are_errors() {
+        let mut config = minimal();
+        let err = with_env(
+            &mut config,
+            &[
+                ("BIRDBOX_RTSP_TRANSPORT_PROTOCOL", "quic"),
+                ("BIRDBOX_VIDEO_FANOUT_BUFFER_FRAMES", "lots"),
+                ("BIRDBOX_ADAPTIVE_VIDEO", "maybe"),
+            ],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("BIRDBOX_RTSP_TRANSPORT_PROTOCOL='quic'"));
+        assert!(err.contains("BIRDBOX_VIDEO_FANOUT_BUFFER_FRAMES='lots'"));
+        assert!(err.contains("BIRDBOX_ADAPTIVE_VIDEO='maybe'"));
+    }
+
+    #[test]
+    fn test_unknown_keys_are_errors() {
+        assert!(toml::from_str::<Config>("[video]\nfanout_bufer_frames = 8\n").is_err());
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let mut config = Config::default();
+        config.video.fanout_buffer_frames = 0;
+        config.turn.port = Some(3478);
+        config.turn.tls_port = Some(3478);
+        config.rtsp_server.user = Some("nvr".into());
+        config.http.cors_origins = vec!["*".into()];
+        config.hls.part_ms = 2000;
+        let err = config.validate().unwrap_err().to_string();
+        for key in [
+            "doorbird.url",
+            "doorbird.user",
+            "doorbird.password",
+            "video.fanout_buffer_frames",
+            "turn.host",
+            "auth.user_header",
+            "turn.tls_port",
+            "turn.tls_cert",
+            "http.cors_origins",
+            "rtsp_server.user",
+            "hls.part_ms",
+        ] {
+            assert!(err.contains(key), "missing {} in {}", key, err);
+        }
+        minimal().validate().unwrap();
+    }
+
+    #[test]
+    fn test_automation_rules() {
+        let mut config = minimal();
+        config.automation = toml::from_str(
+            r#"
+            away = true
+
+            [[rules]]
+            name = "parcel"
+            event = "doorbell"
+            actions = [{ type = "announce", clip = "parcel" }, { type = "record" }]
+
+            [[rules]]
+            name = "parcel"
+            event = "motion"
+            actions = [{ type = "webhook", url = "ftp://example.com" }]
+            "#,
+        )
+        .unwrap();
+        assert!(config.automation.away);
+
+        let err = config.validate().unwrap_err().to_string();
+        for problem in [
+            "duplicate rule name 'parcel'",
+            "announce.dir",
+            "recording.dir",
+            "webhook must start with http://",
+        ] {
+            assert!(err.contains(problem), "missing {} in {}", problem, err);
+        }
+    }
+
+    #[test]
+    fn test_redacted() {
+        let mut config = minimal();
+        config.turn.secret = Some("turn-secret".into());
+        config.automation = toml::from_str(
+            r#"
+            [[rules]]
+            name = "ring"
+            event = "doorbell"
+            actions = [{ type = "webhook", url = "https://hooks.example.com/T0/hook-token" }]
+            "#,
+        )
+        .unwrap();
+        let text = toml::to_string(&config.redacted()).unwrap();
+        assert!(!text.contains("hunter2"));
+        assert!(!text.contains("turn-secret"));
+        assert!(!text.contains("hook-token"));
+        assert!(text.contains("https://hooks.example.com/<redacted>"));
+        assert!(text.contains(REDACTED));
+        assert!(text.contains("ghxxxx0001"));
+    }
+}
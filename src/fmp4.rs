@@ -0,0 +1,468 @@
+//! Fragmented MP4 boxes for LL-HLS
+//!
+//! Just enough of ISO/IEC 14496-12 to package the H.264 video and Opus audio
+//! passthrough: an init segment (`ftyp` + `moov` describing both tracks) and
+//! fragments (`moof` + `mdat`) holding a few samples of each track. Every LL-HLS
+//! partial segment is one fragment, and a full segment is its parts concatenated.
+//!
+//! Video samples are length-prefixed (AVCC) access units; the parameter sets
+//! live in the `avcC` box of the init segment. Audio samples are raw Opus
+//! packets described by a `dOps` box (Opus in ISOBMFF).
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+pub const VIDEO_TRACK_ID: u32 = 1;
+pub const AUDIO_TRACK_ID: u32 = 2;
+
+/// Track timescales (same clocks as the RTP streams)
+pub const VIDEO_TIMESCALE: u32 = 90_000;
+pub const AUDIO_TIMESCALE: u32 = 48_000;
+
+/// Sample flags of a sync sample (depends on no other sample)
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// Sample flags of a non-sync sample (depends on others, sample_is_non_sync_sample)
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// `tfhd` flag: sample data offsets are relative to the start of the `moof`
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+/// `trun` flags: data offset plus per-sample duration, size and flags
+const TRUN_FLAGS: u32 = 0x0001 | 0x0100 | 0x0200 | 0x0400;
+
+/// Identity transformation matrix of `mvhd`/`tkhd`
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// ISO-639-2 "und", packed as three 5-bit letters
+const LANGUAGE_UND: u16 = 0x55c4;
+
+/// One video access unit or audio frame
+pub struct Sample {
+    pub data: Bytes,
+    /// Duration in the track's timescale
+    pub duration: u32,
+    /// Sync sample (video keyframe; every Opus frame)
+    pub key: bool,
+}
+
+/// Consecutive samples of one track in a fragment
+pub struct Run<'a> {
+    /// Decode time of the first sample in the track's timescale
+    pub decode_time: u64,
+    pub samples: &'a [Sample],
+}
+
+/// Build the init segment for the H.264 and Opus tracks
+///
+/// # Arguments
+/// * `sps`, `pps` - H.264 parameter sets (NAL units without start codes)
+/// * `width`, `height` - Picture size (0 if unknown; players read the SPS)
+/// * `pre_skip` - Opus decoder pre-skip in 48kHz samples
+pub fn init_segment(sps: &[u8], pps: &[u8], width: u16, height: u16, pre_skip: u16) -> Bytes {
+    let mut out = BytesMut::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.put_slice(b"iso6"); // major brand
+        out.put_u32(0); // minor version
+        for brand in [b"iso6", b"cmfc", b"mp41"] {
+            out.put_slice(brand);
+        }
+    });
+    write_box(&mut out, b"moov", |out| {
+        write_full_box(out, b"mvhd", 0, 0, |out| {
+            out.put_u32(0); // creation time
+            out.put_u32(0); // modification time
+            out.put_u32(1000); // timescale
+            out.put_u32(0); // duration (fragmented)
+            out.put_u32(0x0001_0000); // rate 1.0
+            out.put_u16(0x0100); // volume 1.0
+            out.put_bytes(0, 10); // reserved
+            put_matrix(out);
+            out.put_bytes(0, 24); // pre_defined
+            out.put_u32(AUDIO_TRACK_ID + 1); // next track ID
+        });
+        write_track(
+            out,
+            VIDEO_TRACK_ID,
+            VIDEO_TIMESCALE,
+            width,
+            height,
+            |out| {
+                write_full_box(out, b"vmhd", 0, 1, |out| {
+                    out.put_u16(0); // graphics mode
+                    out.put_bytes(0, 6); // opcolor
+                });
+            },
+            |out| write_avc1(out, sps, pps, width, height),
+        );
+        write_track(
+            out,
+            AUDIO_TRACK_ID,
+            AUDIO_TIMESCALE,
+            0,
+            0,
+            |out| {
+                write_full_box(out, b"smhd", 0, 0, |out| {
+                    out.put_u16(0); // balance
+                    out.put_u16(0); // reserved
+                });
+            },
+            |out| write_opus(out, pre_skip),
+        );
+        write_box(out, b"mvex", |out| {
+            for track_id in [VIDEO_TRACK_ID, AUDIO_TRACK_ID] {
+                write_full_box(out, b"trex", 0, 0, |out| {
+                    out.put_u32(track_id);
+                    out.put_u32(1); // default sample description index
+                    out.put_u32(0); // default sample duration
+                    out.put_u32(0); // default sample size
+                    out.put_u32(0); // default sample flags
+                });
+            }
+        });
+    });
+    out.freeze()
+}
+
+/// Build a fragment (`moof` + `mdat`) with the given video and audio samples
+///
+/// `sequence` numbers the fragments from 1 in the order they are written.
+pub fn fragment(sequence: u32, video: Run<'_>, audio: Run<'_>) -> Bytes {
+    let runs = [(VIDEO_TRACK_ID, video), (AUDIO_TRACK_ID, audio)];
+    let mut out = BytesMut::new();
+    // Positions of the trun data offsets, patched once the moof size is known
+    let mut data_offsets = Vec::new();
+
+    write_box(&mut out, b"moof", |out| {
+        write_full_box(out, b"mfhd", 0, 0, |out| out.put_u32(sequence));
+        for (track_id, run) in runs.iter().filter(|(_, run)| !run.samples.is_empty()) {
+            write_box(out, b"traf", |out| {
+                write_full_box(out, b"tfhd", 0, TFHD_DEFAULT_BASE_IS_MOOF, |out| {
+                    out.put_u32(*track_id);
+                });
+                write_full_box(out, b"tfdt", 1, 0, |out| out.put_u64(run.decode_time));
+                write_full_box(out, b"trun", 0, TRUN_FLAGS, |out| {
+                    out.put_u32(run.samples.len() as u32);
+                    data_offsets.push(out.len());
+                    out.put_u32(0);
+                    for sample in run.samples {
+                        out.put_u32(sample.duration);
+                        out.put_u32(sample.data.len() as u32);
+                        out.put_u32(if sample.key {
+                            SYNC_SAMPLE_FLAGS
+                        } else {
+                            NON_SYNC_SAMPLE_FLAGS
+                        });
+                    }
+                });
+            });
+        }
+    });
+
+    // Sample data follows the 8-byte mdat header, video first
+    let mut offset = out.len() + 8;
+    let samples = runs.iter().filter(|(_, run)| !run.samples.is_empty());
+    for (position, (_, run)) in data_offsets.into_iter().zip(samples) {
+        out[position..position + 4].copy_from_slice(&(offset as u32).to_be_bytes());
+        offset += run.samples.iter().map(|s| s.data.len()).sum::<usize>();
+    }
+
+    write_box(&mut out, b"mdat", |out| {
+        for (_, run) in &runs {
+            for sample in run.samples {
+                out.put_slice(&sample.data);
+            }
+        }
+    });
+    out.freeze()
+}
+
+/// Write a box: size and type, then whatever `body` writes
+fn write_box(out: &mut BytesMut, kind: &[u8; 4], body: impl FnOnce(&mut BytesMut)) {
+    let start = out.len();
+    out.put_u32(0);
+    out.put_slice(kind);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Write a full box (box with version and flags)
+fn write_full_box(
+    out: &mut BytesMut,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut BytesMut),
+) {
+    write_box(out, kind, |out| {
+        out.put_u32((version as u32) << 24 | flags);
+        body(out);
+    });
+}
+
+fn put_matrix(out: &mut BytesMut) {
+    for value in UNITY_MATRIX {
+        out.put_u32(value);
+    }
+}
+
+/// Write a `trak` with an empty sample table (samples come in fragments)
+fn write_track(
+    out: &mut BytesMut,
+    track_id: u32,
+    timescale: u32,
+    width: u16,
+    height: u16,
+    media_header: impl FnOnce(&mut BytesMut),
+    sample_entry: impl FnOnce(&mut BytesMut),
+) {
+    let audio = track_id == AUDIO_TRACK_ID;
+    write_box(out, b"trak", |out| {
+        // Flags: track enabled and in movie
+        write_full_box(out, b"tkhd", 0, 0x3, |out| {
+            out.put_u32(0); // creation time
+            out.put_u32(0); // modification time
+            out.put_u32(track_id);
+            out.put_u32(0); // reserved
+            out.put_u32(0); // duration
+            out.put_bytes(0, 8); // reserved
+            out.put_u16(0); // layer
+            out.put_u16(0); // alternate group
+            out.put_u16(if audio { 0x0100 } else { 0 }); // volume
+            out.put_u16(0); // reserved
+            put_matrix(out);
+            out.put_u32((width as u32) << 16);
+            out.put_u32((height as u32) << 16);
+        });
+        write_box(out, b"mdia", |out| {
+            write_full_box(out, b"mdhd", 0, 0, |out| {
+                out.put_u32(0); // creation time
+                out.put_u32(0); // modification time
+                out.put_u32(timescale);
+                out.put_u32(0); // duration
+                out.put_u16(LANGUAGE_UND);
+                out.put_u16(0); // pre_defined
+            });
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                out.put_u32(0); // pre_defined
+                out.put_slice(if audio { b"soun" } else { b"vide" });
+                out.put_bytes(0, 12); // reserved
+                out.put_slice(if audio {
+                    b"SoundHandler\0"
+                } else {
+                    b"VideoHandler\0"
+                });
+            });
+            write_box(out, b"minf", |out| {
+                media_header(out);
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        out.put_u32(1); // entry count
+                                        // Flags: media data is in this file
+                        write_full_box(out, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_box(out, b"stbl", |out| {
+                    write_full_box(out, b"stsd", 0, 0, |out| {
+                        out.put_u32(1); // entry count
+                        sample_entry(out);
+                    });
+                    write_full_box(out, b"stts", 0, 0, |out| out.put_u32(0));
+                    write_full_box(out, b"stsc", 0, 0, |out| out.put_u32(0));
+                    write_full_box(out, b"stsz", 0, 0, |out| {
+                        out.put_u32(0); // sample size
+                        out.put_u32(0); // sample count
+                    });
+                    write_full_box(out, b"stco", 0, 0, |out| out.put_u32(0));
+                });
+            });
+        });
+    });
+}
+
+/// H.264 sample entry with its decoder configuration (ISO/IEC 14496-15 §5.3.3)
+fn write_avc1(out: &mut BytesMut, sps: &[u8], pps: &[u8], width: u16, height: u16) {
+    write_box(out, b"avc1", |out| {
+        out.put_bytes(0, 6); // reserved
+        out.put_u16(1); // data reference index
+        out.put_bytes(0, 16); // pre_defined and reserved
+        out.put_u16(width);
+        out.put_u16(height);
+        out.put_u32(0x0048_0000); // 72 dpi horizontal
+        out.put_u32(0x0048_0000); // 72 dpi vertical
+        out.put_u32(0); // reserved
+        out.put_u16(1); // frame count
+        out.put_bytes(0, 32); // compressor name
+        out.put_u16(0x0018); // depth
+        out.put_i16(-1); // pre_defined
+        write_box(out, b"avcC", |out| {
+            let profile = sps.get(1).copied().unwrap_or(0);
+            out.put_u8(1); // configuration version
+            out.put_u8(profile);
+            out.put_u8(sps.get(2).copied().unwrap_or(0)); // profile compatibility
+            out.put_u8(sps.get(3).copied().unwrap_or(0)); // level
+            out.put_u8(0xfc | 3); // 4-byte NAL unit lengths
+            out.put_u8(0xe0 | 1); // one SPS
+            out.put_u16(sps.len() as u16);
+            out.put_slice(sps);
+            out.put_u8(1); // one PPS
+            out.put_u16(pps.len() as u16);
+            out.put_slice(pps);
+            if matches!(profile, 100 | 110 | 122 | 144) {
+                // High profiles: 4:2:0, 8 bit, as IP cameras send
+                out.put_u8(0xfc | 1);
+                out.put_u8(0xf8);
+                out.put_u8(0xf8);
+                out.put_u8(0); // no SPS extensions
+            }
+        });
+    });
+}
+
+/// Mono Opus sample entry (Opus in ISOBMFF §4.3)
+fn write_opus(out: &mut BytesMut, pre_skip: u16) {
+    write_box(out, b"Opus", |out| {
+        out.put_bytes(0, 6); // reserved
+        out.put_u16(1); // data reference index
+        out.put_bytes(0, 8); // reserved
+        out.put_u16(1); // channel count
+        out.put_u16(16); // sample size
+        out.put_u32(0); // pre_defined and reserved
+        out.put_u32(AUDIO_TIMESCALE << 16);
+        write_box(out, b"dOps", |out| {
+            out.put_u8(0); // version
+            out.put_u8(1); // output channel count
+            out.put_u16(pre_skip);
+            out.put_u32(AUDIO_TIMESCALE); // input sample rate
+            out.put_i16(0); // output gain
+            out.put_u8(0); // channel mapping family (mono/stereo)
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a buffer into (type, body) of its top-level boxes
+    fn boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut out = Vec::new();
+        let mut rest = data;
+        while rest.len() >= 8 {
+            let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            assert!(size >= 8 && size <= rest.len(), "bad box size {}", size);
+            out.push((&rest[4..8], &rest[8..size]));
+            rest = &rest[size..];
+        }
+        assert!(rest.is_empty());
+        out
+    }
+
+    fn child<'a>(data: &'a [u8], kind: &[u8]) -> &'a [u8] {
+        boxes(data)
+            .into_iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, body)| body)
+            .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(kind)))
+    }
+
+    fn sample(data: &[u8], duration: u32, key: bool) -> Sample {
+        Sample {
+            data: Bytes::copy_from_slice(data),
+            duration,
+            key,
+        }
+    }
+
+    #[test]
+    fn test_init_segment_layout() {
+        let sps = [0x67, 0x64, 0x00, 0x1f, 0xac];
+        let pps = [0x68, 0xee, 0x3c, 0x80];
+        let init = init_segment(&sps, &pps, 1280, 720, 312);
+
+        let kinds: Vec<&[u8]> = boxes(&init).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, vec![&b"ftyp"[..], b"moov"]);
+        let moov = child(&init, b"moov");
+        let kinds: Vec<&[u8]> = boxes(moov).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, vec![&b"mvhd"[..], b"trak", b"trak", b"mvex"]);
+
+        let video = boxes(moov)[1].1;
+        let stsd = child(
+            child(child(child(video, b"mdia"), b"minf"), b"stbl"),
+            b"stsd",
+        );
+        // Full box header and entry count precede the sample entry
+        let avc1 = child(&stsd[8..], b"avc1");
+        assert_eq!(&avc1[24..28], &[0x05, 0x00, 0x02, 0xd0]); // 1280x720
+        let avcc = child(&avc1[78..], b"avcC");
+        assert_eq!(&avcc[1..4], &sps[1..4]);
+        assert_eq!(&avcc[8..8 + sps.len()], &sps);
+        assert_eq!(&avcc[11 + sps.len()..11 + sps.len() + pps.len()], &pps);
+
+        let audio = boxes(moov)[2].1;
+        let stsd = child(
+            child(child(child(audio, b"mdia"), b"minf"), b"stbl"),
+            b"stsd",
+        );
+        let dops = child(&child(&stsd[8..], b"Opus")[28..], b"dOps");
+        assert_eq!(u16::from_be_bytes([dops[2], dops[3]]), 312);
+    }
+
+    #[test]
+    fn test_fragment_data_offsets_point_at_samples() {
+        let video = [sample(b"key", 3000, true), sample(b"delta", 3000, false)];
+        let audio = [sample(b"opus1", 960, true), sample(b"opus2", 960, true)];
+        let fragment = fragment(
+            7,
+            Run {
+                decode_time: 90_000,
+                samples: &video,
+            },
+            Run {
+                decode_time: 48_000,
+                samples: &audio,
+            },
+        );
+
+        let moof = child(&fragment, b"moof");
+        assert_eq!(&child(moof, b"mfhd")[4..], &7u32.to_be_bytes());
+        let trafs: Vec<&[u8]> = boxes(moof)
+            .into_iter()
+            .filter(|(k, _)| *k == b"traf")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(trafs.len(), 2);
+
+        for (traf, samples) in trafs.into_iter().zip([&video, &audio]) {
+            let tfdt = child(traf, b"tfdt");
+            assert_eq!(tfdt[0], 1);
+            let trun = child(traf, b"trun");
+            let count = u32::from_be_bytes(trun[4..8].try_into().unwrap()) as usize;
+            assert_eq!(count, samples.len());
+            let mut offset = u32::from_be_bytes(trun[8..12].try_into().unwrap()) as usize;
+            for (i, sample) in samples.iter().enumerate() {
+                let entry = &trun[12 + i * 12..24 + i * 12];
+                let size = u32::from_be_bytes(entry[4..8].try_into().unwrap()) as usize;
+                assert_eq!(&fragment[offset..offset + size], &sample.data[..]);
+                offset += size;
+            }
+        }
+    }
+
+    #[test]
+    fn test_fragment_without_audio() {
+        let video = [sample(b"key", 3000, true)];
+        let fragment = fragment(
+            1,
+            Run {
+                decode_time: 0,
+                samples: &video,
+            },
+            Run {
+                decode_time: 0,
+                samples: &[],
+            },
+        );
+        let moof = child(&fragment, b"moof");
+        assert_eq!(boxes(moof).iter().filter(|(k, _)| *k == b"traf").count(), 1);
+        assert_eq!(child(&fragment, b"mdat"), b"key");
+    }
+}
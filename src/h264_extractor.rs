@@ -19,14 +19,20 @@ pub struct H264Packet {
     pub data: Bytes,
     /// Packet timestamp from RTSP stream
     ///
-    /// Note: Available for reference, but WebRTC uses fixed sample duration (~83ms)
-    /// instead of accumulated timestamps to prevent drift.
-    #[allow(unused)]
+    /// The RTSP server derives RTP timestamps from it. WebRTC uses a fixed sample
+    /// duration (~83ms) instead of accumulated timestamps to prevent drift.
     pub timestamp: Duration,
     /// Whether this is a keyframe (I-frame)
     pub is_keyframe: bool,
 }
 
+/// Properties of the H.264 stream, known once connected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VideoStreamInfo {
+    pub width: u32,
+    pub height: u32,
+}
+
 /// H.264 packet extractor from RTSP stream
 pub struct H264Extractor {
     rtsp_url: String,
@@ -34,6 +40,7 @@ pub struct H264Extractor {
     input_context: Option<ffmpeg::format::context::Input>,
     video_stream_index: Option<usize>,
     time_base: Option<ffmpeg::Rational>,
+    stream_info: Option<VideoStreamInfo>,
     is_reconnecting: bool,
     last_reconnect_attempt: Instant,
 }
@@ -54,6 +61,7 @@ impl H264Extractor {
             input_context: None,
             video_stream_index: None,
             time_base: None,
+            stream_info: None,
             is_reconnecting: false,
             last_reconnect_attempt: Instant::now(),
         };
@@ -119,9 +127,25 @@ impl H264Extractor {
             );
         }
 
+        // Resolution is probed by ffmpeg when opening the stream; packagers that
+        // write container headers (HLS) need it
+        let stream_info =
+            ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())
+                .and_then(|ctx| ctx.decoder().video())
+                .map(|video| VideoStreamInfo {
+                    width: video.width(),
+                    height: video.height(),
+                })
+                .ok()
+                .filter(|info| info.width > 0 && info.height > 0);
+        if let Some(info) = stream_info {
+            info!("Video resolution: {}x{}", info.width, info.height);
+        }
+
         self.input_context = Some(input);
         self.video_stream_index = Some(video_stream_index);
         self.time_base = Some(time_base);
+        self.stream_info = stream_info;
         self.is_reconnecting = false;
 
         info!("Successfully connected to RTSP stream");
@@ -141,6 +165,11 @@ impl H264Extractor {
         self.connect()
     }
 
+    /// Returns the stream properties of the current connection, if known
+    pub fn stream_info(&self) -> Option<VideoStreamInfo> {
+        self.stream_info
+    }
+
     /// Returns the next H.264 packet
     ///
     /// On error, attempts reconnection
@@ -0,0 +1,229 @@
+//! Liveness and readiness probes
+//!
+//! - `GET /healthz`: the process is up and serving HTTP (always `200`)
+//! - `GET /readyz`: everything needed to serve viewers works (`200`, or `503` with
+//!   the failing checks): the WebRTC UDP mux reads a probe datagram sent to its
+//!   socket within `UDP_MUX_PROBE_TIMEOUT`, the DoorBird answers an
+//!   `info.cgi` request within `health.doorbird_timeout_secs`, the event monitor
+//!   is connected and ffmpeg is initialised
+//!
+//! The DoorBird probe result is cached for `DOORBIRD_CHECK_INTERVAL`, so frequent
+//! probes don't load the device.
+
+use crate::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// How long a DoorBird probe result is reused
+const DOORBIRD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the WebRTC UDP mux has to read the readiness probe datagram
+const UDP_MUX_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default time the DoorBird has to answer the readiness probe
+pub const DEFAULT_DOORBIRD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of one readiness check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn ok(detail: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Readiness checks
+#[derive(Debug, Clone, Serialize)]
+pub struct Checks {
+    pub udp_mux: Check,
+    pub doorbird: Check,
+    pub event_monitor: Check,
+    pub ffmpeg: Check,
+}
+
+impl Checks {
+    pub fn ready(&self) -> bool {
+        [
+            &self.udp_mux,
+            &self.doorbird,
+            &self.event_monitor,
+            &self.ffmpeg,
+        ]
+        .iter()
+        .all(|check| check.ok)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    /// `ready` or `not_ready`
+    status: &'static str,
+    checks: Checks,
+}
+
+/// Health state updated by the background tasks
+pub struct Health {
+    event_monitor_connected: AtomicBool,
+    /// Result of initialising ffmpeg at startup
+    ffmpeg: Check,
+    /// Seconds; changed by configuration reloads
+    doorbird_timeout_secs: AtomicU64,
+    /// Last DoorBird probe; the lock also makes concurrent probes share one request
+    doorbird: Mutex<Option<(Instant, Check)>>,
+}
+
+impl Health {
+    /// `doorbird_timeout`: how long the DoorBird has to answer a readiness probe
+    pub fn new(doorbird_timeout: Duration, ffmpeg: Result<(), String>) -> Self {
+        info!(
+            "Readiness requires a DoorBird response within {}s",
+            doorbird_timeout.as_secs()
+        );
+        Self {
+            event_monitor_connected: AtomicBool::new(false),
+            ffmpeg: match ffmpeg {
+                Ok(()) => Check::ok("initialised"),
+                Err(e) => Check::failed(e),
+            },
+            doorbird_timeout_secs: AtomicU64::new(doorbird_timeout.as_secs()),
+            doorbird: Mutex::new(None),
+        }
+    }
+
+    pub fn set_doorbird_timeout(&self, timeout: Duration) {
+        self.doorbird_timeout_secs
+            .store(timeout.as_secs(), Ordering::Relaxed);
+    }
+
+    pub fn set_event_monitor_connected(&self, connected: bool) {
+        self.event_monitor_connected
+            .store(connected, Ordering::Relaxed);
+    }
+
+    fn event_monitor(&self) -> Check {
+        if self.event_monitor_connected.load(Ordering::Relaxed) {
+            Check::ok("connected")
+        } else {
+            Check::failed("not connected")
+        }
+    }
+
+    /// Probe the DoorBird, reusing a recent result
+    async fn doorbird(&self, client: &doorbird::Client) -> Check {
+        let mut last = self.doorbird.lock().await;
+        if let Some((at, check)) = last.as_ref() {
+            if at.elapsed() < DOORBIRD_CHECK_INTERVAL {
+                return check.clone();
+            }
+        }
+
+        let timeout = Duration::from_secs(self.doorbird_timeout_secs.load(Ordering::Relaxed));
+        let started = Instant::now();
+        let check = match tokio::time::timeout(timeout, client.info()).await {
+            Ok(Ok(_)) => Check::ok(format!("responded in {} ms", started.elapsed().as_millis())),
+            Ok(Err(e)) => Check::failed(format!("{:#}", e)),
+            Err(_) => Check::failed(format!("no response within {}s", timeout.as_secs())),
+        };
+        if !check.ok {
+            warn!("Readiness: DoorBird unreachable: {}", check.detail);
+        }
+        *last = Some((Instant::now(), check.clone()));
+        check
+    }
+}
+
+/// Probe the WebRTC UDP mux
+async fn udp_mux(infra: &crate::webrtc::WebRtcInfra) -> Check {
+    let addr = infra.udp_addr();
+    match infra.probe_udp_mux(UDP_MUX_PROBE_TIMEOUT).await {
+        Ok(elapsed) => Check::ok(format!(
+            "reading on {} (probe took {} ms)",
+            addr,
+            elapsed.as_millis()
+        )),
+        Err(e) => {
+            warn!("Readiness: WebRTC UDP mux on {} not reading: {:#}", addr, e);
+            Check::failed(format!("{}: {:#}", addr, e))
+        }
+    }
+}
+
+/// `GET /healthz` - liveness
+pub async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "ok",
+        "uptime_secs": state.started.elapsed().as_secs(),
+    }))
+}
+
+/// `GET /readyz` - readiness, `503` while any check fails
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let checks = Checks {
+        udp_mux: udp_mux(&state.webrtc_infra).await,
+        doorbird: state.health.doorbird(&state.doorbird_client).await,
+        event_monitor: state.health.event_monitor(),
+        ffmpeg: state.health.ffmpeg.clone(),
+    };
+    let (code, status) = if checks.ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    (code, Json(Readiness { status, checks }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checks() -> Checks {
+        Checks {
+            udp_mux: Check::ok("reading on [::]:50000 (probe took 0 ms)"),
+            doorbird: Check::ok("responded in 20 ms"),
+            event_monitor: Check::ok("connected"),
+            ffmpeg: Check::ok("initialised"),
+        }
+    }
+
+    #[test]
+    fn test_ready_when_all_checks_pass() {
+        assert!(checks().ready());
+    }
+
+    #[test]
+    fn test_not_ready_when_any_check_fails() {
+        let mut checks = checks();
+        checks.event_monitor = Check::failed("not connected");
+        assert!(!checks.ready());
+    }
+
+    #[test]
+    fn test_event_monitor_check() {
+        let health = Health::new(DEFAULT_DOORBIRD_TIMEOUT, Err("no codecs".to_string()));
+        assert!(!health.event_monitor().ok);
+        health.set_event_monitor_connected(true);
+        assert!(health.event_monitor().ok);
+        assert_eq!(health.ffmpeg, Check::failed("no codecs"));
+    }
+}
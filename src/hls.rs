@@ -0,0 +1,1229 @@
+//! LL-HLS output for viewers that can't use WebRTC
+//!
+//! Smart TVs and networks that block the WebRTC UDP port can still play HLS over
+//! plain HTTP. The packager subscribes to the high video tier and the audio fanout
+//! and packages both without transcoding (H.264 video, Opus audio) as fMP4
+//! (`fmp4.rs`), held in memory and served under `/hls/`:
+//! - `GET /hls/index.m3u8` - media playlist
+//! - `GET /hls/init.mp4` - fMP4 init segment
+//! - `GET /hls/segment_N.m4s` - media segment `N`
+//! - `GET /hls/part_N_M.m4s` - partial segment `M` of segment `N`
+//!
+//! This is Low-Latency HLS: every segment is made of short partial segments
+//! (`hls.part_ms`) that are published as soon as they are complete, and the
+//! playlist supports blocking reloads (`_HLS_msn`/`_HLS_part`), so players hear
+//! about a new part the moment it exists instead of polling. The next part is
+//! announced with a preload hint; a request for it waits until it is complete.
+//! Players without LL-HLS support ignore the parts and play the full segments.
+//!
+//! Packaging starts on the first request and stops once no player has fetched
+//! anything for `HLS_IDLE_TIMEOUT`, so the DoorBird isn't streamed for nobody.
+//!
+//! Segments are cut on keyframes, so the actual segment length is the target
+//! duration rounded up to the camera's GOP length. Parts are cut on any frame.
+//!
+//! `mux` is shared with recordings (`recorder.rs`), which ffmpeg writes to a
+//! single MP4 file.
+
+use crate::audio_fanout::AudioFanout;
+use crate::fmp4;
+use crate::h264_extractor::VideoStreamInfo;
+use crate::video_fanout::VideoFanout;
+use crate::AppState;
+use anyhow::{Context, Result};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::{BufMut, Bytes, BytesMut};
+use ffmpeg_next as ffmpeg;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Stop packaging after this long without any HLS request
+const HLS_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a playlist request waits for the first part when packaging just started
+const PLAYLIST_WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Packets queued between the async forwarder and the blocking MP4 muxer
+const MUXER_QUEUE_PACKETS: usize = 256;
+
+/// Complete segments kept in the playlist
+const PLAYLIST_SEGMENTS: usize = 6;
+
+/// Complete segments (the most recent ones) whose parts are still listed
+const PART_LISTED_SEGMENTS: usize = 2;
+
+/// How far ahead of the playlist a blocking reload may ask (LL-HLS limits)
+const ADVANCE_SEGMENT_LIMIT: u64 = 2;
+const ADVANCE_PART_LIMIT: usize = 3;
+
+/// File names under `/hls/`
+const PLAYLIST_NAME: &str = "index.m3u8";
+const INIT_NAME: &str = "init.mp4";
+
+/// Opus decoder pre-skip in 48kHz samples (standard libopus encoder delay)
+const OPUS_PRE_SKIP: u16 = 312;
+
+/// H.264 NAL unit types carrying parameter sets and access unit delimiters
+const NAL_TYPE_SPS: u8 = 7;
+const NAL_TYPE_PPS: u8 = 8;
+const NAL_TYPE_AUD: u8 = 9;
+
+/// Media handed from the fanouts to a muxer
+enum MuxInput {
+    /// Annex-B H.264 access unit, pts in 1/90000
+    Video { data: Bytes, pts: i64, key: bool },
+    /// Opus frame, pts and duration in 1/48000
+    Audio {
+        data: Bytes,
+        pts: i64,
+        duration: u32,
+    },
+}
+
+/// On-demand LL-HLS packager
+pub struct HlsPackager {
+    segment_secs: u32,
+    part_ms: u32,
+    audio_fanout: Arc<AudioFanout>,
+    video_fanout: Arc<VideoFanout>,
+    running: AtomicBool,
+    last_request: Mutex<Instant>,
+    /// Playlist and media of the running packager
+    live: Mutex<Live>,
+    /// Signalled whenever `live` changes, for blocking requests
+    updates: watch::Sender<()>,
+    /// Cancelled when the server shuts down; packaging stops as if idle
+    shutdown: CancellationToken,
+}
+
+impl HlsPackager {
+    /// Creates a packager (nothing runs until the first request)
+    ///
+    /// # Arguments
+    /// * `segment_secs` - Target segment duration in seconds
+    /// * `part_ms` - Target partial segment duration in milliseconds
+    /// * `audio_fanout` - Source of Opus audio
+    /// * `video_fanout` - Source of H.264 video
+    /// * `shutdown` - Server shutdown token
+    pub fn new(
+        segment_secs: u32,
+        part_ms: u32,
+        audio_fanout: Arc<AudioFanout>,
+        video_fanout: Arc<VideoFanout>,
+        shutdown: CancellationToken,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            segment_secs: segment_secs.max(1),
+            part_ms: part_ms.max(1),
+            audio_fanout,
+            video_fanout,
+            running: AtomicBool::new(false),
+            last_request: Mutex::new(Instant::now()),
+            live: Mutex::new(Live::default()),
+            updates: watch::Sender::new(()),
+            shutdown,
+        })
+    }
+
+    /// Record player activity, starting the packager if it isn't running
+    fn touch(self: &Arc<Self>) {
+        *self.last_request.lock().unwrap() = Instant::now();
+
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        if !self.running.swap(true, Ordering::SeqCst) {
+            let packager = Arc::clone(self);
+            tokio::spawn(async move {
+                if let Err(e) = packager.run().await {
+                    error!("HLS packager error: {:#}", e);
+                }
+                // Players never see media of a previous run
+                packager.publish(|live| *live = Live::default());
+                packager.running.store(false, Ordering::SeqCst);
+            });
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.shutdown.is_cancelled()
+            || self.last_request.lock().unwrap().elapsed() > HLS_IDLE_TIMEOUT
+    }
+
+    /// Change the live playlist and wake up blocked requests
+    fn publish(&self, update: impl FnOnce(&mut Live)) {
+        update(&mut self.live.lock().unwrap());
+        self.updates.send_replace(());
+    }
+
+    /// Wait until `ready` holds for the live playlist; false on timeout or shutdown
+    async fn wait_until(&self, timeout: Duration, ready: impl Fn(&Live) -> bool) -> bool {
+        let mut updates = self.updates.subscribe();
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        loop {
+            let is_ready = ready(&self.live.lock().unwrap());
+            if is_ready {
+                return true;
+            }
+            tokio::select! {
+                changed = updates.changed() => {
+                    if changed.is_err() {
+                        return false;
+                    }
+                }
+                _ = &mut deadline => return false,
+                _ = self.shutdown.cancelled() => return false,
+            }
+        }
+    }
+
+    /// Longest a blocking request may wait (three target durations)
+    fn blocking_timeout(&self) -> Duration {
+        Duration::from_secs(3 * self.segment_secs as u64)
+    }
+
+    /// Package until idle
+    async fn run(&self) -> Result<()> {
+        info!(
+            "📺 Starting LL-HLS packager ({}s segments, {}ms parts)",
+            self.segment_secs, self.part_ms
+        );
+        self.publish(|live| *live = Live::default());
+
+        let result = package(
+            &self.video_fanout,
+            &self.audio_fanout,
+            || self.is_idle(),
+            |parameter_sets, stream_info| {
+                let init = init_segment(&parameter_sets, stream_info)?;
+                self.publish(|live| live.init = Some(init));
+                let mut fragmenter =
+                    Fragmenter::new(self.part_ms as i64 * 90, self.segment_secs as i64 * 90_000);
+                Ok(move |input: MuxInput| {
+                    if let Some((part, starts_segment)) = fragmenter.push(input) {
+                        self.publish(|live| live.push_part(part, starts_segment));
+                    }
+                    Ok(())
+                })
+            },
+        )
+        .await;
+
+        info!("📺 HLS packager stopped");
+        result
+    }
+
+    /// Current playlist, after waiting for what a blocking reload asked for
+    async fn playlist(&self, params: PlaylistParams) -> Response {
+        if params.msn.is_none() && params.part.is_some() {
+            return (StatusCode::BAD_REQUEST, "_HLS_part requires _HLS_msn").into_response();
+        }
+
+        // The first playlist appears once the first part is complete
+        if !self
+            .wait_until(PLAYLIST_WAIT_TIMEOUT, |live| live.current.is_some())
+            .await
+        {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "stream starting, retry shortly",
+            )
+                .into_response();
+        }
+
+        if let Some(msn) = params.msn {
+            let too_far = self.live.lock().unwrap().is_too_far_ahead(msn, params.part);
+            if too_far {
+                return (StatusCode::BAD_REQUEST, "_HLS_msn/_HLS_part too far ahead")
+                    .into_response();
+            }
+            let available = self
+                .wait_until(self.blocking_timeout(), |live| {
+                    live.is_available(msn, params.part)
+                })
+                .await;
+            if !available {
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+        }
+
+        let part_target = self.part_ms as f64 / 1000.0;
+        let playlist = self
+            .live
+            .lock()
+            .unwrap()
+            .playlist(self.segment_secs, part_target);
+        match playlist {
+            Some(playlist) => (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/vnd.apple.mpegurl"),
+                    ),
+                    (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+                ],
+                playlist,
+            )
+                .into_response(),
+            None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        }
+    }
+}
+
+/// Mux the video and audio fanouts into an MP4 file at `path` until `stop` returns true
+///
+/// `stop` is checked every second. Nothing is written before the first keyframe.
+pub async fn mux(
+    video_fanout: &VideoFanout,
+    audio_fanout: &AudioFanout,
+    path: PathBuf,
+    stop: impl Fn() -> bool,
+) -> Result<()> {
+    let mut muxer = None;
+    let result = package(
+        video_fanout,
+        audio_fanout,
+        stop,
+        |parameter_sets, stream_info| {
+            let (mux_tx, mux_rx) = mpsc::channel::<MuxInput>(MUXER_QUEUE_PACKETS);
+            muxer = Some(tokio::task::spawn_blocking(move || {
+                run_muxer(&path, stream_info, parameter_sets, mux_rx)
+            }));
+            Ok(move |input: MuxInput| mux_tx.try_send(input))
+        },
+    )
+    .await;
+
+    // The sender is gone once `package` returns, so the muxer finishes the file
+    let muxed = match muxer {
+        Some(muxer) => muxer.await.context("Muxer task panicked")?,
+        None => Ok(()),
+    };
+    result.and(muxed)
+}
+
+/// Feed the fanouts to a muxer until `stop` returns true
+///
+/// `start` receives the parameter sets of the first keyframe and the stream info
+/// and returns the sink that takes every input from then on.
+async fn package<S>(
+    video_fanout: &VideoFanout,
+    audio_fanout: &AudioFanout,
+    stop: impl Fn() -> bool,
+    start: impl FnOnce(Bytes, Option<VideoStreamInfo>) -> Result<S>,
+) -> Result<()>
+where
+    S: FnMut(MuxInput) -> Result<(), mpsc::error::TrySendError<MuxInput>>,
+{
+    let mut video_rx = video_fanout.subscribe().await;
+    let mut audio_rx = audio_fanout.subscribe().await;
+    let result = forward(video_fanout, &mut video_rx, &mut audio_rx, stop, start).await;
+    video_fanout.unsubscribe().await;
+    audio_fanout.unsubscribe().await;
+    result
+}
+
+/// Timestamp fanout media and hand it to the sink
+async fn forward<S>(
+    video_fanout: &VideoFanout,
+    video_rx: &mut broadcast::Receiver<crate::h264_extractor::H264Packet>,
+    audio_rx: &mut broadcast::Receiver<crate::audio_fanout::OpusSample>,
+    stop: impl Fn() -> bool,
+    start: impl FnOnce(Bytes, Option<VideoStreamInfo>) -> Result<S>,
+) -> Result<()>
+where
+    S: FnMut(MuxInput) -> Result<(), mpsc::error::TrySendError<MuxInput>>,
+{
+    let mut idle_check = tokio::time::interval(Duration::from_secs(1));
+
+    // The muxer header needs the parameter sets, which arrive with the first keyframe
+    let first_keyframe = loop {
+        tokio::select! {
+            _ = idle_check.tick() => {
+                if stop() {
+                    return Ok(());
+                }
+            }
+            packet = video_rx.recv() => match packet {
+                Ok(packet) if packet.is_keyframe => break packet,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    };
+    let parameter_sets = extract_parameter_sets(&first_keyframe.data)
+        .context("First keyframe carries no SPS/PPS")?;
+    let mut sink = start(parameter_sets, video_fanout.stream_info())?;
+
+    // Audio and video come from separate DoorBird connections, so the only common
+    // clock is arrival time
+    let start = Instant::now();
+    let mut last_video_pts = 0i64;
+    let mut next_audio_pts: Option<i64> = None;
+    let mut awaiting_keyframe = false;
+    let mut pending = Some(MuxInput::Video {
+        data: first_keyframe.data,
+        pts: 0,
+        key: true,
+    });
+
+    loop {
+        if let Some(input) = pending.take() {
+            match sink(input) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    // Muxer can't keep up (slow disk); skip to the next keyframe
+                    warn!("Muxer queue full, dropping media");
+                    awaiting_keyframe = true;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => break,
+            }
+        }
+
+        tokio::select! {
+            _ = idle_check.tick() => {
+                if stop() {
+                    break;
+                }
+            }
+            packet = video_rx.recv() => match packet {
+                Ok(packet) => {
+                    if awaiting_keyframe && !packet.is_keyframe {
+                        continue;
+                    }
+                    awaiting_keyframe = false;
+                    let pts = ((start.elapsed().as_secs_f64() * 90000.0) as i64)
+                        .max(last_video_pts + 1);
+                    last_video_pts = pts;
+                    pending = Some(MuxInput::Video {
+                        data: packet.data,
+                        pts,
+                        key: packet.is_keyframe,
+                    });
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    debug!("Muxer lagged behind video by {} packets", n);
+                    awaiting_keyframe = true;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            sample = audio_rx.recv() => match sample {
+                Ok(sample) => {
+                    // Audio timestamps advance by frame duration to avoid jitter,
+                    // re-anchored to arrival time after gaps
+                    let arrival = (start.elapsed().as_secs_f64() * 48000.0) as i64;
+                    let pts = match next_audio_pts {
+                        Some(next) if (arrival - next).abs() < 48000 / 5 => next,
+                        _ => arrival,
+                    };
+                    let duration = (sample.duration.as_secs_f64() * 48000.0) as u32;
+                    next_audio_pts = Some(pts + duration as i64);
+                    pending = Some(MuxInput::Audio {
+                        data: sample.data,
+                        pts,
+                        duration,
+                    });
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    next_audio_pts = None;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the MP4 file until the input channel closes
+fn run_muxer(
+    path: &std::path::Path,
+    stream_info: Option<VideoStreamInfo>,
+    parameter_sets: Bytes,
+    mut rx: mpsc::Receiver<MuxInput>,
+) -> Result<()> {
+    ffmpeg::init().context("Failed to initialize ffmpeg")?;
+
+    // Fragmented, so the file stays playable if the process dies mid-recording
+    let mut options = ffmpeg::Dictionary::new();
+    options.set("movflags", "frag_keyframe+empty_moov+default_base_moof");
+    let mut octx = ffmpeg::format::output_as_with(path, "mp4", options)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    let video_time_base = ffmpeg::Rational::new(1, 90000);
+    let audio_time_base = ffmpeg::Rational::new(1, 48000);
+
+    let video_index = {
+        let mut stream = octx.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))?;
+        stream.set_parameters(h264_parameters(stream_info, &parameter_sets));
+        stream.set_time_base(video_time_base);
+        stream.index()
+    };
+    let audio_index = {
+        let mut stream = octx.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))?;
+        stream.set_parameters(opus_parameters());
+        stream.set_time_base(audio_time_base);
+        stream.index()
+    };
+
+    octx.write_header().context("Failed to write header")?;
+
+    // The muxer may pick its own time bases when writing the header
+    let video_out_tb = octx
+        .stream(video_index)
+        .context("video stream")?
+        .time_base();
+    let audio_out_tb = octx
+        .stream(audio_index)
+        .context("audio stream")?
+        .time_base();
+
+    while let Some(input) = rx.blocking_recv() {
+        let mut packet = match input {
+            MuxInput::Video { data, pts, key } => {
+                let mut packet = ffmpeg::Packet::copy(&data);
+                packet.set_stream(video_index);
+                packet.set_pts(Some(pts));
+                packet.set_dts(Some(pts));
+                if key {
+                    packet.set_flags(ffmpeg::packet::Flags::KEY);
+                }
+                packet.rescale_ts(video_time_base, video_out_tb);
+                packet
+            }
+            MuxInput::Audio { data, pts, .. } => {
+                let mut packet = ffmpeg::Packet::copy(&data);
+                packet.set_stream(audio_index);
+                packet.set_pts(Some(pts));
+                packet.set_dts(Some(pts));
+                packet.rescale_ts(audio_time_base, audio_out_tb);
+                packet
+            }
+        };
+        if let Err(e) = packet.write_interleaved(&mut octx) {
+            // Usually a non-monotonic timestamp after a reconnect; skip the packet
+            debug!("Muxer rejected packet: {}", e);
+        }
+    }
+
+    octx.write_trailer().context("Failed to finalize output")?;
+    Ok(())
+}
+
+/// Codec parameters for the H.264 stream (Annex-B extradata is converted by the mp4 muxer)
+fn h264_parameters(
+    info: Option<VideoStreamInfo>,
+    parameter_sets: &[u8],
+) -> ffmpeg::codec::Parameters {
+    let mut parameters = ffmpeg::codec::Parameters::new();
+    // SAFETY: `parameters` owns a valid AVCodecParameters; extradata is allocated with
+    // av_malloc so it is freed together with the parameters
+    unsafe {
+        let par = parameters.as_mut_ptr();
+        (*par).codec_type = ffmpeg::ffi::AVMediaType::AVMEDIA_TYPE_VIDEO;
+        (*par).codec_id = ffmpeg::ffi::AVCodecID::AV_CODEC_ID_H264;
+        if let Some(info) = info {
+            (*par).width = info.width as i32;
+            (*par).height = info.height as i32;
+        }
+        set_extradata(par, parameter_sets);
+    }
+    parameters
+}
+
+/// Codec parameters for the mono 48kHz Opus stream
+fn opus_parameters() -> ffmpeg::codec::Parameters {
+    let mut parameters = ffmpeg::codec::Parameters::new();
+    // SAFETY: see `h264_parameters`
+    unsafe {
+        let par = parameters.as_mut_ptr();
+        (*par).codec_type = ffmpeg::ffi::AVMediaType::AVMEDIA_TYPE_AUDIO;
+        (*par).codec_id = ffmpeg::ffi::AVCodecID::AV_CODEC_ID_OPUS;
+        (*par).sample_rate = 48000;
+        (*par).frame_size = 960;
+        (*par).initial_padding = OPUS_PRE_SKIP as i32;
+        ffmpeg::ffi::av_channel_layout_default(&mut (*par).ch_layout, 1);
+        set_extradata(par, &opus_head(1));
+    }
+    parameters
+}
+
+/// Copy `data` into the parameters' extradata (padded as ffmpeg requires)
+///
+/// # Safety
+/// `par` must point to valid codec parameters without extradata.
+unsafe fn set_extradata(par: *mut ffmpeg::ffi::AVCodecParameters, data: &[u8]) {
+    let padding = ffmpeg::ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize;
+    let buf = ffmpeg::ffi::av_mallocz(data.len() + padding) as *mut u8;
+    if buf.is_null() {
+        return;
+    }
+    std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+    (*par).extradata = buf;
+    (*par).extradata_size = data.len() as i32;
+}
+
+/// Build an `OpusHead` identification header (RFC 7845 §5.1), used as Opus extradata
+fn opus_head(channels: u8) -> Bytes {
+    let mut head = BytesMut::with_capacity(19);
+    head.put_slice(b"OpusHead");
+    head.put_u8(1); // version
+    head.put_u8(channels);
+    head.put_u16_le(OPUS_PRE_SKIP);
+    head.put_u32_le(48000); // input sample rate
+    head.put_i16_le(0); // output gain
+    head.put_u8(0); // channel mapping family (mono/stereo)
+    head.freeze()
+}
+
+/// Collect the SPS and PPS NAL units (with start codes) from an Annex-B access unit
+fn extract_parameter_sets(annexb: &[u8]) -> Option<Bytes> {
+    let mut out = BytesMut::new();
+    for nal in split_nal_units(annexb) {
+        let nal_type = nal.first().map(|b| b & 0x1f);
+        if matches!(nal_type, Some(NAL_TYPE_SPS | NAL_TYPE_PPS)) {
+            out.put_slice(&[0, 0, 0, 1]);
+            out.put_slice(nal);
+        }
+    }
+    let has_sps = split_nal_units(&out).any(|n| n[0] & 0x1f == NAL_TYPE_SPS);
+    let has_pps = split_nal_units(&out).any(|n| n[0] & 0x1f == NAL_TYPE_PPS);
+    (has_sps && has_pps).then(|| out.freeze())
+}
+
+/// Split an Annex-B byte stream into NAL units (without start codes)
+fn split_nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|&next| {
+            // Trim the start code (and the extra zero of a 4-byte start code)
+            let mut end = next - 3;
+            while end > 0 && data[end - 1] == 0 {
+                end -= 1;
+            }
+            end
+        })
+        .chain(std::iter::once(data.len()))
+        .collect();
+
+    starts
+        .into_iter()
+        .zip(ends)
+        .filter(|(start, end)| end > start)
+        .map(move |(start, end)| &data[start..end])
+}
+
+/// Build the fMP4 init segment from the Annex-B parameter sets
+fn init_segment(parameter_sets: &[u8], info: Option<VideoStreamInfo>) -> Result<Bytes> {
+    let find = |nal_type| split_nal_units(parameter_sets).find(|nal| nal[0] & 0x1f == nal_type);
+    let sps = find(NAL_TYPE_SPS).context("no SPS")?;
+    let pps = find(NAL_TYPE_PPS).context("no PPS")?;
+    let (width, height) = info.map_or((0, 0), |info| (info.width as u16, info.height as u16));
+    Ok(fmp4::init_segment(sps, pps, width, height, OPUS_PRE_SKIP))
+}
+
+/// Convert an Annex-B access unit to length-prefixed NAL units (AVCC)
+///
+/// Parameter sets and delimiters are dropped; the init segment carries the former.
+fn annexb_to_avcc(annexb: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(annexb.len() + 16);
+    for nal in split_nal_units(annexb) {
+        if !matches!(nal[0] & 0x1f, NAL_TYPE_SPS | NAL_TYPE_PPS | NAL_TYPE_AUD) {
+            out.put_u32(nal.len() as u32);
+            out.put_slice(nal);
+        }
+    }
+    out.freeze()
+}
+
+/// A published LL-HLS partial segment (one fMP4 fragment)
+struct Part {
+    data: Bytes,
+    /// Duration in seconds
+    duration: f64,
+    /// Starts with a keyframe
+    independent: bool,
+}
+
+/// A media segment, made of its parts
+struct Segment {
+    /// Media sequence number
+    msn: u64,
+    parts: Vec<Part>,
+}
+
+impl Segment {
+    fn duration(&self) -> f64 {
+        self.parts.iter().map(|part| part.duration).sum()
+    }
+}
+
+/// Cuts the timestamped media into parts
+///
+/// A part ends before the video frame that would take it past the part target, or
+/// at a keyframe once the segment has reached its target; that keyframe starts the
+/// next segment. Audio frames go into the part being collected when they arrive.
+struct Fragmenter {
+    /// Part and segment targets in 1/90000
+    part_target: i64,
+    segment_target: i64,
+    /// Fragment sequence number of the last part
+    sequence: u32,
+    /// Video access units of the part being collected (AVCC, pts, key)
+    video: Vec<(Bytes, i64, bool)>,
+    /// Audio frames of the part being collected (data, pts, duration in 1/48000)
+    audio: Vec<(Bytes, i64, u32)>,
+    /// Pts of the keyframe that started the current segment
+    segment_start: i64,
+    /// Whether the part being collected starts a segment
+    starts_segment: bool,
+}
+
+impl Fragmenter {
+    fn new(part_target: i64, segment_target: i64) -> Self {
+        Self {
+            part_target,
+            segment_target,
+            sequence: 0,
+            video: Vec::new(),
+            audio: Vec::new(),
+            segment_start: 0,
+            starts_segment: true,
+        }
+    }
+
+    /// Add an input; returns a part (and whether it starts a segment) when one is complete
+    fn push(&mut self, input: MuxInput) -> Option<(Part, bool)> {
+        let (data, pts, key) = match input {
+            MuxInput::Audio {
+                data,
+                pts,
+                duration,
+            } => {
+                self.audio.push((data, pts, duration));
+                return None;
+            }
+            MuxInput::Video { data, pts, key } => (annexb_to_avcc(&data), pts, key),
+        };
+
+        let mut part = None;
+        if let (Some(first), Some(last)) = (self.video.first(), self.video.last()) {
+            // Assume the next frame comes as late as this one did
+            let frame_interval = pts - last.1;
+            let new_segment = key && pts - self.segment_start >= self.segment_target;
+            let full = pts - first.1 + frame_interval > self.part_target;
+            if new_segment || full {
+                part = Some((self.cut(pts), self.starts_segment));
+                self.starts_segment = new_segment;
+            }
+            if new_segment {
+                self.segment_start = pts;
+            }
+        } else {
+            self.segment_start = pts;
+        }
+        self.video.push((data, pts, key));
+        part
+    }
+
+    /// Package the collected media as a part ending at `end` (1/90000)
+    fn cut(&mut self, end: i64) -> Part {
+        let video: Vec<fmp4::Sample> = self
+            .video
+            .iter()
+            .enumerate()
+            .map(|(i, (data, pts, key))| {
+                let next = self.video.get(i + 1).map_or(end, |next| next.1);
+                fmp4::Sample {
+                    data: data.clone(),
+                    duration: (next - pts) as u32,
+                    key: *key,
+                }
+            })
+            .collect();
+        let audio: Vec<fmp4::Sample> = self
+            .audio
+            .iter()
+            .map(|(data, _, duration)| fmp4::Sample {
+                data: data.clone(),
+                duration: *duration,
+                key: true,
+            })
+            .collect();
+
+        let start = self.video[0].1;
+        self.sequence += 1;
+        let data = fmp4::fragment(
+            self.sequence,
+            fmp4::Run {
+                decode_time: start.max(0) as u64,
+                samples: &video,
+            },
+            fmp4::Run {
+                decode_time: self.audio.first().map_or(0, |a| a.1.max(0) as u64),
+                samples: &audio,
+            },
+        );
+        let part = Part {
+            data,
+            duration: (end - start) as f64 / fmp4::VIDEO_TIMESCALE as f64,
+            independent: self.video[0].2,
+        };
+        self.video.clear();
+        self.audio.clear();
+        part
+    }
+}
+
+/// Playlist and media of the running packager
+#[derive(Default)]
+struct Live {
+    init: Option<Bytes>,
+    /// Complete segments, oldest first
+    segments: VecDeque<Segment>,
+    /// Segment whose parts are still being published
+    current: Option<Segment>,
+}
+
+impl Live {
+    fn push_part(&mut self, part: Part, starts_segment: bool) {
+        match self.current.as_mut() {
+            Some(current) if !starts_segment => current.parts.push(part),
+            _ => {
+                let msn = self.current.as_ref().map_or(0, |current| current.msn + 1);
+                let segment = Segment {
+                    msn,
+                    parts: vec![part],
+                };
+                if let Some(done) = self.current.replace(segment) {
+                    self.segments.push_back(done);
+                    if self.segments.len() > PLAYLIST_SEGMENTS {
+                        self.segments.pop_front();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether segment `msn` is complete, or has part `part` when given
+    fn is_available(&self, msn: u64, part: Option<usize>) -> bool {
+        self.current.as_ref().is_some_and(|current| {
+            msn < current.msn
+                || (msn == current.msn && part.is_some_and(|part| part < current.parts.len()))
+        })
+    }
+
+    /// Whether a blocking reload asks for more than the server may make it wait for
+    fn is_too_far_ahead(&self, msn: u64, part: Option<usize>) -> bool {
+        self.current.as_ref().is_some_and(|current| {
+            msn > current.msn + ADVANCE_SEGMENT_LIMIT
+                || (msn == current.msn
+                    && part.is_some_and(|part| part >= current.parts.len() + ADVANCE_PART_LIMIT))
+        })
+    }
+
+    fn all_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter().chain(self.current.as_ref())
+    }
+
+    fn part(&self, msn: u64, index: usize) -> Option<Bytes> {
+        let segment = self.all_segments().find(|segment| segment.msn == msn)?;
+        segment.parts.get(index).map(|part| part.data.clone())
+    }
+
+    /// A complete segment (its parts concatenated)
+    fn segment(&self, msn: u64) -> Option<Bytes> {
+        let segment = self.segments.iter().find(|segment| segment.msn == msn)?;
+        let mut data = BytesMut::new();
+        for part in &segment.parts {
+            data.put_slice(&part.data);
+        }
+        Some(data.freeze())
+    }
+
+    /// Render the media playlist (`None` until the first part exists)
+    ///
+    /// # Arguments
+    /// * `segment_secs` - Configured target segment duration
+    /// * `part_target` - Part target duration in seconds
+    fn playlist(&self, segment_secs: u32, part_target: f64) -> Option<String> {
+        let current = self.current.as_ref()?;
+        self.init.as_ref()?;
+
+        // Keyframe-aligned segments can run past the configured target
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|segment| segment.duration().round() as u64)
+            .max()
+            .unwrap_or(0)
+            .max(segment_secs as u64);
+        let first_msn = self.segments.front().unwrap_or(current).msn;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "#EXTM3U");
+        let _ = writeln!(out, "#EXT-X-VERSION:6");
+        let _ = writeln!(out, "#EXT-X-TARGETDURATION:{}", target_duration);
+        let _ = writeln!(out, "#EXT-X-PART-INF:PART-TARGET={:.3}", part_target);
+        let _ = writeln!(
+            out,
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}",
+            part_target * 3.0
+        );
+        let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", first_msn);
+        let _ = writeln!(out, "#EXT-X-INDEPENDENT-SEGMENTS");
+        let _ = writeln!(out, "#EXT-X-MAP:URI=\"{}\"", INIT_NAME);
+
+        let listed_from = self.segments.len().saturating_sub(PART_LISTED_SEGMENTS);
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i >= listed_from {
+                write_parts(&mut out, segment);
+            }
+            let _ = writeln!(out, "#EXTINF:{:.3},", segment.duration());
+            let _ = writeln!(out, "segment_{}.m4s", segment.msn);
+        }
+        write_parts(&mut out, current);
+        let _ = writeln!(
+            out,
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part_{}_{}.m4s\"",
+            current.msn,
+            current.parts.len()
+        );
+        Some(out)
+    }
+}
+
+fn write_parts(out: &mut String, segment: &Segment) {
+    for (i, part) in segment.parts.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "#EXT-X-PART:DURATION={:.3},URI=\"part_{}_{}.m4s\"{}",
+            part.duration,
+            segment.msn,
+            i,
+            if part.independent {
+                ",INDEPENDENT=YES"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// A file under `/hls/`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resource {
+    Playlist,
+    Init,
+    Segment(u64),
+    /// Segment and part index
+    Part(u64, usize),
+}
+
+impl Resource {
+    /// Only the names the playlist uses are served (no path traversal)
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            PLAYLIST_NAME => return Some(Resource::Playlist),
+            INIT_NAME => return Some(Resource::Init),
+            _ => {}
+        }
+        let stem = name.strip_suffix(".m4s")?;
+        if let Some(msn) = stem.strip_prefix("segment_") {
+            return Some(Resource::Segment(parse_index(msn)?));
+        }
+        let (msn, part) = stem.strip_prefix("part_")?.split_once('_')?;
+        Some(Resource::Part(parse_index(msn)?, parse_index(part)?))
+    }
+}
+
+fn parse_index<T: std::str::FromStr>(digits: &str) -> Option<T> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Query parameters of `GET /hls/index.m3u8` (LL-HLS blocking playlist reload)
+#[derive(Debug, Deserialize)]
+pub struct PlaylistParams {
+    /// Wait until this media sequence number is in the playlist
+    #[serde(rename = "_HLS_msn")]
+    msn: Option<u64>,
+    /// ... and has this part
+    #[serde(rename = "_HLS_part")]
+    part: Option<usize>,
+}
+
+/// `GET /hls/{file}` - serve the playlist, parts and segments, starting the packager
+/// on demand
+pub async fn serve_file(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<PlaylistParams>,
+) -> Response {
+    let Some(resource) = Resource::parse(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let packager = &state.hls_packager;
+    packager.touch();
+
+    let data = match resource {
+        Resource::Playlist => return packager.playlist(params).await,
+        Resource::Init => packager.live.lock().unwrap().init.clone(),
+        Resource::Segment(msn) => packager.live.lock().unwrap().segment(msn),
+        Resource::Part(msn, index) => {
+            // The preload hint names a part before it exists; hold the request
+            // until it is complete
+            let too_far = packager
+                .live
+                .lock()
+                .unwrap()
+                .is_too_far_ahead(msn, Some(index));
+            if !too_far {
+                packager
+                    .wait_until(packager.blocking_timeout(), |live| {
+                        live.is_available(msn, Some(index))
+                    })
+                    .await;
+            }
+            packager.live.lock().unwrap().part(msn, index)
+        }
+    };
+
+    let content_type = if resource == Resource::Init {
+        "video/mp4"
+    } else {
+        "video/iso.segment"
+    };
+    match data {
+        Some(data) => (
+            [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+            data,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_parameter_sets() {
+        let sps = [0x67, 0x42, 0x00, 0x1f];
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+        let idr = [0x65, 0x88, 0x84, 0x00];
+        let mut au = Vec::new();
+        for nal in [&sps[..], &pps[..], &idr[..]] {
+            au.extend_from_slice(&[0, 0, 0, 1]);
+            au.extend_from_slice(nal);
+        }
+
+        let sets = extract_parameter_sets(&au).unwrap();
+        let nals: Vec<&[u8]> = split_nal_units(&sets).collect();
+        assert_eq!(nals, vec![&sps[..], &pps[..]]);
+    }
+
+    #[test]
+    fn test_extract_parameter_sets_requires_sps_and_pps() {
+        let au = [0, 0, 1, 0x65, 0x88, 0, 0, 1, 0x68, 0xce];
+        assert!(extract_parameter_sets(&au).is_none());
+    }
+
+    #[test]
+    fn test_split_nal_units_three_byte_start_codes() {
+        let data = [0, 0, 1, 0x67, 0x01, 0, 0, 1, 0x68, 0x02];
+        let nals: Vec<&[u8]> = split_nal_units(&data).collect();
+        assert_eq!(nals, vec![&[0x67, 0x01][..], &[0x68, 0x02][..]]);
+    }
+
+    #[test]
+    fn test_opus_head() {
+        let head = opus_head(1);
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[9], 1);
+        assert_eq!(u16::from_le_bytes([head[10], head[11]]), OPUS_PRE_SKIP);
+        assert_eq!(
+            u32::from_le_bytes([head[12], head[13], head[14], head[15]]),
+            48000
+        );
+    }
+
+    #[test]
+    fn test_resource_names() {
+        assert_eq!(Resource::parse("index.m3u8"), Some(Resource::Playlist));
+        assert_eq!(Resource::parse("init.mp4"), Some(Resource::Init));
+        assert_eq!(
+            Resource::parse("segment_12.m4s"),
+            Some(Resource::Segment(12))
+        );
+        assert_eq!(
+            Resource::parse("part_12_3.m4s"),
+            Some(Resource::Part(12, 3))
+        );
+        assert_eq!(Resource::parse("../etc/passwd"), None);
+        assert_eq!(Resource::parse("segment_+1.m4s"), None);
+        assert_eq!(Resource::parse("part_1.m4s"), None);
+        assert_eq!(Resource::parse("segment_.m4s"), None);
+    }
+
+    #[test]
+    fn test_annexb_to_avcc_drops_parameter_sets() {
+        let au = [
+            0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x65, 0x88, 0x84,
+        ];
+        assert_eq!(&annexb_to_avcc(&au)[..], &[0, 0, 0, 3, 0x65, 0x88, 0x84]);
+    }
+
+    fn video(pts: i64, key: bool) -> MuxInput {
+        MuxInput::Video {
+            data: Bytes::from_static(&[0, 0, 1, 0x65, 0x88]),
+            pts,
+            key,
+        }
+    }
+
+    /// Feed 10fps video with a keyframe every `gop` frames; returns the parts
+    /// (duration, independent, starts segment)
+    fn fragment(frames: i64, gop: i64) -> Vec<(f64, bool, bool)> {
+        // 300ms parts, 1s segments
+        let mut fragmenter = Fragmenter::new(27_000, 90_000);
+        let mut parts = Vec::new();
+        for i in 0..frames {
+            fragmenter.push(MuxInput::Audio {
+                data: Bytes::from_static(&[0xfc]),
+                pts: i * 4800,
+                duration: 960,
+            });
+            if let Some((part, starts)) = fragmenter.push(video(i * 9000, i % gop == 0)) {
+                parts.push((part.duration, part.independent, starts));
+            }
+        }
+        parts
+    }
+
+    #[test]
+    fn test_parts_stay_within_target() {
+        let parts = fragment(40, 10);
+        assert!(!parts.is_empty());
+        for (duration, _, _) in &parts {
+            assert!(*duration <= 0.3 + 1e-9, "part of {}s", duration);
+        }
+    }
+
+    #[test]
+    fn test_segments_start_on_keyframes_after_target() {
+        let parts = fragment(41, 15);
+        let starts: Vec<bool> = parts.iter().map(|p| p.2).collect();
+        let independent: Vec<bool> = parts.iter().map(|p| p.1).collect();
+        // Every segment starts with an independent part
+        for (starts, independent) in starts.iter().zip(&independent) {
+            assert!(!starts || *independent);
+        }
+        // Keyframes at 0s, 1.5s, 3s: the 1s target is reached between them
+        let segment_starts: Vec<usize> = (0..parts.len()).filter(|&i| starts[i]).collect();
+        assert_eq!(segment_starts.len(), 3);
+        let first_segment: f64 = parts[..segment_starts[1]].iter().map(|p| p.0).sum();
+        assert!((first_segment - 1.5).abs() < 1e-9);
+    }
+
+    fn part(duration: f64, independent: bool) -> Part {
+        Part {
+            data: Bytes::from_static(b"moof"),
+            duration,
+            independent,
+        }
+    }
+
+    fn live(segments: usize, parts_per_segment: usize, current_parts: usize) -> Live {
+        let mut live = Live {
+            init: Some(Bytes::from_static(b"init")),
+            ..Default::default()
+        };
+        for _ in 0..segments {
+            for i in 0..parts_per_segment {
+                live.push_part(part(0.25, i == 0), i == 0);
+            }
+        }
+        for i in 0..current_parts {
+            live.push_part(part(0.25, i == 0), i == 0);
+        }
+        live
+    }
+
+    #[test]
+    fn test_playlist_lists_recent_parts_and_preload_hint() {
+        let playlist = live(3, 4, 2).playlist(1, 0.25).unwrap();
+        assert!(playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.250\n"));
+        assert!(playlist.contains("CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=0.750\n"));
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:0\n"));
+        assert!(playlist.contains("#EXT-X-MAP:URI=\"init.mp4\"\n"));
+        assert!(playlist.contains("#EXTINF:1.000,\nsegment_0.m4s\n"));
+        // Parts only for the last two complete segments and the current one
+        assert!(!playlist.contains("part_0_"));
+        assert!(
+            playlist.contains("#EXT-X-PART:DURATION=0.250,URI=\"part_1_0.m4s\",INDEPENDENT=YES\n")
+        );
+        assert!(playlist.contains("#EXT-X-PART:DURATION=0.250,URI=\"part_3_1.m4s\"\n"));
+        assert!(playlist.ends_with("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part_3_2.m4s\"\n"));
+    }
+
+    #[test]
+    fn test_playlist_window() {
+        let live = live(PLAYLIST_SEGMENTS + 3, 2, 1);
+        assert_eq!(live.segments.len(), PLAYLIST_SEGMENTS);
+        let playlist = live.playlist(1, 0.25).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:3\n"));
+        assert!(!playlist.contains("segment_2.m4s"));
+        assert!(live.segment(2).is_none());
+        assert_eq!(&live.segment(3).unwrap()[..], b"moofmoof");
+    }
+
+    #[test]
+    fn test_playlist_needs_a_part() {
+        assert!(Live::default().playlist(1, 0.25).is_none());
+    }
+
+    #[test]
+    fn test_blocking_reload_availability() {
+        let live = live(2, 4, 2);
+        // Segments 0 and 1 are complete, segment 2 has parts 0 and 1
+        assert!(live.is_available(1, None));
+        assert!(!live.is_available(2, None));
+        assert!(live.is_available(2, Some(1)));
+        assert!(!live.is_available(2, Some(2)));
+        assert!(!live.is_available(3, Some(0)));
+
+        assert!(!live.is_too_far_ahead(4, None));
+        assert!(live.is_too_far_ahead(5, None));
+        assert!(!live.is_too_far_ahead(2, Some(4)));
+        assert!(live.is_too_far_ahead(2, Some(5)));
+    }
+}
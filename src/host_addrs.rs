@@ -0,0 +1,151 @@
+//! Host addresses advertised to WebRTC clients
+//!
+//! `BIRDBOX_HOST_IP` and `BIRDBOX_HOST_IP_LAN` accept a single IPv4 or IPv6
+//! address or a comma-separated list. When no host IP is configured the
+//! addresses are detected from the network interfaces: at most one IPv4 and one
+//! IPv6 address, preferring private IPv4 and global IPv6. Detection works on an
+//! offline LAN since it doesn't need a route to the internet.
+
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Interface name prefixes of container and VPN-internal bridges skipped during detection
+const VIRTUAL_INTERFACE_PREFIXES: &[&str] = &["docker", "br-", "veth", "virbr", "cni", "flannel"];
+
+/// Parse a comma-separated list of IP addresses (IPv6 may be bracketed)
+pub fn parse_list(value: &str) -> Result<Vec<IpAddr>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .with_context(|| format!("'{}' is not an IP address", s))
+        })
+        .collect()
+}
+
+/// Detect host addresses from the network interfaces
+pub fn detect() -> Vec<IpAddr> {
+    match webrtc::util::ifaces::ifaces() {
+        Ok(interfaces) => choose(
+            interfaces
+                .into_iter()
+                .filter_map(|iface| iface.addr.map(|addr| (iface.name, addr.ip()))),
+        ),
+        Err(e) => {
+            tracing::warn!("Failed to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Pick the best IPv4 and IPv6 address from `(interface name, address)` pairs
+fn choose(addrs: impl IntoIterator<Item = (String, IpAddr)>) -> Vec<IpAddr> {
+    let mut best_v4: Option<(u8, IpAddr)> = None;
+    let mut best_v6: Option<(u8, IpAddr)> = None;
+
+    for (name, ip) in addrs {
+        if !is_usable(&ip)
+            || VIRTUAL_INTERFACE_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        {
+            continue;
+        }
+
+        // Lower rank wins; the first address of a rank is kept
+        let (best, rank) = match ip {
+            IpAddr::V4(v4) => (&mut best_v4, if v4.is_private() { 0 } else { 1 }),
+            IpAddr::V6(v6) => (&mut best_v6, if is_unique_local(&v6) { 1 } else { 0 }),
+        };
+        if best.is_none_or(|(best_rank, _)| rank < best_rank) {
+            *best = Some((rank, ip));
+        }
+    }
+
+    best_v4
+        .into_iter()
+        .chain(best_v6)
+        .map(|(_, ip)| ip)
+        .collect()
+}
+
+/// Addresses a remote client could reach (no loopback, wildcard or link-local)
+pub fn is_usable(ip: &IpAddr) -> bool {
+    if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() {
+        return false;
+    }
+    match ip {
+        IpAddr::V4(v4) => !v4.is_link_local(),
+        // fe80::/10 needs a scope ID that ICE candidates can't carry
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+/// fc00::/7 unique local address
+fn is_unique_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xfe00 == 0xfc00
+}
+
+/// Addresses to advertise: LAN addresses first, then public ones, without duplicates
+pub fn advertised(host_ips: &[IpAddr], lan_ips: &[IpAddr]) -> Vec<IpAddr> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    for ip in lan_ips.iter().chain(host_ips) {
+        if is_usable(ip) && !ips.contains(ip) {
+            ips.push(*ip);
+        }
+    }
+    ips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("192.168.1.50, 2001:db8::1,[fd00::5]").unwrap(),
+            vec![ip("192.168.1.50"), ip("2001:db8::1"), ip("fd00::5")]
+        );
+        assert!(parse_list("").unwrap().is_empty());
+        assert!(parse_list("192.168.1.50,birdbox.local").is_err());
+    }
+
+    #[test]
+    fn test_choose_prefers_private_v4_and_global_v6() {
+        let chosen = choose(vec![
+            ("lo".to_string(), ip("127.0.0.1")),
+            ("docker0".to_string(), ip("172.17.0.1")),
+            ("eth0".to_string(), ip("fe80::1")),
+            ("eth0".to_string(), ip("fd00::10")),
+            ("wg0".to_string(), ip("100.64.0.2")),
+            ("eth0".to_string(), ip("192.168.1.50")),
+            ("eth0".to_string(), ip("2001:db8::10")),
+        ]);
+        assert_eq!(chosen, vec![ip("192.168.1.50"), ip("2001:db8::10")]);
+    }
+
+    #[test]
+    fn test_choose_nothing_usable() {
+        assert!(choose(vec![("lo".to_string(), ip("::1"))]).is_empty());
+    }
+
+    #[test]
+    fn test_advertised_orders_lan_first_and_filters() {
+        assert_eq!(
+            advertised(
+                &[ip("203.0.113.50"), ip("0.0.0.0"), ip("192.168.1.50")],
+                &[ip("192.168.1.50"), ip("127.0.0.1"), ip("fd00::10")],
+            ),
+            vec![ip("192.168.1.50"), ip("fd00::10"), ip("203.0.113.50")]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! STUN/TURN servers offered to WebRTC clients
+//!
+//! Birdbox advertises its own address as a host candidate, which is enough on the
+//! LAN and with a forwarded UDP port. Viewers on networks that block outbound UDP
+//! (corporate Wi-Fi, some mobile carriers) can only connect through a TURN relay
+//! reachable over TCP/TLS, e.g. `turns:turn.example.com:443?transport=tcp`.
+//!
+//! The configured servers are handed to clients, not used by the server itself:
+//! - browsers receive them in the `hello` signaling message
+//! - WHEP/WHIP clients receive them as `Link: <...>; rel="ice-server"` headers
+//!
+//! ICE-TCP (TCP host candidates) is not available: webrtc-rs only gathers UDP
+//! candidates, so TURN over TCP/TLS is the way through UDP-blocking networks.
+
+use crate::config;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// A STUN or TURN server, serialized like the browser `RTCIceServer` dictionary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// The configured ICE servers (`[ice]`, or `BIRDBOX_ICE_SERVERS` with
+/// `BIRDBOX_TURN_USERNAME` / `BIRDBOX_TURN_CREDENTIAL`)
+pub fn from_config(config: &config::Ice) -> Vec<IceServer> {
+    let servers = parse(
+        config.servers.iter().map(String::as_str),
+        config.username.clone(),
+        config.credential.clone(),
+    );
+
+    if servers.is_empty() {
+        info!("🌐 No STUN/TURN servers configured (host candidates only)");
+    } else {
+        for server in &servers {
+            info!("🌐 Offering ICE server(s) to clients: {:?}", server.urls);
+        }
+    }
+    servers
+}
+
+/// URL scheme, lowercased
+fn scheme(url: &str) -> Option<String> {
+    url.split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+}
+
+/// Whether `url` is a `stun:`, `stuns:`, `turn:` or `turns:` URL
+pub fn is_ice_url(url: &str) -> bool {
+    matches!(
+        scheme(url).as_deref(),
+        Some("stun" | "stuns" | "turn" | "turns")
+    )
+}
+
+/// Group URLs into a STUN entry and a TURN entry carrying the credentials
+pub fn parse<'a>(
+    urls: impl IntoIterator<Item = &'a str>,
+    username: Option<String>,
+    credential: Option<String>,
+) -> Vec<IceServer> {
+    let mut stun = Vec::new();
+    let mut turn = Vec::new();
+
+    for url in urls.into_iter().map(str::trim).filter(|u| !u.is_empty()) {
+        match scheme(url).as_deref() {
+            Some("stun" | "stuns") => stun.push(url.to_string()),
+            Some("turn" | "turns") => turn.push(url.to_string()),
+            _ => warn!("Ignoring invalid ICE server URL: {}", url),
+        }
+    }
+
+    let mut servers = Vec::new();
+    if !stun.is_empty() {
+        servers.push(IceServer {
+            urls: stun,
+            username: None,
+            credential: None,
+        });
+    }
+    if !turn.is_empty() {
+        if username.is_none() || credential.is_none() {
+            warn!("TURN servers configured without BIRDBOX_TURN_USERNAME/BIRDBOX_TURN_CREDENTIAL");
+        }
+        servers.push(IceServer {
+            urls: turn,
+            username,
+            credential,
+        });
+    }
+    servers
+}
+
+/// `Link` header values advertising the servers to WHIP/WHEP clients (RFC 9725 §4.6)
+pub fn link_headers(servers: &[IceServer]) -> Vec<String> {
+    servers
+        .iter()
+        .flat_map(|server| {
+            server.urls.iter().map(move |url| {
+                let mut link = format!("<{}>; rel=\"ice-server\"", url);
+                if let (Some(username), Some(credential)) = (&server.username, &server.credential) {
+                    link.push_str(&format!(
+                        "; username=\"{}\"; credential=\"{}\"; credential-type=\"password\"",
+                        username.replace('"', "\\\""),
+                        credential.replace('"', "\\\"")
+                    ));
+                }
+                link
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_groups_stun_and_turn() {
+        let servers = parse(
+            "stun:stun.example.com:3478, turns:turn.example.com:443?transport=tcp,turn:turn.example.com:3478"
+                .split(','),
+            Some("user".into()),
+            Some("secret".into()),
+        );
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].urls, vec!["stun:stun.example.com:3478"]);
+        assert_eq!(servers[0].username, None);
+        assert_eq!(servers[1].urls.len(), 2);
+        assert_eq!(servers[1].username.as_deref(), Some("user"));
+        assert_eq!(servers[1].credential.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn test_parse_ignores_invalid_and_empty() {
+        assert!(parse([], None, None).is_empty());
+        assert!(parse(["http://example.com", " ", ""], None, None).is_empty());
+        assert!(!is_ice_url("example.com"));
+        assert!(is_ice_url("TURNS:turn.example.com:443"));
+    }
+
+    #[test]
+    fn test_serializes_like_rtc_ice_server() {
+        let servers = parse(["stun:stun.example.com"], None, None);
+        let json = serde_json::to_value(&servers).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{ "urls": ["stun:stun.example.com"] }])
+        );
+    }
+
+    #[test]
+    fn test_link_headers() {
+        let servers = parse(
+            ["stun:stun.example.com", "turn:turn.example.com"],
+            Some("u".into()),
+            Some("p".into()),
+        );
+        assert_eq!(
+            link_headers(&servers),
+            vec![
+                "<stun:stun.example.com>; rel=\"ice-server\"".to_string(),
+                "<turn:turn.example.com>; rel=\"ice-server\"; username=\"u\"; credential=\"p\"; credential-type=\"password\"".to_string(),
+            ]
+        );
+    }
+}
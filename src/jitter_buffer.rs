@@ -0,0 +1,423 @@
+//! Jitter buffer for push-to-talk audio
+//!
+//! The client's Opus packets arrive with network jitter: in bursts, with gaps, and
+//! occasionally out of order. The DoorBird plays audio as it arrives, so the PTT
+//! pipeline (`webrtc::spawn_ptt_transmitter`) pushes packets in here and takes
+//! them out paced by a 20ms tick:
+//! - packets are ordered by RTP sequence number; late and duplicate packets are dropped
+//! - a packet lasts as many ticks as the RTP timestamps of consecutive packets say
+//!   (browsers send 20ms, other clients up to 120ms), so longer packets don't pile up
+//! - playout starts once the target depth is buffered. The target follows the
+//!   interarrival jitter (RFC 3550, from RTP timestamps and arrival times), between
+//!   `MIN_DEPTH` and `MAX_DEPTH` frames
+//! - a missing frame is reported as `Lost` once later packets are buffered, with the
+//!   packet after it (if buffered) so the decoder can recover it from in-band FEC data,
+//!   or else conceal it
+//! - when the buffer runs dry playout pauses (`Waiting`) until the target depth is
+//!   buffered again; after a burst of more than `MAX_DEPTH` frames (or twice the
+//!   target) the oldest frames are dropped to keep the delay down
+//! - once the stream has ended (`finish`) whatever is buffered plays out without
+//!   waiting for the target depth
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Playout tick, the duration of the Opus packets browsers send
+pub const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// RTP timestamp increment of one frame (20ms at 48kHz)
+const FRAME_TICKS: u32 = 960;
+
+/// Longest Opus packet in frames (120ms)
+pub const MAX_PACKET_FRAMES: u64 = 6;
+
+/// Opus RTP clock rate
+const CLOCK_RATE: f64 = 48000.0;
+
+/// Smallest target depth in frames
+const MIN_DEPTH: u64 = 2;
+
+/// Largest target depth in frames
+const MAX_DEPTH: u64 = 10;
+
+/// A sequence number jump this large is a restarted stream rather than loss
+const MAX_SEQUENCE_JUMP: i64 = 1000;
+
+/// Opus packet from the client's RTP stream
+#[derive(Debug, Clone)]
+pub struct RtpAudio {
+    pub sequence_number: u16,
+    /// RTP timestamp (48kHz)
+    pub timestamp: u32,
+    pub payload: Bytes,
+}
+
+/// What to play for the current frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// The next packet in sequence
+    Frame(Bytes),
+    /// The next packet is missing but later ones have arrived; carries the packet
+    /// after the missing one if it is buffered (its Opus FEC data covers the loss)
+    Lost(Option<Bytes>),
+    /// Nothing new to play: buffering, or the previous packet lasts longer than a tick
+    Waiting,
+}
+
+/// Packets dropped or concealed since the buffer was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    /// Frames reported as `Lost`
+    pub lost: u64,
+    /// Packets that arrived after their frame was played, and duplicates
+    pub late: u64,
+    /// Frames dropped to reduce the delay after a burst
+    pub dropped: u64,
+}
+
+/// Reorders RTP audio packets and releases them one frame at a time
+#[derive(Debug, Default)]
+pub struct JitterBuffer {
+    /// Payloads by extended sequence number
+    packets: BTreeMap<u64, Bytes>,
+    /// Extended sequence number of the next frame to play (once playout started)
+    next: Option<u64>,
+    /// Highest extended sequence number seen, for unwrapping
+    highest: Option<u64>,
+    playing: bool,
+    /// Interarrival jitter in seconds
+    jitter: f64,
+    /// Arrival time and RTP timestamp of the previous packet
+    last_arrival: Option<(Instant, u32)>,
+    /// Extended sequence number of the previous packet
+    last_seq: Option<u64>,
+    /// Frames per packet (0 until two consecutive packets arrived, then 1 or more)
+    packet_frames: u64,
+    /// Ticks the packet played last still lasts
+    hold: u64,
+    /// No more packets will be pushed
+    finished: bool,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a packet that arrived at `now`
+    pub fn push(&mut self, packet: RtpAudio, now: Instant) {
+        let seq = match self.highest {
+            // Start a cycle in, so packets from before the first one stay positive
+            None => (1 << 16) + packet.sequence_number as u64,
+            Some(highest) => {
+                let delta = packet.sequence_number.wrapping_sub(highest as u16) as i16 as i64;
+                if delta.abs() > MAX_SEQUENCE_JUMP {
+                    // The client restarted its stream
+                    self.reset();
+                    (1 << 16) + packet.sequence_number as u64
+                } else {
+                    (highest as i64 + delta) as u64
+                }
+            }
+        };
+        self.highest = Some(self.highest.map_or(seq, |highest| highest.max(seq)));
+
+        if let Some((arrival, timestamp)) = self.last_arrival {
+            let elapsed = now.saturating_duration_since(arrival).as_secs_f64();
+            let sent = packet.timestamp.wrapping_sub(timestamp) as i32 as f64 / CLOCK_RATE;
+            self.jitter += ((elapsed - sent).abs() - self.jitter) / 16.0;
+
+            if self.last_seq == Some(seq - 1) {
+                let ticks = packet.timestamp.wrapping_sub(timestamp) as i32;
+                let frames = (ticks as f64 / FRAME_TICKS as f64).round() as i64;
+                self.packet_frames = frames.clamp(1, MAX_PACKET_FRAMES as i64) as u64;
+            }
+        }
+        self.last_arrival = Some((now, packet.timestamp));
+        self.last_seq = Some(seq);
+
+        if self.next.is_some_and(|next| seq < next) || self.packets.contains_key(&seq) {
+            self.stats.late += 1;
+            return;
+        }
+        self.packets.insert(seq, packet.payload);
+    }
+
+    /// Take the packet to play now; call once per `FRAME_DURATION`
+    pub fn pop(&mut self) -> Playout {
+        if self.hold > 0 {
+            self.hold -= 1;
+            return Playout::Waiting;
+        }
+        if !self.playing {
+            if self.packets.is_empty() || (!self.finished && self.depth() < self.target_depth()) {
+                return Playout::Waiting;
+            }
+            self.playing = true;
+            self.next = self.packets.keys().next().copied();
+        }
+
+        // Catch up after a burst
+        let max_depth = MAX_DEPTH.max(2 * self.target_depth());
+        while self.depth() > max_depth {
+            if let Some((seq, _)) = self.packets.pop_first() {
+                self.next = Some(seq + 1);
+                self.stats.dropped += 1;
+            }
+        }
+
+        let Some(next) = self.next else {
+            self.playing = false;
+            return Playout::Waiting;
+        };
+        if let Some(payload) = self.packets.remove(&next) {
+            self.next = Some(next + 1);
+            self.hold = self.packet_frames() - 1;
+            return Playout::Frame(payload);
+        }
+        if self.packets.is_empty() {
+            // Ran dry: re-buffer (a packet that turns up before then still plays)
+            self.playing = false;
+            return Playout::Waiting;
+        }
+        self.next = Some(next + 1);
+        self.hold = self.packet_frames() - 1;
+        self.stats.lost += 1;
+        Playout::Lost(self.packets.get(&(next + 1)).cloned())
+    }
+
+    /// The stream has ended: play out what is buffered without waiting for more
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Nothing left to play
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty() && self.hold == 0
+    }
+
+    /// Frames from the next one to play to the newest one buffered
+    fn depth(&self) -> u64 {
+        let (Some(first), Some(last)) = (self.packets.keys().next(), self.packets.keys().last())
+        else {
+            return 0;
+        };
+        let first = self.next.filter(|_| self.playing).unwrap_or(*first);
+        (last + 1 - first.min(*last)) * self.packet_frames()
+    }
+
+    fn packet_frames(&self) -> u64 {
+        self.packet_frames.max(1)
+    }
+
+    /// Frames to buffer before playing, from the measured jitter
+    fn target_depth(&self) -> u64 {
+        let frames = (3.0 * self.jitter / FRAME_DURATION.as_secs_f64()).ceil() as u64;
+        (frames + 1).clamp(MIN_DEPTH, MAX_DEPTH)
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    fn reset(&mut self) {
+        self.packets.clear();
+        self.next = None;
+        self.highest = None;
+        self.playing = false;
+        self.jitter = 0.0;
+        self.last_arrival = None;
+        self.last_seq = None;
+        self.packet_frames = 0;
+        self.hold = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16) -> RtpAudio {
+        RtpAudio {
+            sequence_number,
+            timestamp: (sequence_number as u32).wrapping_mul(960),
+            payload: Bytes::from(sequence_number.to_be_bytes().to_vec()),
+        }
+    }
+
+    fn frame(sequence_number: u16) -> Playout {
+        Playout::Frame(Bytes::from(sequence_number.to_be_bytes().to_vec()))
+    }
+
+    /// Push packets as if they arrived exactly on time
+    fn push_all(buffer: &mut JitterBuffer, start: Instant, sequence_numbers: &[u16]) {
+        for (i, &seq) in sequence_numbers.iter().enumerate() {
+            buffer.push(packet(seq), start + FRAME_DURATION * i as u32);
+        }
+    }
+
+    #[test]
+    fn test_prebuffers_then_plays_in_order() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        buffer.push(packet(10), start);
+        assert_eq!(buffer.pop(), Playout::Waiting);
+
+        push_all(&mut buffer, start, &[12, 11, 13]);
+        assert_eq!(buffer.pop(), frame(10));
+        assert_eq!(buffer.pop(), frame(11));
+        assert_eq!(buffer.pop(), frame(12));
+        assert_eq!(buffer.pop(), frame(13));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert_eq!(buffer.stats(), JitterStats::default());
+    }
+
+    #[test]
+    fn test_reports_loss_and_drops_late_packets() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        push_all(&mut buffer, start, &[1, 2, 4, 5]);
+        assert_eq!(buffer.pop(), frame(1));
+        assert_eq!(buffer.pop(), frame(2));
+        assert_eq!(
+            buffer.pop(),
+            Playout::Lost(Some(Bytes::from(4u16.to_be_bytes().to_vec())))
+        );
+
+        // 3 turns up after its turn, 4 again as a duplicate
+        buffer.push(packet(3), start);
+        buffer.push(packet(4), start);
+        assert_eq!(buffer.pop(), frame(4));
+        assert_eq!(buffer.pop(), frame(5));
+        assert_eq!(
+            buffer.stats(),
+            JitterStats {
+                lost: 1,
+                late: 2,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_lost_frames_carry_the_next_packet() {
+        let mut buffer = JitterBuffer::new();
+        push_all(&mut buffer, Instant::now(), &[1, 2, 5]);
+        assert_eq!(buffer.pop(), frame(1));
+        assert_eq!(buffer.pop(), frame(2));
+        // 3 and 4 are missing: no FEC data for 3, 5 carries 4's
+        assert_eq!(buffer.pop(), Playout::Lost(None));
+        assert_eq!(
+            buffer.pop(),
+            Playout::Lost(Some(Bytes::from(5u16.to_be_bytes().to_vec())))
+        );
+        assert_eq!(buffer.pop(), frame(5));
+        assert_eq!(buffer.stats().lost, 2);
+    }
+
+    #[test]
+    fn test_sequence_number_wraparound() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        for (i, seq) in [65534u16, 0, 65535, 1].into_iter().enumerate() {
+            let position = seq.wrapping_sub(65534) as u32;
+            buffer.push(
+                RtpAudio {
+                    timestamp: (u32::MAX - 959).wrapping_add(position * 960),
+                    ..packet(seq)
+                },
+                start + FRAME_DURATION * i as u32,
+            );
+        }
+        assert_eq!(buffer.pop(), frame(65534));
+        assert_eq!(buffer.pop(), frame(65535));
+        assert_eq!(buffer.pop(), frame(0));
+        assert_eq!(buffer.pop(), frame(1));
+    }
+
+    #[test]
+    fn test_rebuffers_after_underrun() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        push_all(&mut buffer, start, &[1, 2]);
+        assert_eq!(buffer.pop(), frame(1));
+        assert_eq!(buffer.pop(), frame(2));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+
+        buffer.push(packet(3), start + FRAME_DURATION * 2);
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        buffer.push(packet(4), start + FRAME_DURATION * 3);
+        assert_eq!(buffer.pop(), frame(3));
+    }
+
+    #[test]
+    fn test_drops_oldest_frames_after_burst() {
+        let mut buffer = JitterBuffer::new();
+        let sequence_numbers: Vec<u16> = (0..15).collect();
+        push_all(&mut buffer, Instant::now(), &sequence_numbers);
+        assert_eq!(buffer.pop(), frame(5));
+        assert_eq!(buffer.stats().dropped, 5);
+    }
+
+    #[test]
+    fn test_target_depth_follows_jitter() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        // Packets sent every 20ms, arriving in bursts of four every 80ms
+        for i in 0..40u16 {
+            let arrival = start + FRAME_DURATION * (i as u32 / 4 * 4);
+            buffer.push(packet(i), arrival);
+        }
+        assert!(buffer.target_depth() > MIN_DEPTH);
+        assert!(buffer.target_depth() <= MAX_DEPTH);
+    }
+
+    #[test]
+    fn test_paces_longer_packets_by_timestamp() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        // 60ms packets
+        for seq in 1..=3u16 {
+            buffer.push(
+                RtpAudio {
+                    timestamp: seq as u32 * 3 * FRAME_TICKS,
+                    ..packet(seq)
+                },
+                start + FRAME_DURATION * 3 * seq as u32,
+            );
+        }
+        assert_eq!(buffer.pop(), frame(1));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert_eq!(buffer.pop(), frame(2));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert_eq!(buffer.pop(), frame(3));
+        assert_eq!(buffer.stats(), JitterStats::default());
+    }
+
+    #[test]
+    fn test_finish_plays_out_the_rest() {
+        let mut buffer = JitterBuffer::new();
+        buffer.push(packet(1), Instant::now());
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert!(!buffer.is_empty());
+
+        buffer.finish();
+        assert_eq!(buffer.pop(), frame(1));
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), Playout::Waiting);
+    }
+
+    #[test]
+    fn test_restarted_stream() {
+        let mut buffer = JitterBuffer::new();
+        let start = Instant::now();
+        push_all(&mut buffer, start, &[100, 101]);
+        assert_eq!(buffer.pop(), frame(100));
+        push_all(&mut buffer, start, &[30000, 30001]);
+        assert_eq!(buffer.pop(), frame(30000));
+    }
+}
@@ -18,31 +18,65 @@ use askama::Template;
 use axum::extract::ws::{Message, WebSocket};
 use axum::response::Html;
 use axum::{extract::ws::WebSocketUpgrade, response::IntoResponse, routing::get, Router};
+use clap::Parser;
 use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::future::IntoFuture;
 use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, RwLock};
 use tower_http::services::ServeDir;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+mod announce;
 mod audio_fanout;
+mod audio_file;
 mod audio_transcode;
+mod auth;
+mod automation;
+mod bandwidth;
+mod cli;
+mod config;
+mod fmp4;
 mod g711;
 mod h264_extractor;
+mod health;
+mod hls;
+mod host_addrs;
+mod ice_servers;
+mod jitter_buffer;
+mod metrics;
+mod mjpeg_fanout;
+mod recorder;
+mod reload;
+mod resume;
+mod rtsp_server;
+mod sessions;
+mod shutdown;
+mod signaling;
+mod status;
+mod turn_server;
 mod video_fanout;
+mod video_tiers;
 mod webrtc;
+mod whep;
+mod whip;
 
 use audio_fanout::AudioFanout;
+use mjpeg_fanout::MjpegFanout;
+use signaling::{ErrorCode, SignalError, SignalMessage};
 use video_fanout::VideoFanout;
+use video_tiers::{VideoTier, VideoTiers};
 
 /// Push-to-talk (PTT) state coordinator
 ///
 /// Ensures only one client can transmit audio to the DoorBird at a time.
 /// Broadcasts PTT state changes to all connected clients so they can update their UI.
 struct PttState {
-    /// Session ID of the client currently transmitting (if any)
-    active_session: Arc<RwLock<Option<Uuid>>>,
+    /// Session ID of the client currently transmitting (if any), and since when
+    active_session: Arc<RwLock<Option<(Uuid, std::time::Instant)>>>,
     /// Broadcast channel for PTT state updates to all clients
     state_tx: broadcast::Sender<PttStateMessage>,
 }
@@ -70,7 +104,7 @@ impl PttState {
     async fn try_acquire(&self, session_id: Uuid) -> bool {
         let mut active = self.active_session.write().await;
         if active.is_none() {
-            *active = Some(session_id);
+            *active = Some((session_id, std::time::Instant::now()));
             info!("PTT acquired by session {}", session_id);
 
             // Broadcast state change
@@ -83,7 +117,8 @@ impl PttState {
         } else {
             warn!(
                 "PTT denied for session {} - already in use by {:?}",
-                session_id, *active
+                session_id,
+                active.map(|(id, _)| id)
             );
             false
         }
@@ -92,9 +127,12 @@ impl PttState {
     /// Release PTT lock for a session
     async fn release(&self, session_id: Uuid) {
         let mut active = self.active_session.write().await;
-        if *active == Some(session_id) {
+        if let Some((_, acquired)) = active.filter(|(id, _)| *id == session_id) {
             *active = None;
             info!("PTT released by session {}", session_id);
+            metrics::METRICS
+                .ptt_duration
+                .observe(&[], acquired.elapsed());
 
             // Broadcast state change
             let _ = self.state_tx.send(PttStateMessage {
@@ -114,6 +152,11 @@ impl PttState {
         let active = self.active_session.read().await;
         active.is_some()
     }
+
+    /// Session currently holding the PTT lock
+    async fn holder(&self) -> Option<Uuid> {
+        self.active_session.read().await.map(|(id, _)| id)
+    }
 }
 
 /// Application state shared across all connections
@@ -123,18 +166,64 @@ impl PttState {
 struct AppState {
     /// Audio fanout for distributing DoorBird audio to multiple clients
     audio_fanout: Arc<AudioFanout>,
-    /// Video fanout for distributing DoorBird video to multiple clients
-    video_fanout: Arc<VideoFanout>,
+    /// Video fanouts (one per quality tier) for distributing DoorBird video to multiple clients
+    video_tiers: Arc<VideoTiers>,
     /// Shared WebRTC infrastructure (UDP mux, API)
     webrtc_infra: Arc<webrtc::WebRtcInfra>,
     /// Push-to-talk coordination
     ptt_state: Arc<PttState>,
     /// DoorBird API client for device control
     doorbird_client: doorbird::Client,
+    /// Playback sessions created through the WHEP endpoint
+    whep_sessions: Arc<whep::WhepSessions>,
+    /// Push-to-talk sessions created through the WHIP endpoint
+    whip_sessions: Arc<whip::WhipSessions>,
+    /// On-demand HLS packager for viewers without WebRTC
+    hls_packager: Arc<hls::HlsPackager>,
+    /// MJPEG fanout for the `/api/stream.mjpeg` fallback stream
+    mjpeg_fanout: Arc<MjpegFanout>,
+    /// Embedded TURN relay (if enabled)
+    turn_server: Option<Arc<turn_server::TurnServer>>,
+    /// Users authenticated by the reverse proxy
+    auth: Arc<auth::Auth>,
+    /// All active WebRTC sessions (intercom, WHEP, WHIP)
+    sessions: Arc<sessions::SessionManager>,
+    /// Intercom sessions whose WebSocket dropped, waiting to be resumed
+    intercom_sessions: Arc<resume::ResumableSessions<IntercomSession>>,
+    /// When the server started (for uptime)
+    started: std::time::Instant,
+    /// Readiness state for `/readyz`
+    health: Arc<health::Health>,
+    /// Re-reads the configuration on SIGHUP / `POST /api/admin/reload`
+    reloader: Arc<reload::Reloader>,
+    /// Cancelled on SIGTERM/Ctrl-C; long-lived tasks and streams stop on it
+    shutdown: tokio_util::sync::CancellationToken,
+    /// Directory of announcement clips (if configured)
+    announce_dir: Option<std::path::PathBuf>,
+    /// Records the door for automation rules (if `recording.dir` is configured)
+    recorder: Option<Arc<recorder::Recorder>>,
+    /// Automation rules and the away flag
+    automation: Arc<automation::Automation>,
+}
+
+impl AppState {
+    /// STUN/TURN servers for a new client session, including freshly minted
+    /// credentials for the embedded TURN relay if the client is authenticated
+    fn client_ice_servers(&self, client: &sessions::ClientInfo) -> Vec<ice_servers::IceServer> {
+        let mut servers = self.webrtc_infra.ice_servers();
+        if let (Some(turn), Some(user)) = (&self.turn_server, &client.user) {
+            debug!("Minting TURN credentials for {}", user);
+            match turn.ice_server() {
+                Ok(server) => servers.push(server),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+        servers
+    }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Load .env file if present (for development)
     if dotenvy::dotenv().is_ok() {
         info!("Loaded .env file");
@@ -144,50 +233,90 @@ async fn main() {
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    // Read DoorBird configuration from environment
-    let doorbird_url = std::env::var("BIRDBOX_DOORBIRD_URL")
-        .expect("BIRDBOX_DOORBIRD_URL environment variable must be set");
-    let doorbird_user = std::env::var("BIRDBOX_DOORBIRD_USER")
-        .expect("BIRDBOX_DOORBIRD_USER environment variable must be set");
-    let doorbird_password = std::env::var("BIRDBOX_DOORBIRD_PASSWORD")
-        .expect("BIRDBOX_DOORBIRD_PASSWORD environment variable must be set");
-
-    // Read video configuration from environment
-    let video_buffer_frames = std::env::var("BIRDBOX_VIDEO_FANOUT_BUFFER_FRAMES")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(4); // Default to 4 frames if not set or invalid
+    let cli = cli::Cli::parse();
+    match cli.command {
+        Some(cli::Command::Config(cli::ConfigCommand::Check)) => {
+            return cli::config_check(cli.config.as_deref());
+        }
+        Some(cli::Command::Device(command)) => {
+            return cli::device(command, cli.config.as_deref()).await;
+        }
+        None => {}
+    }
+
+    // Configuration file + BIRDBOX_* environment overrides
+    let config = match config::Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match &config.source {
+        Some(path) => info!("Loaded configuration from {}", path.display()),
+        None => info!("No configuration file, using environment variables"),
+    }
+
+    // Cancelled on SIGTERM/Ctrl-C (see shutdown.rs)
+    let shutdown_token = tokio_util::sync::CancellationToken::new();
+
+    let video_buffer_frames = config.video.fanout_buffer_frames;
     info!("Video fanout buffer size: {} frames", video_buffer_frames);
 
-    // Create DoorBird client
+    // Create DoorBird client (request counts and latencies go to /metrics)
+    let doorbird_url = config.doorbird.url.clone();
     let doorbird_client = doorbird::Client::new(
         doorbird_url.clone(),
-        doorbird_user.clone(),
-        doorbird_password.clone(),
-    );
+        config.doorbird.user.clone(),
+        config.doorbird.password.clone(),
+    )
+    .with_observer(Arc::new(metrics::DoorbirdObserver));
+
+    // Initialise ffmpeg up front so /readyz can report a broken installation
+    let health = Arc::new(health::Health::new(
+        std::time::Duration::from_secs(config.health.doorbird_timeout_secs),
+        ffmpeg_next::init().map_err(|e| format!("Failed to initialize ffmpeg: {}", e)),
+    ));
 
-    // Spawn background task to monitor DoorBird events
+    // Spawn background task to monitor DoorBird events (passed on to the automation rules)
+    let (monitor_events_tx, monitor_events_rx) = tokio::sync::mpsc::unbounded_channel();
     let monitor_client = doorbird_client.clone();
-    tokio::spawn(async move {
+    let monitor_health = health.clone();
+    let monitor_task = tokio::spawn(async move {
         loop {
             info!("DoorBird event monitor connecting...");
 
             match monitor_client.monitor_events().await {
                 Ok(mut event_stream) => {
                     info!("DoorBird event monitor connected");
+                    monitor_health.set_event_monitor_connected(true);
 
                     // Process events as they arrive
                     while let Some(event_result) = event_stream.next().await {
                         match event_result {
                             Ok(doorbird::MonitorEvent::Doorbell) => {
                                 info!("🔔 DoorBird event: Doorbell pressed!");
+                                metrics::METRICS
+                                    .monitor_events
+                                    .inc(&[("event", "doorbell")]);
+                                let _ = monitor_events_tx.send(doorbird::MonitorEvent::Doorbell);
                             }
                             Ok(doorbird::MonitorEvent::MotionSensor { active }) => {
+                                metrics::METRICS.monitor_events.inc(&[(
+                                    "event",
+                                    if active {
+                                        "motion_detected"
+                                    } else {
+                                        "motion_cleared"
+                                    },
+                                )]);
                                 if active {
                                     warn!("👁️  DoorBird event: Motion detected!");
                                 } else {
                                     info!("DoorBird event: Motion cleared");
                                 }
+                                let _ = monitor_events_tx
+                                    .send(doorbird::MonitorEvent::MotionSensor { active });
                             }
                             Err(e) => {
                                 warn!("DoorBird event stream error: {:#}", e);
@@ -196,6 +325,7 @@ async fn main() {
                         }
                     }
 
+                    monitor_health.set_event_monitor_connected(false);
                     warn!("DoorBird event monitor disconnected, reconnecting in 5s...");
                 }
                 Err(e) => {
@@ -255,15 +385,12 @@ async fn main() {
         doorbird::VideoQuality::Default
     };
 
-    // Get RTSP URL for video streaming
-    let rtsp_url = doorbird_client.video_receive(video_quality);
-    info!("RTSP URL configured for video streaming");
+    // Simulcast: when enabled and the device supports a higher resolution, the
+    // default resolution is offered as an additional low tier
+    let simulcast_enabled = config.video.simulcast;
 
     // Create audio fanout system with configurable buffer size
-    let audio_buffer_samples = std::env::var("BIRDBOX_AUDIO_FANOUT_BUFFER_SAMPLES")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(20); // Default to 20 samples (~400ms) if not set or invalid
+    let audio_buffer_samples = config.audio.fanout_buffer_samples;
     info!(
         "Audio fanout buffer size: {} samples (~{}ms)",
         audio_buffer_samples,
@@ -271,54 +398,250 @@ async fn main() {
     );
     let audio_fanout = AudioFanout::new(doorbird_client.clone(), audio_buffer_samples);
 
-    // Read RTSP transport protocol configuration
-    let rtsp_transport = std::env::var("BIRDBOX_RTSP_TRANSPORT_PROTOCOL")
-        .unwrap_or_else(|_| "udp".to_string())
-        .to_lowercase();
-
-    // Validate and normalize the transport protocol
-    let rtsp_transport = match rtsp_transport.as_str() {
-        "tcp" => {
-            info!("Using TCP transport for RTSP (more reliable for VPN/Docker scenarios)");
-            "tcp"
+    // RTSP transport protocol for the DoorBird video stream
+    let rtsp_transport = config.video.rtsp_transport.as_str();
+    match config.video.rtsp_transport {
+        config::RtspTransport::Tcp => {
+            info!("Using TCP transport for RTSP (more reliable for VPN/Docker scenarios)")
         }
-        _ => {
-            info!("Using UDP transport for RTSP (lower latency for simple networks)");
-            "udp"
+        config::RtspTransport::Udp => {
+            info!("Using UDP transport for RTSP (lower latency for simple networks)")
         }
-    };
+    }
 
-    // Create video fanout system with configurable buffer size
-    let video_fanout = VideoFanout::new(rtsp_url, video_buffer_frames, rtsp_transport);
+    // Create video fanout system(s) with configurable buffer size
+    // The high tier always uses the best supported resolution; the low tier reuses the
+    // device default stream and only connects while someone is watching it.
+    let high_fanout = VideoFanout::new(
+        doorbird_client.video_receive(video_quality),
+        video_buffer_frames,
+        rtsp_transport,
+    );
+    let low_fanout = if simulcast_enabled && video_quality != doorbird::VideoQuality::Default {
+        info!("Simulcast enabled: offering default resolution as low video tier");
+        Some(VideoFanout::new(
+            doorbird_client.video_receive(doorbird::VideoQuality::Default),
+            video_buffer_frames,
+            rtsp_transport,
+        ))
+    } else {
+        info!("Simulcast disabled or unavailable: single video tier");
+        None
+    };
+    let video_tiers = VideoTiers::new(high_fanout, low_fanout);
+    info!("RTSP URL(s) configured for video streaming");
 
     // Initialize shared WebRTC infrastructure (UDP mux on port 50000)
-    let webrtc_infra = webrtc::WebRtcInfra::new()
+    let webrtc_infra = webrtc::WebRtcInfra::new(&config.webrtc, &config.ice)
         .await
         .expect("Failed to initialize WebRTC infrastructure");
 
+    // Optional RTSP re-streaming server for NVRs (disabled unless a port is set)
+    if let Some(rtsp_port) = config.rtsp_server.port {
+        let credentials = match (&config.rtsp_server.user, &config.rtsp_server.password) {
+            (Some(username), Some(password)) => Some(rtsp_server::RtspCredentials {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        };
+        let rtsp_addr = SocketAddr::from(([0, 0, 0, 0], rtsp_port));
+        if let Err(e) = rtsp_server::RtspServer::start(
+            rtsp_addr,
+            audio_fanout.clone(),
+            video_tiers.clone(),
+            credentials,
+            shutdown_token.clone(),
+        )
+        .await
+        {
+            error!("Failed to start RTSP server: {:#}", e);
+        }
+    }
+
+    // Optional embedded TURN relay (disabled unless a port is set)
+    let turn_server = match turn_server::TurnConfig::from_config(&config.turn, &config.webrtc) {
+        Ok(Some(config)) => match turn_server::TurnServer::start(config).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                error!("Failed to start TURN server: {:#}", e);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            error!("Invalid TURN server configuration: {:#}", e);
+            None
+        }
+    };
+
+    // HLS output for viewers that can't use WebRTC (packages on demand)
+    info!(
+        "LL-HLS output: {}s target segments, {}ms parts",
+        config.hls.segment_secs, config.hls.part_ms
+    );
+    let hls_packager = hls::HlsPackager::new(
+        config.hls.segment_secs,
+        config.hls.part_ms,
+        audio_fanout.clone(),
+        video_tiers.get(VideoTier::High),
+        shutdown_token.clone(),
+    );
+
+    // Recordings for automation rules
+    let recorder = config.recording.dir.clone().map(|dir| {
+        info!("Recordings go to {}", dir.display());
+        recorder::Recorder::new(
+            dir,
+            audio_fanout.clone(),
+            video_tiers.get(VideoTier::High),
+            shutdown_token.clone(),
+        )
+    });
+
+    // MJPEG fallback stream, proxied from the DoorBird and shared by all viewers
+    let mjpeg_max_fps = config.mjpeg.max_fps;
+    info!("MJPEG stream limited to {} fps", mjpeg_max_fps);
+    let mjpeg_fanout = MjpegFanout::new(doorbird_client.clone(), mjpeg_max_fps);
+
+    // Intercom sessions survive a dropped WebSocket for this long (0 disables resuming)
+    let resume_timeout = std::time::Duration::from_secs(config.webrtc.session_resume_secs);
+    info!(
+        "Intercom sessions resumable for {}s after the WebSocket drops",
+        resume_timeout.as_secs()
+    );
+
     // Create PTT state manager
     let ptt_state = Arc::new(PttState::new());
 
     let state = AppState {
         audio_fanout,
-        video_fanout,
+        video_tiers,
         webrtc_infra,
         ptt_state,
         doorbird_client,
+        whep_sessions: Arc::new(whep::WhepSessions::default()),
+        whip_sessions: Arc::new(whip::WhipSessions::default()),
+        hls_packager,
+        mjpeg_fanout,
+        turn_server,
+        auth: Arc::new(auth::Auth::new(&config.auth)),
+        sessions: sessions::SessionManager::new(),
+        intercom_sessions: Arc::new(resume::ResumableSessions::new(resume_timeout)),
+        started: std::time::Instant::now(),
+        health,
+        reloader: Arc::new(reload::Reloader::new(cli.config.clone(), config.clone())),
+        shutdown: shutdown_token.clone(),
+        announce_dir: config.announce.dir.clone(),
+        recorder,
+        automation: Arc::new(automation::Automation::new(&config.automation)),
     };
+    let drain_state = state.clone();
+
+    // Automation rules react to the monitored events
+    info!(
+        "{} automation rule(s), away mode {}{}",
+        config.automation.rules.len(),
+        if config.automation.away { "on" } else { "off" },
+        if config.automation.dry_run {
+            " (dry run)"
+        } else {
+            ""
+        }
+    );
+    tokio::spawn(automation::run(state.clone(), monitor_events_rx));
+
+    // Apply configuration changes on SIGHUP without dropping sessions
+    #[cfg(unix)]
+    reload::spawn_sighup_handler(state.clone());
+
+    // WHEP playback for standard WebRTC players, WHIP push-to-talk ingest and HLS
+    // (CORS so browser-based players on the configured origins can use them)
+    let player_routes = Router::new()
+        .route("/whep", axum::routing::post(whep::create_session))
+        .route(
+            "/whep/{id}",
+            axum::routing::patch(whep::update_session).delete(whep::delete_session),
+        )
+        .route("/whip", axum::routing::post(whip::create_session))
+        .route(
+            "/whip/{id}",
+            axum::routing::patch(whip::update_session).delete(whip::delete_session),
+        )
+        .route("/hls/{file}", get(hls::serve_file))
+        .layer(whep::cors_layer(&config.http.cors_origins));
 
     let app = Router::new()
         .route("/", get(intercom))
         .route("/intercom", get(intercom))
         .route("/ws", get(ws_handler))
         .route("/api/open-gates", axum::routing::post(open_gates))
+        .route(
+            "/api/announce",
+            axum::routing::post(announce::announce).layer(axum::extract::DefaultBodyLimit::max(
+                announce::MAX_UPLOAD_BYTES,
+            )),
+        )
+        .route("/api/announce/clips", get(announce::list_clips))
+        .route("/api/away", axum::routing::post(automation::set_away))
+        .route("/api/automation", get(automation::status))
+        .route("/api/stream.mjpeg", get(stream_mjpeg))
+        .route("/api/sessions", get(list_sessions))
+        .route("/api/status", get(status::status))
+        .route("/api/admin/reload", axum::routing::post(reload::reload))
+        .route("/metrics", get(metrics::metrics))
+        .route("/admin", get(status::admin))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .merge(player_routes)
         .nest_service("/static", ServeDir::new("static"))
         .with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     info!("Listening on http://{}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Connection info lets sessions record the client address. On shutdown the
+    // server stops accepting and finishes in-flight requests.
+    let mut server = tokio::spawn(
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_token.clone().cancelled_owned())
+        .into_future(),
+    );
+
+    tokio::select! {
+        result = &mut server => {
+            error!("HTTP server stopped unexpectedly: {:?}", result);
+            return ExitCode::FAILURE;
+        }
+        _ = shutdown::wait_for_signal() => {}
+    }
+
+    let timeout = std::time::Duration::from_secs(config.shutdown.timeout_secs);
+    info!(
+        "🛑 Shutting down, waiting up to {}s for sessions to end",
+        timeout.as_secs()
+    );
+    shutdown_token.cancel();
+    // Ends the event stream (nothing else depends on it)
+    monitor_task.abort();
+    drain_state.health.set_event_monitor_connected(false);
+    let drained = tokio::time::timeout(timeout, async {
+        shutdown::drain(&drain_state).await;
+        let _ = server.await;
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Shutdown complete"),
+        Err(_) => warn!(
+            "Shutdown did not complete within {}s, exiting anyway",
+            timeout.as_secs()
+        ),
+    }
+
+    ExitCode::SUCCESS
 }
 
 #[derive(Template)]
@@ -358,162 +681,414 @@ async fn open_gates(
     }
 }
 
+/// Multipart boundary separating JPEG frames in the MJPEG response
+const MJPEG_BOUNDARY: &str = "birdboxframe";
+
+/// MJPEG viewer subscription, released when the response body is dropped
+/// (i.e. when the client disconnects)
+struct MjpegSubscription {
+    fanout: Arc<MjpegFanout>,
+    rx: broadcast::Receiver<bytes::Bytes>,
+}
+
+impl Drop for MjpegSubscription {
+    fn drop(&mut self) {
+        let fanout = Arc::clone(&self.fanout);
+        tokio::spawn(async move {
+            fanout.unsubscribe().await;
+        });
+    }
+}
+
+/// `GET /api/stream.mjpeg` - live video as multipart JPEG for `<img>` tags and dashboards
+async fn stream_mjpeg(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> impl IntoResponse {
+    let subscription = MjpegSubscription {
+        rx: state.mjpeg_fanout.subscribe().await,
+        fanout: state.mjpeg_fanout.clone(),
+    };
+
+    let parts = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        loop {
+            match subscription.rx.recv().await {
+                Ok(frame) => {
+                    let mut part = format!(
+                        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                        MJPEG_BOUNDARY,
+                        frame.len()
+                    )
+                    .into_bytes();
+                    part.extend_from_slice(&frame);
+                    part.extend_from_slice(b"\r\n");
+                    return Some((Ok::<_, std::convert::Infallible>(part), subscription));
+                }
+                // Slow viewer: skip to the newest frame
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    // End the body on shutdown so the server can finish this request
+    let parts = parts.take_until(state.shutdown.clone().cancelled_owned());
+
+    (
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={}", MJPEG_BOUNDARY),
+            ),
+            (axum::http::header::CACHE_CONTROL, "no-cache".to_string()),
+        ],
+        axum::body::Body::from_stream(parts),
+    )
+}
+
+/// Query parameters of the signaling WebSocket
+#[derive(Debug, Deserialize)]
+struct WsParams {
+    /// Resume token of a session whose WebSocket dropped
+    resume: Option<String>,
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+    axum::extract::Query(params): axum::extract::Query<WsParams>,
     axum::extract::State(state): axum::extract::State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+) -> axum::response::Response {
+    if state.shutdown.is_cancelled() {
+        return (
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            "server shutting down",
+        )
+            .into_response();
+    }
+    let client = sessions::ClientInfo::new(addr, &headers, state.auth.user(addr, &headers));
+    ws.on_upgrade(move |socket| handle_socket(socket, state, client, params.resume))
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
-    // Generate unique session ID
-    let session_id = Uuid::new_v4();
-    info!("New WebSocket connection: session {}", session_id);
+/// An intercom session, which outlives its WebSocket while it can be resumed
+struct IntercomSession {
+    id: Uuid,
+    /// Secret the client presents to resume the session
+    resume_token: String,
+    webrtc: Arc<webrtc::WebRtcSession>,
+    /// Messages for the client; drained by whichever WebSocket is attached
+    outbox: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message>>>,
+}
+
+/// Why a WebSocket connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WsEnd {
+    /// The client sent a close frame (page closed or reloaded)
+    Closed,
+    /// The connection dropped without a close frame (network change)
+    Dropped,
+    /// The session ended on the server (peer connection failed or closed)
+    SessionEnded,
+    /// The server is shutting down
+    Shutdown,
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    client: sessions::ClientInfo,
+    resume_token: Option<String>,
+) {
+    let (mut sink, mut ws_rx) = socket.split();
+    let ice_servers = state.client_ice_servers(&client);
+
+    // Pick up a parked session, or start a new one
+    let resumed = match &resume_token {
+        Some(token) => state.intercom_sessions.resume(token).await,
+        None => None,
+    };
+    let is_resumed = resumed.is_some();
+    let intercom = match resumed {
+        Some(intercom) => {
+            info!("WebSocket reconnected: resuming session {}", intercom.id);
+            state.sessions.update_client(intercom.id, client).await;
+            intercom
+        }
+        None => {
+            if resume_token.is_some() {
+                info!("Resume token unknown or expired, starting a new session");
+            }
+            match create_intercom_session(&state, client).await {
+                Ok(intercom) => intercom,
+                Err(e) => {
+                    error!("failed to create WebRTC session: {:#}", e);
+                    let error = SignalError::new(ErrorCode::MediaUnavailable, format!("{:#}", e));
+                    let _ = sink.send(error.to_message().to_ws()).await;
+                    let _ = sink.send(Message::Close(None)).await;
+                    return;
+                }
+            }
+        }
+    };
+    let session_id = intercom.id;
+    let session = intercom.webrtc.clone();
+    let cancel = session.cancellation_token();
 
-    let (ws_tx, mut ws_rx) = {
-        let (mut sender, receiver) = socket.split();
-        let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Message>();
+    // Handshake: tell the client which STUN/TURN servers to use before it creates
+    // its peer connection and sends the offer, and how to resume the session. A
+    // resumed client keeps its peer connection and restarts ICE instead.
+    let hello = SignalMessage::Hello {
+        protocol_version: signaling::PROTOCOL_VERSION,
+        ice_servers,
+        session_id,
+        resume_token: intercom.resume_token.clone(),
+        resumed: is_resumed,
+    };
+    if let Err(e) = sink.send(hello.to_ws()).await {
+        error!("ws send error: {}", e);
+    }
 
+    // Forward the session's messages to this WebSocket until the connection ends
+    let connection_done = tokio_util::sync::CancellationToken::new();
+    let writer = {
+        let outbox = intercom.outbox.clone();
+        let done = connection_done.clone();
         tokio::spawn(async move {
-            while let Some(msg) = out_rx.recv().await {
-                if let Err(e) = sender.send(msg).await {
+            // Released by the previous connection's writer when it stopped
+            let mut outbox = outbox.lock().await;
+            loop {
+                let msg = tokio::select! {
+                    msg = outbox.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    _ = done.cancelled() => break,
+                };
+                if let Err(e) = sink.send(msg).await {
                     error!("ws send error: {}", e);
                     break;
                 }
             }
-        });
-
-        (out_tx, receiver)
+            sink
+        })
     };
 
     // Subscribe to PTT state changes
     let mut ptt_state_rx = state.ptt_state.subscribe();
-    let ws_tx_for_ptt = ws_tx.clone();
+    let session_for_ptt = session.clone();
 
     // Spawn task to forward PTT state changes to this client
     let ptt_forward_task = tokio::spawn(async move {
         while let Ok(ptt_msg) = ptt_state_rx.recv().await {
-            let json = serde_json::json!({
-                "type": "ptt_state",
-                "transmitting": ptt_msg.transmitting,
-            });
-            let _ = ws_tx_for_ptt.send(Message::Text(json.to_string().into()));
+            let msg = SignalMessage::PttState {
+                transmitting: ptt_msg.transmitting,
+            };
+            session_for_ptt.send_signal(&msg);
         }
     });
 
-    let session = match webrtc::WebRtcSession::new(
+    // Send initial PTT state
+    let initial_transmitting = state.ptt_state.is_transmitting().await;
+    let initial_state_msg = SignalMessage::PttState {
+        transmitting: initial_transmitting,
+    };
+    session.send_signal(&initial_state_msg);
+
+    // Send initial video tier and the tiers this server can offer
+    session.send_signal(&session.video_tier_message());
+
+    // Process incoming signaling messages until the WebSocket closes or the
+    // session ends (peer connection failed or closed)
+    let end = loop {
+        let msg = tokio::select! {
+            msg = ws_rx.next() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break WsEnd::Dropped,
+            },
+            _ = cancel.cancelled() => break WsEnd::SessionEnded,
+            _ = state.shutdown.cancelled() => break WsEnd::Shutdown,
+        };
+        let result = match msg {
+            Message::Text(txt) => handle_signal_text(&session, &state, session_id, &txt).await,
+            Message::Binary(bin) => match String::from_utf8(bin.to_vec()) {
+                Ok(txt) => handle_signal_text(&session, &state, session_id, &txt).await,
+                Err(e) => Err(SignalError::new(ErrorCode::InvalidMessage, e)),
+            },
+            Message::Close(_) => break WsEnd::Closed,
+            Message::Ping(_) | Message::Pong(_) => Ok(()),
+        };
+        // Tell the client why its request failed
+        if let Err(e) = result {
+            error!("signal handling error (session {}): {}", session_id, e);
+            session.send_signal(&e.to_message());
+        }
+    };
+
+    // The drain may already have ended the session; still tell the client why
+    let end = if end == WsEnd::SessionEnded && state.shutdown.is_cancelled() {
+        WsEnd::Shutdown
+    } else {
+        end
+    };
+
+    // Detach this connection from the session
+    ptt_forward_task.abort();
+    connection_done.cancel();
+    if let Ok(mut sink) = writer.await {
+        match end {
+            WsEnd::SessionEnded => {
+                info!(
+                    "Session {} ended by the server, closing WebSocket",
+                    session_id
+                );
+                let _ = sink.send(Message::Close(None)).await;
+            }
+            WsEnd::Shutdown => {
+                info!("Server shutting down, closing session {}", session_id);
+                let msg = SignalMessage::Shutdown {
+                    reason: "server shutting down".to_string(),
+                };
+                let _ = sink.send(msg.to_ws()).await;
+                let _ = sink.send(Message::Close(None)).await;
+            }
+            WsEnd::Closed | WsEnd::Dropped => {}
+        }
+    }
+
+    let resumable = state.intercom_sessions.enabled() && !state.shutdown.is_cancelled();
+    if end == WsEnd::Dropped && resumable {
+        park_intercom_session(state, intercom).await;
+    } else {
+        info!("WebSocket closed, cleaning up session {}", session_id);
+        close_intercom_session(&state, intercom).await;
+    }
+}
+
+/// Create a WebRTC session for a new intercom client
+async fn create_intercom_session(
+    state: &AppState,
+    client: sessions::ClientInfo,
+) -> anyhow::Result<IntercomSession> {
+    // Generate unique session ID
+    let session_id = Uuid::new_v4();
+    info!("New WebSocket connection: session {}", session_id);
+
+    let (outbox_tx, outbox_rx) = mpsc::unbounded_channel::<Message>();
+    let session = webrtc::WebRtcSession::new(
         state.webrtc_infra.clone(),
-        ws_tx.clone(),
+        Some(outbox_tx),
         state.audio_fanout.clone(),
-        state.video_fanout.clone(),
+        state.video_tiers.clone(),
         state.ptt_state.clone(),
         state.doorbird_client.clone(),
         session_id,
     )
-    .await
-    {
-        Ok(s) => s,
-        Err(e) => {
-            error!("failed to create WebRTC session: {:#}", e);
-            return;
-        }
-    };
+    .await?;
 
-    // Send initial PTT state
-    let initial_transmitting = state.ptt_state.is_transmitting().await;
-    let initial_state_msg = serde_json::json!({
-        "type": "ptt_state",
-        "transmitting": initial_transmitting,
-    });
-    let _ = ws_tx.send(Message::Text(initial_state_msg.to_string().into()));
-
-    // Process incoming signaling messages
-    while let Some(Ok(msg)) = ws_rx.next().await {
-        match msg {
-            Message::Text(txt) => {
-                if let Err(e) = handle_signal_text(&session, &state, session_id, &txt).await {
-                    error!("signal handling error: {:#}", e);
-                }
-            }
-            Message::Binary(bin) => {
-                if let Ok(txt) = String::from_utf8(bin.to_vec()) {
-                    handle_signal_text(&session, &state, session_id, &txt)
-                        .await
-                        .unwrap_or_else(|e| {
-                            error!("signal handling error: {:#}", e);
-                        });
-                }
-            }
-            Message::Close(_) => break,
-            Message::Ping(_) | Message::Pong(_) => {}
+    state
+        .sessions
+        .register(
+            session_id,
+            sessions::SessionKind::Intercom,
+            client,
+            session.cancellation_token(),
+            session.pc.clone(),
+            session.traffic(),
+        )
+        .await;
+
+    Ok(IntercomSession {
+        id: session_id,
+        resume_token: Uuid::new_v4().simple().to_string(),
+        webrtc: Arc::new(session),
+        outbox: Arc::new(tokio::sync::Mutex::new(outbox_rx)),
+    })
+}
+
+/// Keep a session whose WebSocket dropped until the client resumes it, the
+/// timeout passes, or its peer connection ends
+async fn park_intercom_session(state: AppState, intercom: IntercomSession) {
+    let session_id = intercom.id;
+    let token = intercom.resume_token.clone();
+    let cancel = intercom.webrtc.cancellation_token();
+    let timeout = state.intercom_sessions.timeout();
+    info!(
+        "WebSocket dropped, session {} can be resumed for {}s",
+        session_id,
+        timeout.as_secs()
+    );
+    let generation = state.intercom_sessions.park(token.clone(), intercom).await;
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => {}
+            _ = cancel.cancelled() => {}
         }
-    }
+        if let Some(intercom) = state.intercom_sessions.expire(&token, generation).await {
+            info!("Session {} was not resumed, cleaning up", session_id);
+            close_intercom_session(&state, intercom).await;
+        }
+    });
+}
+
+/// Tear an intercom session down and release everything it holds
+async fn close_intercom_session(state: &AppState, intercom: IntercomSession) {
+    let session_id = intercom.id;
 
-    // Clean up WebRTC connection when WebSocket closes
-    info!("WebSocket closed, cleaning up session {}", session_id);
+    // Stop streaming and close the peer connection (the shared UDP mux stays open)
+    intercom.webrtc.close().await;
 
     // Release PTT if this session had it
     state.ptt_state.release(session_id).await;
 
-    // Stop PTT forward task
-    ptt_forward_task.abort();
+    state.sessions.remove(session_id).await;
+    info!("Session {} cleanup complete", session_id);
+}
 
-    // Don't explicitly close the peer connection to avoid affecting the shared UDP mux
-    // The peer connection will be garbage collected when the session is dropped
-    info!(
-        "Session {} cleanup complete, peer connection will be garbage collected",
-        session_id
-    );
+/// `GET /api/sessions` - active WebRTC sessions
+async fn list_sessions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> impl IntoResponse {
+    axum::Json(state.sessions.list().await)
 }
 
 /// Handle WebRTC signaling messages from the client
 ///
-/// Processes JSON messages for:
+/// Processes `SignalMessage`s for:
 /// - SDP offer/answer exchange
-/// - ICE candidate exchange  
+/// - ICE candidate exchange
 /// - Push-to-talk control (start/stop)
+/// - Video quality tier selection
+///
+/// Failures are returned as `SignalError` so the caller can reply with an `error`
+/// message.
 async fn handle_signal_text(
     session: &webrtc::WebRtcSession,
     state: &AppState,
     session_id: Uuid,
     json_text: &str,
-) -> anyhow::Result<()> {
-    let signal_msg: serde_json::Value = serde_json::from_str(json_text)?;
-    let msg_type = signal_msg
-        .get("type")
-        .and_then(|t| t.as_str())
-        .unwrap_or("");
-    match msg_type {
-        "offer" => {
-            let sdp = signal_msg
-                .get("sdp")
-                .and_then(|s| s.as_str())
-                .unwrap_or("")
-                .to_string();
-            info!("received client offer, creating answer...");
-            let answer = session.set_remote_offer_and_create_answer(sdp).await?;
+) -> Result<(), SignalError> {
+    match signaling::parse(json_text)? {
+        SignalMessage::Offer { sdp, ice_restart } => {
+            // A resumed client restarts ICE over its new network path; webrtc-rs
+            // restarts the ICE agent when the offer carries new credentials
+            if ice_restart {
+                info!("received ICE restart offer from session {}", session_id);
+            } else {
+                info!("received client offer, creating answer...");
+            }
+            let answer = session
+                .set_remote_offer_and_create_answer(sdp)
+                .await
+                .map_err(|e| SignalError::new(ErrorCode::InvalidSdp, format!("{:#}", e)))?;
             info!("sending answer to client");
-            let msg = serde_json::json!({
-                "type": "answer",
-                "sdp": answer.sdp,
-            });
-            let _ = session.ws_out.send(Message::Text(msg.to_string().into()));
+            session.send_signal(&SignalMessage::Answer { sdp: answer.sdp });
         }
-        "candidate" => {
-            let candidate = signal_msg
-                .get("candidate")
-                .and_then(|c| c.as_str())
-                .unwrap_or("")
-                .to_string();
-            let sdp_mid = signal_msg
-                .get("sdpMid")
-                .and_then(|m| m.as_str())
-                .map(|s| s.to_string());
-            let sdp_mline_index = signal_msg
-                .get("sdpMLineIndex")
-                .and_then(|i| i.as_u64())
-                .map(|u| u as u16);
-
+        SignalMessage::Candidate {
+            candidate,
+            sdp_mid,
+            sdp_mline_index,
+        } => {
             // Log mDNS candidates at debug level to reduce spam (they're ignored anyway)
             if candidate.contains(".local") {
                 debug!(
@@ -528,32 +1103,47 @@ async fn handle_signal_text(
             }
             session
                 .add_ice_candidate(candidate, sdp_mid, sdp_mline_index)
-                .await?;
+                .await
+                .map_err(|e| SignalError::new(ErrorCode::InvalidCandidate, format!("{:#}", e)))?;
         }
-        "start_ptt" => {
+        SignalMessage::StartPtt => {
             info!("PTT start requested by session {}", session_id);
             if state.ptt_state.try_acquire(session_id).await {
                 info!("PTT granted to session {}", session_id);
-                session.start_ptt().await?;
-                let msg = serde_json::json!({
-                    "type": "ptt_granted",
-                });
-                let _ = session.ws_out.send(Message::Text(msg.to_string().into()));
+                if let Err(e) = session.start_ptt().await {
+                    // Don't hold the lock for a session that can't talk
+                    state.ptt_state.release(session_id).await;
+                    return Err(SignalError::new(ErrorCode::PttFailed, format!("{:#}", e)));
+                }
+                session.send_signal(&SignalMessage::PttGranted);
             } else {
                 warn!("PTT denied to session {} - already in use", session_id);
-                let msg = serde_json::json!({
-                    "type": "ptt_denied",
-                    "reason": "another_user",
-                });
-                let _ = session.ws_out.send(Message::Text(msg.to_string().into()));
+                let msg = SignalMessage::PttDenied {
+                    reason: "another_user".to_string(),
+                };
+                session.send_signal(&msg);
             }
         }
-        "stop_ptt" => {
+        SignalMessage::StopPtt => {
             info!("PTT stop requested by session {}", session_id);
             session.stop_ptt().await;
             state.ptt_state.release(session_id).await;
         }
-        _ => {}
+        SignalMessage::SetVideoTier { tier: requested } => {
+            let tier = session.set_video_tier(requested);
+            info!(
+                "Session {} requested video tier {}, using {}",
+                session_id,
+                requested.as_str(),
+                tier.as_str()
+            );
+        }
+        other => {
+            return Err(SignalError::new(
+                ErrorCode::UnexpectedMessage,
+                format!("unexpected message from client: {:?}", other),
+            ));
+        }
     }
     Ok(())
 }
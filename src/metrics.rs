@@ -0,0 +1,578 @@
+//! Prometheus metrics
+//!
+//! Counters and histograms live in the global `METRICS` so the fanouts, transcoders
+//! and WebRTC tasks can record events without threading a handle through every
+//! constructor. Gauges (fanout connections and subscribers, active sessions, PTT)
+//! are read from `AppState` when `GET /metrics` is scraped.
+//!
+//! Rendered in the Prometheus text exposition format; there is no client library
+//! dependency, the handful of metric types needed here are implemented below.
+
+use crate::sessions::SessionKind;
+use crate::AppState;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Bucket bounds (seconds) for DoorBird HTTP request latency
+const REQUEST_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Bucket bounds (seconds) for push-to-talk durations
+const PTT_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// All recorded metrics
+pub static METRICS: Metrics = Metrics::new();
+
+/// Monotonic counter
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Media track of a WebRTC session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Track {
+    Audio,
+    Video,
+    /// Push-to-talk audio received from the browser
+    Ptt,
+}
+
+impl Track {
+    const ALL: [Track; 3] = [Track::Audio, Track::Video, Track::Ptt];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Track::Audio => "audio",
+            Track::Video => "video",
+            Track::Ptt => "ptt",
+        }
+    }
+}
+
+/// Counter per track, without locking or allocating (counted for every packet)
+pub struct TrackCounter([Counter; Track::ALL.len()]);
+
+impl TrackCounter {
+    const fn new() -> Self {
+        Self([Counter::new(), Counter::new(), Counter::new()])
+    }
+
+    pub fn add(&self, track: Track, n: u64) {
+        self.0[track as usize].add(n);
+    }
+
+    fn get(&self, track: Track) -> u64 {
+        self.0[track as usize].get()
+    }
+}
+
+/// Counter with label values, e.g. `{fanout="audio"}`
+pub struct LabeledCounter {
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LabeledCounter {
+    const fn new() -> Self {
+        Self {
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: &[(&str, &str)]) {
+        self.add(labels, 1);
+    }
+
+    pub fn add(&self, labels: &[(&str, &str)], n: u64) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        *values.entry(format_labels(labels)).or_default() += n;
+    }
+}
+
+struct HistogramData {
+    /// Non-cumulative count per bucket, plus one for `+Inf`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Histogram with label values
+pub struct Histogram {
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<String, HistogramData>>,
+}
+
+impl Histogram {
+    const fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: &[(&str, &str)], value: Duration) {
+        let value = value.as_secs_f64();
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let data = values
+            .entry(format_labels(labels))
+            .or_insert_with(|| HistogramData {
+                buckets: vec![0; self.bounds.len() + 1],
+                sum: 0.0,
+                count: 0,
+            });
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        data.buckets[bucket] += 1;
+        data.sum += value;
+        data.count += 1;
+    }
+}
+
+/// Everything recorded outside of `AppState`
+pub struct Metrics {
+    /// Connection attempts to the DoorBird, by fanout
+    pub fanout_connects: LabeledCounter,
+    /// Failed DoorBird streams, by fanout
+    pub fanout_errors: LabeledCounter,
+    /// Broadcast lag events in the per-session stream tasks, by track
+    pub broadcast_lag_events: TrackCounter,
+    /// Samples skipped because of broadcast lag, by track
+    pub broadcast_lag_skipped: TrackCounter,
+    /// Media samples sent (`audio`, `video`) or RTP packets received (`ptt`), by track
+    pub track_packets: TrackCounter,
+    /// Media payload bytes, by track
+    pub track_bytes: TrackCounter,
+    pub opus_encode_errors: Counter,
+    pub opus_decode_errors: Counter,
+    /// Push-to-talk lock hold times
+    pub ptt_duration: Histogram,
+    /// Push-to-talk frames the jitter buffer concealed or discarded, by reason
+    pub ptt_jitter_frames: LabeledCounter,
+    /// Lost push-to-talk frames replaced by the Opus decoder, by method (`fec`, `plc`)
+    pub ptt_concealed_frames: LabeledCounter,
+    /// DoorBird monitor events, by event
+    pub monitor_events: LabeledCounter,
+    /// DoorBird HTTP requests, by endpoint and status
+    pub doorbird_requests: LabeledCounter,
+    /// DoorBird HTTP request latency, by endpoint
+    pub doorbird_request_duration: Histogram,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            fanout_connects: LabeledCounter::new(),
+            fanout_errors: LabeledCounter::new(),
+            broadcast_lag_events: TrackCounter::new(),
+            broadcast_lag_skipped: TrackCounter::new(),
+            track_packets: TrackCounter::new(),
+            track_bytes: TrackCounter::new(),
+            opus_encode_errors: Counter::new(),
+            opus_decode_errors: Counter::new(),
+            ptt_duration: Histogram::new(PTT_BUCKETS),
+            ptt_jitter_frames: LabeledCounter::new(),
+            ptt_concealed_frames: LabeledCounter::new(),
+            monitor_events: LabeledCounter::new(),
+            doorbird_requests: LabeledCounter::new(),
+            doorbird_request_duration: Histogram::new(REQUEST_BUCKETS),
+        }
+    }
+
+    /// Count a media packet of `bytes` payload on `track`
+    pub fn track_packet(&self, track: Track, bytes: usize) {
+        self.track_packets.add(track, 1);
+        self.track_bytes.add(track, bytes as u64);
+    }
+
+    /// Count a broadcast lag event that skipped `skipped` samples on `track`
+    pub fn broadcast_lag(&self, track: Track, skipped: u64) {
+        self.broadcast_lag_events.add(track, 1);
+        self.broadcast_lag_skipped.add(track, skipped);
+    }
+
+    /// Append all recorded metrics in the text exposition format
+    fn render(&self, out: &mut String) {
+        render_labeled(
+            out,
+            "birdbox_fanout_connects_total",
+            "Connection attempts to the DoorBird per fanout",
+            &self.fanout_connects,
+        );
+        render_labeled(
+            out,
+            "birdbox_fanout_errors_total",
+            "DoorBird streams that ended with an error per fanout",
+            &self.fanout_errors,
+        );
+        render_tracks(
+            out,
+            "birdbox_broadcast_lag_events_total",
+            "Times a session fell behind a fanout broadcast",
+            &self.broadcast_lag_events,
+        );
+        render_tracks(
+            out,
+            "birdbox_broadcast_lag_skipped_total",
+            "Samples skipped by sessions that fell behind",
+            &self.broadcast_lag_skipped,
+        );
+        render_tracks(
+            out,
+            "birdbox_track_packets_total",
+            "WebRTC media samples sent (audio, video) or RTP packets received (ptt)",
+            &self.track_packets,
+        );
+        render_tracks(
+            out,
+            "birdbox_track_bytes_total",
+            "WebRTC media payload bytes sent (audio, video) or received (ptt)",
+            &self.track_bytes,
+        );
+        render_counter(
+            out,
+            "birdbox_opus_encode_errors_total",
+            "Opus encoding failures in the DoorBird audio transcoder",
+            self.opus_encode_errors.get(),
+        );
+        render_counter(
+            out,
+            "birdbox_opus_decode_errors_total",
+            "Opus decoding failures in the push-to-talk transcoder",
+            self.opus_decode_errors.get(),
+        );
+        render_histogram(
+            out,
+            "birdbox_ptt_duration_seconds",
+            "How long the push-to-talk lock was held",
+            &self.ptt_duration,
+        );
+        render_labeled(
+            out,
+            "birdbox_ptt_jitter_frames_total",
+            "Push-to-talk frames lost (concealed), late or dropped by the jitter buffer",
+            &self.ptt_jitter_frames,
+        );
+        render_labeled(
+            out,
+            "birdbox_ptt_concealed_frames_total",
+            "Lost push-to-talk frames recovered from in-band FEC (fec) or concealed (plc)",
+            &self.ptt_concealed_frames,
+        );
+        render_labeled(
+            out,
+            "birdbox_monitor_events_total",
+            "DoorBird monitor events by type",
+            &self.monitor_events,
+        );
+        render_labeled(
+            out,
+            "birdbox_doorbird_requests_total",
+            "DoorBird HTTP requests by endpoint and status (none = no response)",
+            &self.doorbird_requests,
+        );
+        render_histogram(
+            out,
+            "birdbox_doorbird_request_duration_seconds",
+            "DoorBird HTTP time to response headers by endpoint",
+            &self.doorbird_request_duration,
+        );
+    }
+}
+
+/// Records DoorBird HTTP requests
+pub struct DoorbirdObserver;
+
+impl doorbird::RequestObserver for DoorbirdObserver {
+    fn on_request(&self, endpoint: &'static str, status: Option<u16>, elapsed: Duration) {
+        let status = status.map_or_else(|| "none".to_string(), |s| s.to_string());
+        METRICS
+            .doorbird_requests
+            .inc(&[("endpoint", endpoint), ("status", &status)]);
+        METRICS
+            .doorbird_request_duration
+            .observe(&[("endpoint", endpoint)], elapsed);
+    }
+}
+
+/// `{name="value",...}`, empty without labels
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// Insert `extra` (`name="value"`) into formatted labels
+fn with_label(labels: &str, extra: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{},{}}}", labels, extra),
+        None => format!("{{{}}}", extra),
+    }
+}
+
+fn render_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    render_header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn render_labeled(out: &mut String, name: &str, help: &str, counter: &LabeledCounter) {
+    render_header(out, name, help, "counter");
+    let values = counter.values.lock().unwrap_or_else(|e| e.into_inner());
+    for (labels, value) in values.iter() {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+fn render_tracks(out: &mut String, name: &str, help: &str, counter: &TrackCounter) {
+    render_header(out, name, help, "counter");
+    for track in Track::ALL {
+        let labels = format_labels(&[("track", track.as_str())]);
+        let _ = writeln!(out, "{}{} {}", name, labels, counter.get(track));
+    }
+}
+
+fn render_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    render_header(out, name, help, "histogram");
+    let values = histogram.values.lock().unwrap_or_else(|e| e.into_inner());
+    for (labels, data) in values.iter() {
+        let mut cumulative = 0;
+        for (i, count) in data.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = match histogram.bounds.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let bucket_labels = with_label(labels, &format!("le=\"{}\"", le));
+            let _ = writeln!(out, "{}_bucket{} {}", name, bucket_labels, cumulative);
+        }
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, data.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, data.count);
+    }
+}
+
+fn render_gauge(out: &mut String, name: &str, help: &str, values: &[(String, f64)]) {
+    render_header(out, name, help, "gauge");
+    for (labels, value) in values {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// `GET /metrics` - Prometheus scrape endpoint
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut out = String::new();
+    METRICS.render(&mut out);
+
+    // Fanout gauges
+    let mut connected = Vec::new();
+    let mut subscribers = Vec::new();
+    let mut add_fanout = |labels: &[(&str, &str)], is_connected: bool, count: usize| {
+        connected.push((format_labels(labels), if is_connected { 1.0 } else { 0.0 }));
+        subscribers.push((format_labels(labels), count as f64));
+    };
+    add_fanout(
+        &[("fanout", "audio")],
+        state.audio_fanout.is_connected().await,
+        state.audio_fanout.subscriber_count().await,
+    );
+    for (tier, fanout) in state.video_tiers.fanouts() {
+        add_fanout(
+            &[("fanout", "video"), ("tier", tier.as_str())],
+            fanout.is_connected().await,
+            fanout.subscriber_count().await,
+        );
+    }
+    add_fanout(
+        &[("fanout", "mjpeg")],
+        state.mjpeg_fanout.is_connected().await,
+        state.mjpeg_fanout.subscriber_count().await,
+    );
+    render_gauge(
+        &mut out,
+        "birdbox_fanout_connected",
+        "Whether the fanout's DoorBird stream is connected",
+        &connected,
+    );
+    render_gauge(
+        &mut out,
+        "birdbox_fanout_subscribers",
+        "Subscribers per fanout",
+        &subscribers,
+    );
+
+    // Peer connections by kind (zero for kinds without sessions)
+    let counts = state.sessions.count_by_kind().await;
+    let sessions: Vec<(String, f64)> =
+        [SessionKind::Intercom, SessionKind::Whep, SessionKind::Whip]
+            .iter()
+            .map(|kind| {
+                (
+                    format_labels(&[("kind", kind.as_str())]),
+                    counts.get(kind).copied().unwrap_or(0) as f64,
+                )
+            })
+            .collect();
+    render_gauge(
+        &mut out,
+        "birdbox_peer_connections",
+        "Active WebRTC peer connections",
+        &sessions,
+    );
+
+    let transmitting = state.ptt_state.is_transmitting().await;
+    render_gauge(
+        &mut out,
+        "birdbox_ptt_active",
+        "Whether someone holds the push-to-talk lock",
+        &[(String::new(), if transmitting { 1.0 } else { 0.0 })],
+    );
+    render_gauge(
+        &mut out,
+        "birdbox_uptime_seconds",
+        "Seconds since the server started",
+        &[(String::new(), state.started.elapsed().as_secs_f64())],
+    );
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labeled_counter_render() {
+        let counter = LabeledCounter::new();
+        counter.inc(&[("endpoint", "info.cgi"), ("status", "200")]);
+        counter.add(&[("endpoint", "info.cgi"), ("status", "200")], 2);
+        counter.inc(&[("endpoint", "weird\"name")]);
+
+        let mut out = String::new();
+        render_labeled(&mut out, "requests_total", "Requests", &counter);
+        assert_eq!(
+            out,
+            "# HELP requests_total Requests\n\
+             # TYPE requests_total counter\n\
+             requests_total{endpoint=\"info.cgi\",status=\"200\"} 3\n\
+             requests_total{endpoint=\"weird\\\"name\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_track_counter_render() {
+        let counter = TrackCounter::new();
+        counter.add(Track::Video, 2);
+        counter.add(Track::Ptt, 1);
+
+        let mut out = String::new();
+        render_tracks(&mut out, "packets_total", "Packets", &counter);
+        let lines: Vec<&str> = out.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "packets_total{track=\"audio\"} 0",
+                "packets_total{track=\"video\"} 2",
+                "packets_total{track=\"ptt\"} 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doorbird_observer_records_requests() {
+        use doorbird::RequestObserver;
+
+        DoorbirdObserver.on_request("observer-test.cgi", Some(401), Duration::from_millis(30));
+        DoorbirdObserver.on_request("observer-test.cgi", None, Duration::from_secs(20));
+
+        let mut out = String::new();
+        METRICS.render(&mut out);
+        assert!(out.contains(
+            "birdbox_doorbird_requests_total{endpoint=\"observer-test.cgi\",status=\"401\"} 1\n"
+        ));
+        assert!(out.contains(
+            "birdbox_doorbird_requests_total{endpoint=\"observer-test.cgi\",status=\"none\"} 1\n"
+        ));
+        assert!(out.contains(
+            "birdbox_doorbird_request_duration_seconds_bucket{endpoint=\"observer-test.cgi\",le=\"0.05\"} 1\n"
+        ));
+        assert!(out.contains(
+            "birdbox_doorbird_request_duration_seconds_count{endpoint=\"observer-test.cgi\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[1.0, 5.0]);
+        histogram.observe(&[], Duration::from_millis(500));
+        histogram.observe(&[], Duration::from_secs(3));
+        histogram.observe(&[], Duration::from_secs(10));
+
+        let mut out = String::new();
+        render_histogram(&mut out, "ptt_seconds", "PTT", &histogram);
+        let lines: Vec<&str> = out.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "ptt_seconds_bucket{le=\"1\"} 1",
+                "ptt_seconds_bucket{le=\"5\"} 2",
+                "ptt_seconds_bucket{le=\"+Inf\"} 3",
+                "ptt_seconds_sum 13.5",
+                "ptt_seconds_count 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_keeps_labels() {
+        let histogram = Histogram::new(&[0.1]);
+        histogram.observe(&[("endpoint", "info.cgi")], Duration::from_millis(50));
+
+        let mut out = String::new();
+        render_histogram(&mut out, "latency", "Latency", &histogram);
+        assert!(out.contains("latency_bucket{endpoint=\"info.cgi\",le=\"0.1\"} 1\n"));
+        assert!(out.contains("latency_count{endpoint=\"info.cgi\"} 1\n"));
+    }
+}
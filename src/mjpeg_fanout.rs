@@ -0,0 +1,250 @@
+//! MJPEG fanout system for dashboards and devices without WebRTC
+//!
+//! This module implements a fanout queue that:
+//! - Connects to the DoorBird MJPEG stream (`video.cgi`) only when there are active subscribers
+//! - Distributes JPEG frames to multiple HTTP viewers from a single DoorBird connection
+//! - Limits the frame rate sent to viewers to a configurable maximum
+//! - Automatically disconnects after a grace period when all subscribers leave
+
+use crate::metrics::METRICS;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use doorbird::Client as DoorBirdClient;
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// Grace period before disconnecting from DoorBird after last subscriber leaves
+const MJPEG_GRACE_PERIOD_SECS: u64 = 3;
+
+/// Delay before retrying after connection error
+const RECONNECT_DELAY_SECS: u64 = 5;
+
+/// Polling interval for checking subscriber count
+const SUBSCRIBER_POLL_INTERVAL_MS: u64 = 100;
+
+/// Frames buffered per subscriber; slow viewers skip ahead to the newest frame
+const BROADCAST_BUFFER_FRAMES: usize = 2;
+
+/// State of the MJPEG fanout connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    Disconnecting,
+}
+
+/// Shared state for the MJPEG fanout
+struct FanoutState {
+    connection_state: ConnectionState,
+    subscriber_count: usize,
+}
+
+/// MJPEG fanout manager
+///
+/// Manages a single DoorBird MJPEG connection and distributes the JPEG
+/// frames to multiple subscribers (HTTP viewers).
+pub struct MjpegFanout {
+    doorbird_client: DoorBirdClient,
+    /// Changed by configuration reloads
+    max_fps: AtomicU32,
+    broadcast_tx: broadcast::Sender<Bytes>,
+    state: Arc<RwLock<FanoutState>>,
+}
+
+impl MjpegFanout {
+    /// Creates a new MJPEG fanout system
+    ///
+    /// # Arguments
+    /// * `doorbird_client` - Configured DoorBird API client
+    /// * `max_fps` - Maximum frames per second forwarded to viewers
+    pub fn new(doorbird_client: DoorBirdClient, max_fps: u32) -> Arc<Self> {
+        let (broadcast_tx, _) = broadcast::channel(BROADCAST_BUFFER_FRAMES);
+
+        let fanout = Arc::new(Self {
+            doorbird_client,
+            max_fps: AtomicU32::new(max_fps),
+            broadcast_tx,
+            state: Arc::new(RwLock::new(FanoutState {
+                connection_state: ConnectionState::Disconnected,
+                subscriber_count: 0,
+            })),
+        });
+
+        // Start the management task
+        let fanout_clone = Arc::clone(&fanout);
+        tokio::spawn(async move {
+            fanout_clone.manage_connection().await;
+        });
+
+        fanout
+    }
+
+    /// Change the frame rate limit; applies to the running stream immediately
+    pub fn set_max_fps(&self, max_fps: u32) {
+        self.max_fps.store(max_fps, Ordering::Relaxed);
+    }
+
+    fn min_frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.max_fps.load(Ordering::Relaxed).max(1) as f64)
+    }
+
+    /// Subscribe to the MJPEG stream
+    ///
+    /// Returns a receiver that will get complete JPEG images.
+    /// The connection to DoorBird is automatically established when the first
+    /// subscriber joins.
+    pub async fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        let mut state = self.state.write().await;
+        state.subscriber_count += 1;
+        let count = state.subscriber_count;
+        drop(state);
+
+        info!("MJPEG subscriber added (total: {})", count);
+
+        self.broadcast_tx.subscribe()
+    }
+
+    /// Unsubscribe from the MJPEG stream
+    ///
+    /// Should be called when a subscriber is done. The connection to DoorBird
+    /// will be closed after a grace period if this was the last subscriber.
+    pub async fn unsubscribe(&self) {
+        let mut state = self.state.write().await;
+        if state.subscriber_count > 0 {
+            state.subscriber_count -= 1;
+        }
+        let count = state.subscriber_count;
+        drop(state);
+
+        info!("MJPEG subscriber removed (remaining: {})", count);
+    }
+
+    /// Main connection management loop
+    async fn manage_connection(self: Arc<Self>) {
+        loop {
+            // Wait for at least one subscriber
+            loop {
+                let state = self.state.read().await;
+                if state.subscriber_count > 0 {
+                    break;
+                }
+                drop(state);
+                sleep(Duration::from_millis(SUBSCRIBER_POLL_INTERVAL_MS)).await;
+            }
+
+            // Connect and stream
+            info!("Connecting to DoorBird MJPEG stream...");
+            METRICS.fanout_connects.inc(&[("fanout", "mjpeg")]);
+            {
+                let mut state = self.state.write().await;
+                state.connection_state = ConnectionState::Connecting;
+            }
+
+            match self.stream_frames().await {
+                Ok(_) => {
+                    info!("DoorBird MJPEG stream ended normally");
+                }
+                Err(e) => {
+                    error!("DoorBird MJPEG stream error: {:#}", e);
+                    METRICS.fanout_errors.inc(&[("fanout", "mjpeg")]);
+                    // Wait before retry
+                    sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
+                }
+            }
+
+            // Mark as disconnecting
+            {
+                let mut state = self.state.write().await;
+                state.connection_state = ConnectionState::Disconnecting;
+            }
+
+            info!("Disconnected from DoorBird MJPEG stream");
+
+            // Grace period: wait to see if subscribers come back
+            debug!(
+                "Starting {}-second grace period...",
+                MJPEG_GRACE_PERIOD_SECS
+            );
+            sleep(Duration::from_secs(MJPEG_GRACE_PERIOD_SECS)).await;
+
+            // Check if we should reconnect
+            let state = self.state.read().await;
+            if state.subscriber_count > 0 {
+                info!(
+                    "Subscribers still present ({}), reconnecting immediately",
+                    state.subscriber_count
+                );
+                drop(state);
+                continue;
+            } else {
+                info!("No subscribers after grace period, staying disconnected");
+                let mut state_mut = self.state.write().await;
+                state_mut.connection_state = ConnectionState::Disconnected;
+                drop(state_mut);
+            }
+        }
+    }
+
+    /// Stream JPEG frames from DoorBird and broadcast to subscribers
+    async fn stream_frames(&self) -> Result<()> {
+        let mut frames = self
+            .doorbird_client
+            .mjpeg_stream()
+            .await
+            .context("Failed to start DoorBird MJPEG stream")?;
+
+        {
+            let mut state = self.state.write().await;
+            state.connection_state = ConnectionState::Connected;
+        }
+        info!("Successfully connected to DoorBird MJPEG stream");
+
+        let mut last_sent: Option<Instant> = None;
+
+        while let Some(frame_result) = frames.next().await {
+            // Check if we still have subscribers
+            {
+                let state = self.state.read().await;
+                if state.subscriber_count == 0 {
+                    info!("No more subscribers, stopping MJPEG stream");
+                    break;
+                }
+            }
+
+            let frame = frame_result.context("Error receiving MJPEG frame")?;
+
+            // Enforce the max frame rate by dropping frames that arrive too soon
+            if last_sent.is_some_and(|t| t.elapsed() < self.min_frame_interval()) {
+                continue;
+            }
+            last_sent = Some(Instant::now());
+
+            // Send to all subscribers (ignore if no receivers)
+            let _ = self.broadcast_tx.send(frame);
+        }
+
+        Ok(())
+    }
+
+    /// Get current subscriber count
+    ///
+    /// Useful for debugging, monitoring endpoints, or metrics collection.
+    pub async fn subscriber_count(&self) -> usize {
+        let state = self.state.read().await;
+        state.subscriber_count
+    }
+
+    /// Check if currently connected to DoorBird
+    ///
+    /// Useful for debugging, monitoring endpoints, or health checks.
+    pub async fn is_connected(&self) -> bool {
+        let state = self.state.read().await;
+        state.connection_state == ConnectionState::Connected
+    }
+}
@@ -0,0 +1,150 @@
+//! Recordings of the door
+//!
+//! Automation rules (`automation.rs`) can record the DoorBird stream for a while,
+//! e.g. when the doorbell rings while nobody is home. The high video tier and the
+//! audio are written without transcoding to an MP4 file in `recording.dir`, named
+//! after the local start time, using the muxer shared with HLS (`hls::mux`). One recording
+//! runs at a time; asking for another while it runs extends it instead (in a new
+//! file if the extension comes in while the previous one is being finalized).
+
+use crate::audio_fanout::AudioFanout;
+use crate::hls;
+use crate::video_fanout::VideoFanout;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Records the door to MP4 files
+pub struct Recorder {
+    dir: PathBuf,
+    audio_fanout: Arc<AudioFanout>,
+    video_fanout: Arc<VideoFanout>,
+    /// When the running recording ends (`None` when idle)
+    until: Mutex<Option<Instant>>,
+    /// Cancelled when the server shuts down; a running recording is finalized
+    shutdown: CancellationToken,
+}
+
+impl Recorder {
+    /// Creates a recorder (nothing runs until `record`)
+    ///
+    /// # Arguments
+    /// * `dir` - Directory for the recordings (created when needed)
+    /// * `audio_fanout` - Source of Opus audio
+    /// * `video_fanout` - Source of H.264 video
+    /// * `shutdown` - Server shutdown token
+    pub fn new(
+        dir: PathBuf,
+        audio_fanout: Arc<AudioFanout>,
+        video_fanout: Arc<VideoFanout>,
+        shutdown: CancellationToken,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            dir,
+            audio_fanout,
+            video_fanout,
+            until: Mutex::new(None),
+            shutdown,
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.until.lock().unwrap().is_some()
+    }
+
+    /// New file named after the local time
+    fn next_path(&self) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create recording directory {:?}", self.dir))?;
+        let stem = chrono::Local::now()
+            .format("door-%Y%m%d-%H%M%S")
+            .to_string();
+        // A recording continued right after the previous one may start in the same second
+        let mut path = self.dir.join(format!("{}.mp4", stem));
+        let mut n = 2;
+        while path.exists() {
+            path = self.dir.join(format!("{}-{}.mp4", stem, n));
+            n += 1;
+        }
+        Ok(path)
+    }
+
+    /// Record for `duration`, or extend the running recording to last at least that long
+    pub fn record(self: &Arc<Self>, duration: Duration) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            anyhow::bail!("server shutting down");
+        }
+        let end = Instant::now() + duration;
+        let mut until = self.until.lock().unwrap();
+        if let Some(current) = until.as_mut() {
+            *current = (*current).max(end);
+            info!("⏺️  Recording extended by {}s", duration.as_secs());
+            return Ok(());
+        }
+
+        let path = self.next_path()?;
+        *until = Some(end);
+        info!(
+            "⏺️  Recording to {} for {}s",
+            path.display(),
+            duration.as_secs()
+        );
+
+        let recorder = Arc::clone(self);
+        tokio::spawn(async move {
+            let done = || {
+                recorder.shutdown.is_cancelled()
+                    || recorder
+                        .until
+                        .lock()
+                        .unwrap()
+                        .is_some_and(|end| Instant::now() >= end)
+            };
+            let mut path = path;
+            loop {
+                let result = hls::mux(
+                    &recorder.video_fanout,
+                    &recorder.audio_fanout,
+                    path.clone(),
+                    done,
+                )
+                .await;
+                let saved = result.is_ok();
+                match result {
+                    Ok(()) if path.exists() => info!("⏺️  Recording saved to {}", path.display()),
+                    Ok(()) => warn!("Recording ended before the first keyframe, nothing saved"),
+                    Err(e) => error!("Recording to {} failed: {:#}", path.display(), e),
+                }
+
+                // A rule may have extended the recording while the file was being finalized
+                let next = {
+                    let mut until = recorder.until.lock().unwrap();
+                    let extended = saved
+                        && !recorder.shutdown.is_cancelled()
+                        && until.is_some_and(|end| Instant::now() < end);
+                    let next = match extended.then(|| recorder.next_path()) {
+                        Some(Ok(next)) => Some(next),
+                        Some(Err(e)) => {
+                            error!("Failed to continue the recording: {:#}", e);
+                            None
+                        }
+                        None => None,
+                    };
+                    if next.is_none() {
+                        *until = None;
+                    }
+                    next
+                };
+                let Some(next) = next else {
+                    return;
+                };
+                info!("⏺️  Recording extended, continuing in {}", next.display());
+                path = next;
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! Live configuration reload
+//!
+//! `SIGHUP` or `POST /api/admin/reload` re-reads the configuration from the same
+//! file as at startup and applies the settings that can change without dropping
+//! sessions (`HOT_RELOADABLE`). Changes to anything else are reported as requiring
+//! a restart. An invalid configuration is rejected and nothing is applied; the
+//! reason is only logged, as parse errors can quote the file (and its secrets).
+//!
+//! Relays have no labels of their own in the configuration: the DoorBird reports
+//! its relay names and automation rules refer to them, so `automation.rules`
+//! covers renamed relays as well as webhook targets and recordings. Rules that
+//! announce or record are checked against the `announce.dir` and `recording.dir`
+//! the server started with, since those need a restart.
+//!
+//! Environment variables are fixed for the life of the process, so only the TOML
+//! file can change; a setting overridden by the environment keeps its value.
+
+use crate::automation::{Action, Rule};
+use crate::config::Config;
+use crate::AppState;
+use anyhow::{bail, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Settings applied in place; all others need a restart
+///
+/// New sessions pick up the WebRTC and ICE settings, running sessions keep theirs.
+const HOT_RELOADABLE: &[&str] = &[
+    "auth.user_header",
+    "auth.trusted_proxies",
+    "auth.users",
+    "ice.servers",
+    "ice.username",
+    "ice.credential",
+    "webrtc.adaptive_video",
+    "webrtc.bwe_high_min_kbps",
+    "webrtc.bwe_video_min_kbps",
+    "webrtc.session_resume_secs",
+    "mjpeg.max_fps",
+    "health.doorbird_timeout_secs",
+    "automation.dry_run",
+    "automation.rules",
+];
+
+/// Outcome of a reload (setting names only, values may be secrets)
+#[derive(Debug, Serialize)]
+pub struct ReloadReport {
+    /// Settings changed and applied in place
+    pub applied: Vec<String>,
+    /// Settings that differ from the running configuration and need a restart
+    pub restart_required: Vec<String>,
+}
+
+/// Reloads the configuration, remembering what is running
+pub struct Reloader {
+    /// `--config` path given at startup
+    path: Option<PathBuf>,
+    /// Configuration the server started with (for restart-only settings)
+    startup: Config,
+    /// Last applied configuration; the lock also serializes reloads
+    current: Mutex<Config>,
+}
+
+impl Reloader {
+    pub fn new(path: Option<PathBuf>, config: Config) -> Self {
+        Self {
+            path,
+            startup: config.clone(),
+            current: Mutex::new(config),
+        }
+    }
+
+    /// Re-read the configuration and apply the hot-reloadable settings
+    pub async fn reload(&self, state: &AppState) -> Result<ReloadReport> {
+        let mut current = self.current.lock().await;
+        let config = Config::load(self.path.as_deref())?;
+        check_running_dirs(&config.automation.rules, &self.startup)?;
+
+        let report = ReloadReport {
+            applied: changed(&current, &config)
+                .into_iter()
+                .filter(|key| is_hot_reloadable(key))
+                .collect(),
+            restart_required: changed(&self.startup, &config)
+                .into_iter()
+                .filter(|key| !is_hot_reloadable(key))
+                .collect(),
+        };
+
+        state.webrtc_infra.reconfigure(&config.webrtc, &config.ice);
+        state.auth.reconfigure(&config.auth);
+        state
+            .intercom_sessions
+            .set_timeout(Duration::from_secs(config.webrtc.session_resume_secs));
+        state.mjpeg_fanout.set_max_fps(config.mjpeg.max_fps);
+        state
+            .health
+            .set_doorbird_timeout(Duration::from_secs(config.health.doorbird_timeout_secs));
+        state.automation.reconfigure(&config.automation);
+
+        if report.applied.is_empty() {
+            info!("🔄 Configuration reloaded, nothing to apply");
+        } else {
+            info!(
+                "🔄 Configuration reloaded, applied: {}",
+                report.applied.join(", ")
+            );
+        }
+        if !report.restart_required.is_empty() {
+            warn!(
+                "Restart required to apply: {}",
+                report.restart_required.join(", ")
+            );
+        }
+
+        *current = config;
+        Ok(report)
+    }
+}
+
+/// Reject rules that need an `announce.dir` or `recording.dir` the running server
+/// doesn't have (`Config::load` only checks them against the new file)
+fn check_running_dirs(rules: &[Rule], running: &Config) -> Result<()> {
+    let mut errors = Vec::new();
+    for rule in rules {
+        for action in &rule.actions {
+            match action {
+                Action::Announce { .. } if running.announce.dir.is_none() => errors.push(format!(
+                    "automation.rules: rule '{}' announces, but the server was started \
+                     without announce.dir (restart to apply)",
+                    rule.name
+                )),
+                Action::Record { .. } if running.recording.dir.is_none() => errors.push(format!(
+                    "automation.rules: rule '{}' records, but the server was started \
+                     without recording.dir (restart to apply)",
+                    rule.name
+                )),
+                _ => {}
+            }
+        }
+    }
+    if !errors.is_empty() {
+        bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+    }
+    Ok(())
+}
+
+fn is_hot_reloadable(key: &str) -> bool {
+    HOT_RELOADABLE.contains(&key)
+}
+
+/// `section.key` names of the settings that differ
+fn changed(old: &Config, new: &Config) -> Vec<String> {
+    let old = flatten(old);
+    let new = flatten(new);
+    new.iter()
+        .filter(|(key, value)| old.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Settings by `section.key`
+fn flatten(config: &Config) -> BTreeMap<String, serde_json::Value> {
+    let mut settings = BTreeMap::new();
+    if let Ok(serde_json::Value::Object(sections)) = serde_json::to_value(config) {
+        for (section, values) in sections {
+            match values {
+                serde_json::Value::Object(values) => {
+                    for (key, value) in values {
+                        settings.insert(format!("{}.{}", section, key), value);
+                    }
+                }
+                value => {
+                    settings.insert(section, value);
+                }
+            }
+        }
+    }
+    settings
+}
+
+/// Reload on `SIGHUP`
+#[cfg(unix)]
+pub fn spawn_sighup_handler(state: AppState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            if let Err(e) = state.reloader.reload(&state).await {
+                error!(
+                    "Configuration reload failed, keeping the running configuration: {:#}",
+                    e
+                );
+            }
+        }
+    });
+}
+
+/// `POST /api/admin/reload` - reload the configuration
+pub async fn reload(State(state): State<AppState>) -> impl IntoResponse {
+    match state.reloader.reload(&state).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            error!(
+                "Configuration reload failed, keeping the running configuration: {:#}",
+                e
+            );
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(serde_json::json!({
+                    "error": "invalid configuration, see the server log for details"
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_settings() {
+        let old = Config::default();
+        let mut new = old.clone();
+        assert!(changed(&old, &new).is_empty());
+
+        new.mjpeg.max_fps = 2;
+        new.ice.credential = Some("secret".into());
+        new.video.fanout_buffer_frames = 8;
+        assert_eq!(
+            changed(&old, &new),
+            vec![
+                "ice.credential",
+                "mjpeg.max_fps",
+                "video.fanout_buffer_frames"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules_checked_against_running_dirs() {
+        let mut running = Config::default();
+        let rules: Vec<Rule> = toml::from_str::<crate::config::Automation>(
+            r#"
+            [[rules]]
+            name = "parcel"
+            event = "doorbell"
+            actions = [{ type = "announce", clip = "parcel" }, { type = "record" }]
+            "#,
+        )
+        .unwrap()
+        .rules;
+
+        let err = check_running_dirs(&rules, &running)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("without announce.dir"), "{}", err);
+        assert!(err.contains("without recording.dir"), "{}", err);
+
+        running.announce.dir = Some("/clips".into());
+        running.recording.dir = Some("/recordings".into());
+        assert!(check_running_dirs(&rules, &running).is_ok());
+        assert!(check_running_dirs(&[], &Config::default()).is_ok());
+    }
+
+    #[test]
+    fn test_hot_reloadable_keys_exist() {
+        let settings = flatten(&Config::default());
+        for key in HOT_RELOADABLE {
+            assert!(settings.contains_key(*key), "unknown setting {}", key);
+        }
+        assert!(!is_hot_reloadable("video.fanout_buffer_frames"));
+    }
+}
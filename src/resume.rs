@@ -0,0 +1,122 @@
+//! Resumable intercom sessions
+//!
+//! When the signaling WebSocket drops without a close frame (a phone switching from
+//! Wi-Fi to LTE), the `WebRtcSession` is parked instead of torn down. The client
+//! reconnects to `/ws?resume=<token>` with the resume token it got in the `hello`
+//! message, picks the session back up (PTT lock included) and restarts ICE over the
+//! new network path. Sessions that aren't resumed within the timeout, or whose peer
+//! connection ends while parked, are torn down.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Default time an orphaned session waits for its client to reconnect
+pub const DEFAULT_RESUME_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Parked<T> {
+    session: T,
+    generation: u64,
+}
+
+/// Sessions waiting for their client to reconnect, by resume token
+pub struct ResumableSessions<T> {
+    parked: Mutex<HashMap<String, Parked<T>>>,
+    next_generation: AtomicU64,
+    /// Milliseconds; changed by configuration reloads
+    timeout_ms: AtomicU64,
+}
+
+impl<T> ResumableSessions<T> {
+    /// A zero `timeout` disables resuming
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            parked: Mutex::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+            timeout_ms: AtomicU64::new(timeout.as_millis() as u64),
+        }
+    }
+
+    /// How long a parked session waits before it is torn down
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed))
+    }
+
+    /// Change the timeout for sessions parked from now on
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.timeout().is_zero()
+    }
+
+    /// Park a session until it is resumed or `expire`d
+    ///
+    /// Returns the generation to pass to `expire`, so a timer from an earlier
+    /// disconnect never tears down a session that was resumed and parked again.
+    pub async fn park(&self, token: String, session: T) -> u64 {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        self.parked.lock().await.insert(
+            token,
+            Parked {
+                session,
+                generation,
+            },
+        );
+        generation
+    }
+
+    /// Take a parked session back for a reconnecting client
+    pub async fn resume(&self, token: &str) -> Option<T> {
+        self.parked.lock().await.remove(token).map(|p| p.session)
+    }
+
+    /// Remove a session that is still parked from the given `park` call
+    pub async fn expire(&self, token: &str, generation: u64) -> Option<T> {
+        let mut parked = self.parked.lock().await;
+        if parked.get(token)?.generation != generation {
+            return None;
+        }
+        parked.remove(token).map(|p| p.session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_takes_parked_session() {
+        let sessions = ResumableSessions::new(DEFAULT_RESUME_TIMEOUT);
+        sessions.park("token".to_string(), 1).await;
+
+        assert_eq!(sessions.resume("other").await, None);
+        assert_eq!(sessions.resume("token").await, Some(1));
+        assert_eq!(sessions.resume("token").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_expire_ignores_later_park() {
+        let sessions = ResumableSessions::new(DEFAULT_RESUME_TIMEOUT);
+        let first = sessions.park("token".to_string(), 1).await;
+
+        // Resumed and dropped again before the first timer fired
+        let session = sessions.resume("token").await.unwrap();
+        let second = sessions.park("token".to_string(), session).await;
+
+        assert_eq!(sessions.expire("token", first).await, None);
+        assert_eq!(sessions.expire("token", second).await, Some(1));
+    }
+
+    #[test]
+    fn test_zero_timeout_disables() {
+        assert!(!ResumableSessions::<u32>::new(Duration::ZERO).enabled());
+        let sessions = ResumableSessions::<u32>::new(DEFAULT_RESUME_TIMEOUT);
+        assert!(sessions.enabled());
+        sessions.set_timeout(Duration::ZERO);
+        assert!(!sessions.enabled());
+    }
+}
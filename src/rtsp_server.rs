@@ -0,0 +1,752 @@
+//! RTSP re-streaming server
+//!
+//! The DoorBird limits concurrent stream consumers and gives the official app
+//! precedence, so NVRs (Frigate, Home Assistant, ...) should read from birdbox
+//! instead of the device. This module serves the fanouts over RTSP:
+//! - `rtsp://host:port/` (or `/high`) - best resolution H.264 + Opus audio
+//! - `rtsp://host:port/low` - default resolution (when simulcast is available)
+//!
+//! Only RTP-over-RTSP (TCP interleaved) transport is supported, which is what NVRs
+//! use by default for reliability (`rtsp_transport=tcp` in ffmpeg terms). Requests
+//! for UDP transport get `461 Unsupported Transport`.
+//!
+//! H.264 packets from `VideoFanout` are packetized per RFC 6184 and the Opus frames
+//! from `AudioFanout` per RFC 7587, without any transcoding.
+
+use crate::audio_fanout::AudioFanout;
+use crate::video_tiers::{VideoTier, VideoTiers};
+use anyhow::{Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use webrtc::rtp::codecs::h264::H264Payloader;
+use webrtc::rtp::codecs::opus::OpusPayloader;
+use webrtc::rtp::packetizer::{new_packetizer, Packetizer};
+use webrtc::rtp::sequence::new_random_sequencer;
+use webrtc::util::Marshal;
+
+/// RTP payload type used for H.264
+const VIDEO_PAYLOAD_TYPE: u8 = 96;
+
+/// RTP payload type used for Opus
+const AUDIO_PAYLOAD_TYPE: u8 = 97;
+
+/// Maximum RTP packet size (interleaved frames can be larger, but NVRs expect MTU-sized packets)
+const RTP_MTU: usize = 1400;
+
+/// Maximum size of an RTSP request head
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// Interleaved frames queued per client before it is considered too slow
+const CLIENT_QUEUE_FRAMES: usize = 512;
+
+/// RTP clock rate for H.264
+const VIDEO_CLOCK_RATE: u64 = 90_000;
+
+/// Largest PTS step between frames taken as is; bigger jumps or going backwards mean
+/// the DoorBird stream was restarted
+const MAX_PTS_STEP: Duration = Duration::from_secs(1);
+
+/// Optional Basic auth credentials for RTSP clients
+#[derive(Clone)]
+pub struct RtspCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// RTSP server re-serving the DoorBird fanouts
+pub struct RtspServer {
+    audio_fanout: Arc<AudioFanout>,
+    video_tiers: Arc<VideoTiers>,
+    credentials: Option<RtspCredentials>,
+    /// Cancelled when the server shuts down; stops accepting and ends all clients
+    shutdown: CancellationToken,
+}
+
+impl RtspServer {
+    /// Bind the RTSP listener and start accepting clients in the background
+    pub async fn start(
+        addr: SocketAddr,
+        audio_fanout: Arc<AudioFanout>,
+        video_tiers: Arc<VideoTiers>,
+        credentials: Option<RtspCredentials>,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind RTSP server to {}", addr))?;
+        info!(
+            "📹 RTSP server listening on rtsp://{} ({})",
+            addr,
+            if credentials.is_some() {
+                "authentication required"
+            } else {
+                "no authentication"
+            }
+        );
+
+        let server = Arc::new(Self {
+            audio_fanout,
+            video_tiers,
+            credentials,
+            shutdown,
+        });
+
+        tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = server.shutdown.cancelled() => {
+                        info!("RTSP server stopped");
+                        break;
+                    }
+                };
+                match accepted {
+                    Ok((stream, peer)) => {
+                        let server = Arc::clone(&server);
+                        tokio::spawn(async move {
+                            info!("RTSP client connected: {}", peer);
+                            if let Err(e) = server.handle_client(stream).await {
+                                debug!("RTSP client {} error: {:#}", peer, e);
+                            }
+                            info!("RTSP client disconnected: {}", peer);
+                        });
+                    }
+                    Err(e) => {
+                        warn!("RTSP accept error: {:#}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Serve one RTSP connection until TEARDOWN or disconnect
+    async fn handle_client(&self, stream: TcpStream) -> Result<()> {
+        let (read_half, mut write_half) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+
+        // All writes (responses and media) go through one queue so interleaved
+        // frames are never split by a response
+        let (out_tx, mut out_rx) = mpsc::channel::<Bytes>(CLIENT_QUEUE_FRAMES);
+        let writer = tokio::spawn(async move {
+            while let Some(data) = out_rx.recv().await {
+                if write_half.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let session_id = Uuid::new_v4().simple().to_string()[..16].to_string();
+        let mut setup = TrackSetup::default();
+        let mut tier = VideoTier::High;
+        // Dropping the sender stops the media task
+        let mut stop_media: Option<oneshot::Sender<()>> = None;
+
+        let result = loop {
+            let request = tokio::select! {
+                request = read_request(&mut reader) => request,
+                _ = self.shutdown.cancelled() => break Ok(()),
+            };
+            let request = match request {
+                Ok(Some(request)) => request,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            debug!("RTSP {} {}", request.method, request.uri);
+
+            let cseq = request.header("cseq").unwrap_or("0").to_string();
+            let mut headers: Vec<(&str, String)> = vec![("CSeq", cseq)];
+
+            if !self.is_authorized(&request) {
+                headers.push(("WWW-Authenticate", "Basic realm=\"birdbox\"".to_string()));
+                let _ = out_tx
+                    .send(response(401, "Unauthorized", &headers, None))
+                    .await;
+                continue;
+            }
+
+            let reply = match request.method.as_str() {
+                "OPTIONS" => {
+                    headers.push((
+                        "Public",
+                        "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER".to_string(),
+                    ));
+                    response(200, "OK", &headers, None)
+                }
+                "DESCRIBE" => match stream_tier(&request.uri) {
+                    Some(requested) => {
+                        tier = self.video_tiers.resolve(requested);
+                        headers.push(("Content-Type", "application/sdp".to_string()));
+                        headers.push(("Content-Base", format!("{}/", request.uri)));
+                        response(200, "OK", &headers, Some(describe_sdp()))
+                    }
+                    None => response(404, "Not Found", &headers, None),
+                },
+                "SETUP" => {
+                    let transport = request.header("transport").unwrap_or("");
+                    match (track_from_uri(&request.uri), interleaved_channel(transport)) {
+                        (None, _) => response(404, "Not Found", &headers, None),
+                        (Some(_), None) => response(461, "Unsupported Transport", &headers, None),
+                        (Some(track), Some(channel)) => {
+                            match track {
+                                Track::Video => setup.video_channel = Some(channel),
+                                Track::Audio => setup.audio_channel = Some(channel),
+                            }
+                            headers.push((
+                                "Transport",
+                                format!(
+                                    "RTP/AVP/TCP;unicast;interleaved={}-{}",
+                                    channel,
+                                    channel + 1
+                                ),
+                            ));
+                            headers.push(("Session", format!("{};timeout=60", session_id)));
+                            response(200, "OK", &headers, None)
+                        }
+                    }
+                }
+                "PLAY" => {
+                    if setup.video_channel.is_none() && setup.audio_channel.is_none() {
+                        response(455, "Method Not Valid in This State", &headers, None)
+                    } else {
+                        if stop_media.is_none() {
+                            let (stop_tx, stop_rx) = oneshot::channel();
+                            stop_media = Some(stop_tx);
+                            self.spawn_media_task(setup, tier, out_tx.clone(), stop_rx);
+                        }
+                        headers.push(("Session", session_id.clone()));
+                        headers.push(("Range", "npt=0.000-".to_string()));
+                        response(200, "OK", &headers, None)
+                    }
+                }
+                "GET_PARAMETER" | "SET_PARAMETER" => {
+                    // Used by clients as keepalive
+                    headers.push(("Session", session_id.clone()));
+                    response(200, "OK", &headers, None)
+                }
+                "TEARDOWN" => {
+                    headers.push(("Session", session_id.clone()));
+                    let _ = out_tx.send(response(200, "OK", &headers, None)).await;
+                    break Ok(());
+                }
+                _ => response(501, "Not Implemented", &headers, None),
+            };
+
+            if out_tx.send(reply).await.is_err() {
+                break Ok(());
+            }
+        };
+
+        drop(stop_media);
+        drop(out_tx);
+        let _ = writer.await;
+        result
+    }
+
+    fn is_authorized(&self, request: &RtspRequest) -> bool {
+        let Some(credentials) = &self.credentials else {
+            return true;
+        };
+        let expected =
+            BASE64_STANDARD.encode(format!("{}:{}", credentials.username, credentials.password));
+        request
+            .header("authorization")
+            .and_then(|v| v.strip_prefix("Basic "))
+            .map(|v| constant_time_eq(v.trim().as_bytes(), expected.as_bytes()))
+            .unwrap_or(false)
+    }
+
+    /// Forward fanout media to one client as interleaved RTP until stopped
+    fn spawn_media_task(
+        &self,
+        setup: TrackSetup,
+        tier: VideoTier,
+        out_tx: mpsc::Sender<Bytes>,
+        mut stop_rx: oneshot::Receiver<()>,
+    ) {
+        let audio_fanout = Arc::clone(&self.audio_fanout);
+        let video_fanout = self.video_tiers.get(tier);
+
+        tokio::spawn(async move {
+            let mut video_rx = match setup.video_channel {
+                Some(_) => Some(video_fanout.subscribe().await),
+                None => None,
+            };
+            let mut audio_rx = match setup.audio_channel {
+                Some(_) => Some(audio_fanout.subscribe().await),
+                None => None,
+            };
+            info!("RTSP client playing ({} tier)", tier.as_str());
+
+            let mut video_packetizer = new_packetizer(
+                RTP_MTU,
+                VIDEO_PAYLOAD_TYPE,
+                rand_ssrc(),
+                Box::new(H264Payloader::default()),
+                Box::new(new_random_sequencer()),
+                VIDEO_CLOCK_RATE as u32,
+            );
+            let mut audio_packetizer = new_packetizer(
+                RTP_MTU,
+                AUDIO_PAYLOAD_TYPE,
+                rand_ssrc(),
+                Box::new(OpusPayloader),
+                Box::new(new_random_sequencer()),
+                48000,
+            );
+
+            // Start (and restart after overflow) on a keyframe so the client can decode
+            let mut awaiting_keyframe = true;
+            let mut last_pts: Option<Duration> = None;
+            let mut last_step = 0;
+
+            loop {
+                let frames = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    packet = recv_opt(&mut video_rx) => match packet {
+                        Ok(packet) => {
+                            if awaiting_keyframe && !packet.is_keyframe {
+                                continue;
+                            }
+                            awaiting_keyframe = false;
+
+                            // RTP timestamp advances with the frames' presentation time
+                            let samples = video_rtp_step(last_pts, packet.timestamp, last_step);
+                            last_pts = Some(packet.timestamp);
+                            last_step = samples;
+
+                            interleave(
+                                setup.video_channel.unwrap_or(0),
+                                packetize_video(&mut video_packetizer, &packet.data, samples),
+                            )
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("RTSP client lagged behind video by {} packets", n);
+                            awaiting_keyframe = true;
+                            continue;
+                        }
+                        Err(_) => break,
+                    },
+                    sample = recv_opt(&mut audio_rx) => match sample {
+                        Ok(sample) => interleave(
+                            setup.audio_channel.unwrap_or(2),
+                            audio_packetizer.packetize(
+                                &sample.data,
+                                (sample.duration.as_secs_f64() * 48000.0) as u32,
+                            ),
+                        ),
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    },
+                };
+
+                for frame in frames {
+                    match out_tx.try_send(frame) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            // Slow client: drop media and resync on the next keyframe
+                            // rather than buffering without bound
+                            awaiting_keyframe = true;
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => {
+                            stop_rx.close();
+                        }
+                    }
+                }
+                if out_tx.is_closed() {
+                    break;
+                }
+            }
+
+            if video_rx.is_some() {
+                video_fanout.unsubscribe().await;
+            }
+            if audio_rx.is_some() {
+                audio_fanout.unsubscribe().await;
+            }
+            info!("RTSP client stopped playing");
+        });
+    }
+}
+
+/// RTP timestamp increment between the previous video frame and one with `pts`
+///
+/// Computed from whole clock ticks of both timestamps so rounding never drifts. The
+/// first frame starts at 0; after a stream restart (PTS jumps backwards or by more
+/// than `MAX_PTS_STEP`) the previous step is repeated so the RTP clock stays smooth.
+fn video_rtp_step(last_pts: Option<Duration>, pts: Duration, last_step: u32) -> u32 {
+    let ticks = |d: Duration| d.as_nanos() * VIDEO_CLOCK_RATE as u128 / 1_000_000_000;
+    match last_pts {
+        None => 0,
+        Some(last) if pts >= last && pts - last <= MAX_PTS_STEP => {
+            (ticks(pts) - ticks(last)) as u32
+        }
+        Some(_) => last_step,
+    }
+}
+
+/// Packetize a video frame stamped `samples` after the previous one
+///
+/// `Packetizer::packetize` stamps the packets with its current timestamp and only
+/// then advances it, so the step to this frame is skipped ahead first.
+fn packetize_video(
+    packetizer: &mut impl Packetizer,
+    data: &Bytes,
+    samples: u32,
+) -> Result<Vec<webrtc::rtp::packet::Packet>, webrtc::rtp::Error> {
+    packetizer.skip_samples(samples);
+    packetizer.packetize(data, 0)
+}
+
+/// Compare secrets without stopping at the first difference, so response timing
+/// doesn't reveal how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// Receive from an optional broadcast receiver, waiting forever if absent
+async fn recv_opt<T: Clone>(
+    rx: &mut Option<tokio::sync::broadcast::Receiver<T>>,
+) -> Result<T, tokio::sync::broadcast::error::RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wrap packetized RTP in RTSP interleaved frames (`$`, channel, length, data)
+fn interleave(
+    channel: u8,
+    packets: Result<Vec<webrtc::rtp::packet::Packet>, webrtc::rtp::Error>,
+) -> Vec<Bytes> {
+    let packets = match packets {
+        Ok(packets) => packets,
+        Err(e) => {
+            debug!("RTP packetization failed: {}", e);
+            return Vec::new();
+        }
+    };
+
+    packets
+        .iter()
+        .filter_map(|p| p.marshal().ok())
+        .map(|rtp| {
+            let mut frame = BytesMut::with_capacity(rtp.len() + 4);
+            frame.put_u8(b'$');
+            frame.put_u8(channel);
+            frame.put_u16(rtp.len() as u16);
+            frame.put_slice(&rtp);
+            frame.freeze()
+        })
+        .collect()
+}
+
+fn rand_ssrc() -> u32 {
+    let id = Uuid::new_v4();
+    let bytes = id.as_bytes();
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Interleaved channels chosen by the client in SETUP
+#[derive(Debug, Default, Clone, Copy)]
+struct TrackSetup {
+    video_channel: Option<u8>,
+    audio_channel: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Track {
+    Video,
+    Audio,
+}
+
+/// Parsed RTSP request (head only; RTSP requests we handle have no body)
+#[derive(Debug)]
+struct RtspRequest {
+    method: String,
+    uri: String,
+    headers: HashMap<String, String>,
+}
+
+impl RtspRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|s| s.as_str())
+    }
+}
+
+/// Read the next RTSP request, skipping interleaved RTCP frames sent by the client
+///
+/// Returns `Ok(None)` when the client closes the connection.
+async fn read_request<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Option<RtspRequest>> {
+    let mut head = Vec::new();
+
+    loop {
+        let byte = match reader.read_u8().await {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Interleaved binary frame between requests (client RTCP receiver reports)
+        if head.is_empty() && byte == b'$' {
+            let _channel = reader.read_u8().await?;
+            let len = reader.read_u16().await? as usize;
+            let mut discard = vec![0u8; len];
+            reader.read_exact(&mut discard).await?;
+            continue;
+        }
+
+        head.push(byte);
+        if head.ends_with(b"\r\n\r\n") {
+            break;
+        }
+        if head.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("RTSP request too large");
+        }
+    }
+
+    parse_request(&String::from_utf8_lossy(&head)).map(Some)
+}
+
+/// Parse an RTSP request head; header names are lower-cased
+fn parse_request(head: &str) -> Result<RtspRequest> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().context("missing RTSP method")?.to_string();
+    let uri = parts.next().context("missing RTSP URI")?.to_string();
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    Ok(RtspRequest {
+        method,
+        uri,
+        headers,
+    })
+}
+
+/// Path component of an RTSP URI (`rtsp://host:port/path` → `/path`)
+fn uri_path(uri: &str) -> &str {
+    let without_scheme = uri.strip_prefix("rtsp://").unwrap_or(uri);
+    match without_scheme.find('/') {
+        Some(pos) => &without_scheme[pos..],
+        None => "/",
+    }
+}
+
+/// Stream (video tier) addressed by a DESCRIBE URI
+fn stream_tier(uri: &str) -> Option<VideoTier> {
+    match uri_path(uri).trim_end_matches('/') {
+        "" | "/high" => Some(VideoTier::High),
+        "/low" => Some(VideoTier::Low),
+        _ => None,
+    }
+}
+
+/// Track addressed by a SETUP URI (`.../trackID=0` video, `.../trackID=1` audio)
+fn track_from_uri(uri: &str) -> Option<Track> {
+    match uri_path(uri).rsplit('/').next() {
+        Some("trackID=0") => Some(Track::Video),
+        Some("trackID=1") => Some(Track::Audio),
+        _ => None,
+    }
+}
+
+/// First interleaved channel from a TCP Transport header, `None` for other transports
+fn interleaved_channel(transport: &str) -> Option<u8> {
+    // Clients may list several transports in preference order
+    transport.split(',').find_map(|spec| {
+        let mut params = spec.split(';');
+        if !params.next()?.trim().eq_ignore_ascii_case("RTP/AVP/TCP") {
+            return None;
+        }
+        params
+            .find_map(|p| p.trim().strip_prefix("interleaved="))
+            .and_then(|range| range.split('-').next())
+            .and_then(|c| c.parse().ok())
+    })
+}
+
+/// SDP describing the re-served stream
+fn describe_sdp() -> String {
+    [
+        "v=0",
+        "o=- 0 0 IN IP4 0.0.0.0",
+        "s=Birdbox",
+        "c=IN IP4 0.0.0.0",
+        "t=0 0",
+        "a=control:*",
+        "m=video 0 RTP/AVP 96",
+        "a=rtpmap:96 H264/90000",
+        "a=fmtp:96 packetization-mode=1",
+        "a=control:trackID=0",
+        "m=audio 0 RTP/AVP 97",
+        "a=rtpmap:97 opus/48000/2",
+        "a=control:trackID=1",
+        "",
+    ]
+    .join("\r\n")
+}
+
+/// Build an RTSP response
+fn response(code: u16, reason: &str, headers: &[(&str, String)], body: Option<String>) -> Bytes {
+    let mut out = format!("RTSP/1.0 {} {}\r\n", code, reason);
+    for (name, value) in headers {
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match body {
+        Some(body) => {
+            out.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+            out.push_str(&body);
+        }
+        None => out.push_str("\r\n"),
+    }
+    Bytes::from(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let head = "SETUP rtsp://10.0.0.2:8554/trackID=0 RTSP/1.0\r\n\
+            CSeq: 3\r\n\
+            Transport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n\r\n";
+        let request = parse_request(head).unwrap();
+        assert_eq!(request.method, "SETUP");
+        assert_eq!(request.header("cseq"), Some("3"));
+        assert_eq!(
+            request.header("transport"),
+            Some("RTP/AVP/TCP;unicast;interleaved=0-1")
+        );
+    }
+
+    #[test]
+    fn test_interleaved_channel() {
+        assert_eq!(
+            interleaved_channel("RTP/AVP/TCP;unicast;interleaved=2-3"),
+            Some(2)
+        );
+        assert_eq!(
+            interleaved_channel("RTP/AVP;unicast;client_port=5000-5001"),
+            None
+        );
+        assert_eq!(
+            interleaved_channel(
+                "RTP/AVP;unicast;client_port=5000-5001,RTP/AVP/TCP;interleaved=0-1"
+            ),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_stream_and_track_uris() {
+        assert_eq!(stream_tier("rtsp://host:8554"), Some(VideoTier::High));
+        assert_eq!(stream_tier("rtsp://host:8554/"), Some(VideoTier::High));
+        assert_eq!(stream_tier("rtsp://host:8554/low"), Some(VideoTier::Low));
+        assert_eq!(stream_tier("rtsp://host:8554/other"), None);
+        assert_eq!(
+            track_from_uri("rtsp://host:8554/low/trackID=1"),
+            Some(Track::Audio)
+        );
+        assert_eq!(
+            track_from_uri("rtsp://host:8554/trackID=0"),
+            Some(Track::Video)
+        );
+        assert_eq!(track_from_uri("rtsp://host:8554/"), None);
+    }
+
+    #[test]
+    fn test_interleave_framing() {
+        let packet = webrtc::rtp::packet::Packet {
+            header: webrtc::rtp::header::Header {
+                version: 2,
+                payload_type: AUDIO_PAYLOAD_TYPE,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(&[1, 2, 3]),
+        };
+        let frames = interleave(2, Ok(vec![packet]));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][0], b'$');
+        assert_eq!(frames[0][1], 2);
+        // 12-byte RTP header + 3-byte payload
+        assert_eq!(u16::from_be_bytes([frames[0][2], frames[0][3]]), 15);
+    }
+
+    #[test]
+    fn test_video_rtp_step_follows_pts() {
+        let ms = Duration::from_millis;
+        assert_eq!(video_rtp_step(None, ms(5000), 0), 0);
+        assert_eq!(video_rtp_step(Some(ms(0)), ms(40), 0), 3600);
+        // Frames at an uneven rate keep their spacing
+        assert_eq!(video_rtp_step(Some(ms(40)), ms(120), 3600), 7200);
+        // 30 fps: 3000 ticks per frame without accumulated rounding
+        let frame = |n: u64| Duration::from_nanos(n * 1_000_000_000 / 30);
+        let total: u32 = (1..=30)
+            .map(|n| video_rtp_step(Some(frame(n - 1)), frame(n), 0))
+            .sum();
+        assert_eq!(total, 90_000);
+        // Stream restart: PTS goes back to 0 or jumps ahead
+        assert_eq!(video_rtp_step(Some(ms(60_000)), ms(0), 3600), 3600);
+        assert_eq!(video_rtp_step(Some(ms(0)), ms(30_000), 3600), 3600);
+    }
+
+    #[test]
+    fn test_video_packets_stamped_with_frame_pts() {
+        let mut packetizer = new_packetizer(
+            RTP_MTU,
+            VIDEO_PAYLOAD_TYPE,
+            1,
+            Box::new(H264Payloader::default()),
+            Box::new(new_random_sequencer()),
+            VIDEO_CLOCK_RATE as u32,
+        );
+        // Two NAL units per frame, both packets carry the frame's timestamp
+        let frame = Bytes::from_static(&[0, 0, 0, 1, 0x41, 0x9a, 0, 0, 0, 1, 0x41, 0x9b]);
+
+        let mut last_pts = None;
+        let mut last_step = 0;
+        let mut timestamps = Vec::new();
+        for pts in [0, 40, 80, 160] {
+            let pts = Duration::from_millis(pts);
+            let samples = video_rtp_step(last_pts, pts, last_step);
+            last_pts = Some(pts);
+            last_step = samples;
+            let packets = packetize_video(&mut packetizer, &frame, samples).unwrap();
+            assert_eq!(packets.len(), 2);
+            timestamps.extend(packets.iter().map(|p| p.header.timestamp));
+        }
+
+        let first = timestamps[0];
+        let offsets: Vec<u32> = timestamps.iter().map(|t| t.wrapping_sub(first)).collect();
+        assert_eq!(offsets, [0, 0, 3600, 3600, 7200, 7200, 14400, 14400]);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"bnZyOnNlY3JldA==", b"bnZyOnNlY3JldA=="));
+        assert!(!constant_time_eq(b"bnZyOnNlY3JldA==", b"bnZyOnNlY3JldB=="));
+        assert!(!constant_time_eq(b"bnZy", b"bnZyOnNlY3JldA=="));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
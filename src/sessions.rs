@@ -0,0 +1,436 @@
+//! Registry of active WebRTC sessions
+//!
+//! Every peer connection birdbox creates (intercom WebSocket, WHEP, WHIP) is
+//! registered here with the cancellation token that ends it. Cancelling the token
+//! is the single way a session is torn down:
+//! - the WebSocket closes, or a WHEP/WHIP client sends `DELETE`
+//! - the peer connection fails or closes (ICE failure, client vanished)
+//! - `close_all` is called
+//!
+//! The per-session tasks (fanout streaming, RTCP readers, bandwidth controller)
+//! stop on the token, which guarantees they unsubscribe from the fanouts. The
+//! owner of the session then closes its `RTCPeerConnection`; this only removes the
+//! session's ICE connection from the shared `UDPMuxDefault`, the mux itself stays
+//! open for other sessions.
+//!
+//! The manager also keeps what `GET /api/status` reports per session: the client
+//! that created it, its ICE state and selected candidate pair, and its bitrate.
+
+use axum::http::{header, HeaderMap};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use uuid::Uuid;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// Shortest interval bitrates are averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How a session was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    /// Browser intercom over the `/ws` signaling WebSocket
+    Intercom,
+    /// WHEP playback
+    Whep,
+    /// WHIP push-to-talk
+    Whip,
+}
+
+impl SessionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionKind::Intercom => "intercom",
+            SessionKind::Whep => "whep",
+            SessionKind::Whip => "whip",
+        }
+    }
+}
+
+/// HTTP client that created a session
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClientInfo {
+    /// Peer address of the HTTP/WebSocket connection (a reverse proxy, if any)
+    pub addr: Option<SocketAddr>,
+    pub user_agent: Option<String>,
+    /// User the reverse proxy authenticated (see `auth.rs`)
+    pub user: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(addr: SocketAddr, headers: &HeaderMap, user: Option<String>) -> Self {
+        Self {
+            addr: Some(addr),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            user,
+        }
+    }
+}
+
+/// Media payload bitrate of a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Bitrate {
+    pub sent_kbps: u64,
+    pub received_kbps: u64,
+}
+
+struct RateSample {
+    at: Instant,
+    sent: u64,
+    received: u64,
+    bitrate: Bitrate,
+}
+
+/// Media bytes a session sent and received
+pub struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+    last: std::sync::Mutex<RateSample>,
+}
+
+impl Traffic {
+    pub fn new(now: Instant) -> Arc<Self> {
+        Arc::new(Self {
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            last: std::sync::Mutex::new(RateSample {
+                at: now,
+                sent: 0,
+                received: 0,
+                bitrate: Bitrate::default(),
+            }),
+        })
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Total media bytes received
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Bitrate since the previous sample
+    ///
+    /// A new sample is taken at most once per `RATE_WINDOW`; calls in between
+    /// return the last result, so frequent polling doesn't produce noisy rates.
+    pub fn bitrate(&self, now: Instant) -> Bitrate {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = now.saturating_duration_since(last.at);
+        if elapsed < RATE_WINDOW {
+            return last.bitrate;
+        }
+
+        let sent = self.sent.load(Ordering::Relaxed);
+        let received = self.received.load(Ordering::Relaxed);
+        // bytes * 8 / ms = kbit/s
+        let millis = elapsed.as_millis().max(1) as u64;
+        let bitrate = Bitrate {
+            sent_kbps: (sent - last.sent) * 8 / millis,
+            received_kbps: (received - last.received) * 8 / millis,
+        };
+        *last = RateSample {
+            at: now,
+            sent,
+            received,
+            bitrate,
+        };
+        bitrate
+    }
+}
+
+/// Snapshot of an active session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub kind: SessionKind,
+    pub client: ClientInfo,
+    /// Unix timestamp (seconds) the session started
+    pub started_at: u64,
+    pub duration_secs: u64,
+    /// Peer connection state (`new`, `connecting`, `connected`, ...)
+    pub connection_state: String,
+    /// ICE connection state (`checking`, `connected`, `disconnected`, ...)
+    pub ice_state: String,
+    /// Local and remote candidate in use, once ICE has connected
+    pub selected_candidate_pair: Option<String>,
+    pub bitrate: Bitrate,
+}
+
+struct SessionEntry {
+    kind: SessionKind,
+    client: ClientInfo,
+    started_at: SystemTime,
+    started: Instant,
+    cancel: CancellationToken,
+    pc: Arc<RTCPeerConnection>,
+    traffic: Arc<Traffic>,
+}
+
+/// Active sessions by ID
+#[derive(Default)]
+pub struct SessionManager {
+    sessions: RwLock<HashMap<Uuid, SessionEntry>>,
+}
+
+impl SessionManager {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Track a session until `remove` is called
+    pub async fn register(
+        &self,
+        id: Uuid,
+        kind: SessionKind,
+        client: ClientInfo,
+        cancel: CancellationToken,
+        pc: Arc<RTCPeerConnection>,
+        traffic: Arc<Traffic>,
+    ) {
+        let mut sessions = self.sessions.write().await;
+        sessions.insert(
+            id,
+            SessionEntry {
+                kind,
+                client,
+                started_at: SystemTime::now(),
+                started: Instant::now(),
+                cancel,
+                pc,
+                traffic,
+            },
+        );
+        info!(
+            "Session {} ({:?}) registered (active: {})",
+            id,
+            kind,
+            sessions.len()
+        );
+    }
+
+    /// Forget a session after it has been torn down
+    pub async fn remove(&self, id: Uuid) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(entry) = sessions.remove(&id) {
+            // Make sure nothing of the session keeps running
+            entry.cancel.cancel();
+            info!(
+                "Session {} ({:?}) ended after {}s (active: {})",
+                id,
+                entry.kind,
+                entry.started.elapsed().as_secs(),
+                sessions.len()
+            );
+        }
+    }
+
+    /// Update the client of a resumed session (it reconnected from a new address)
+    pub async fn update_client(&self, id: Uuid, client: ClientInfo) {
+        if let Some(entry) = self.sessions.write().await.get_mut(&id) {
+            entry.client = client;
+        }
+    }
+
+    /// Whether no session is active
+    pub async fn is_empty(&self) -> bool {
+        self.sessions.read().await.is_empty()
+    }
+
+    /// Number of active sessions of each kind
+    pub async fn count_by_kind(&self) -> HashMap<SessionKind, usize> {
+        let mut counts = HashMap::new();
+        for entry in self.sessions.read().await.values() {
+            *counts.entry(entry.kind).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Active sessions, oldest first
+    pub async fn list(&self) -> Vec<SessionSummary> {
+        let now = Instant::now();
+        let mut list = Vec::new();
+        // Only the peer connections are queried after the lock is released
+        let entries: Vec<(SessionSummary, Arc<RTCPeerConnection>)> = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .map(|(id, entry)| {
+                let summary = SessionSummary {
+                    id: *id,
+                    kind: entry.kind,
+                    client: entry.client.clone(),
+                    started_at: entry
+                        .started_at
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default(),
+                    duration_secs: now.duration_since(entry.started).as_secs(),
+                    connection_state: entry.pc.connection_state().to_string(),
+                    ice_state: entry.pc.ice_connection_state().to_string(),
+                    selected_candidate_pair: None,
+                    bitrate: entry.traffic.bitrate(now),
+                };
+                (summary, entry.pc.clone())
+            })
+            .collect();
+        for (mut summary, pc) in entries {
+            summary.selected_candidate_pair = pc
+                .sctp()
+                .transport()
+                .ice_transport()
+                .get_selected_candidate_pair()
+                .await
+                .map(|pair| pair.to_string());
+            list.push(summary);
+        }
+        list.sort_by_key(|s| std::cmp::Reverse(s.duration_secs));
+        list
+    }
+
+    /// End every session (their owners complete the teardown)
+    pub async fn close_all(&self) {
+        for entry in self.sessions.read().await.values() {
+            entry.cancel.cancel();
+        }
+    }
+}
+
+/// Cancel `cancel` once the peer connection fails or closes
+///
+/// `Disconnected` is left alone since ICE may still recover from it.
+pub fn cancel_on_disconnect(state: RTCPeerConnectionState, cancel: &CancellationToken) {
+    if matches!(
+        state,
+        RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+    ) {
+        cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    async fn peer_connection() -> Arc<RTCPeerConnection> {
+        let api = APIBuilder::new().build();
+        Arc::new(
+            api.new_peer_connection(RTCConfiguration::default())
+                .await
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_register_list_remove() {
+        let manager = SessionManager::new();
+        let id = Uuid::new_v4();
+        let cancel = CancellationToken::new();
+        let client = ClientInfo {
+            addr: Some("192.168.1.20:50000".parse().unwrap()),
+            user_agent: Some("curl/8.0".to_string()),
+            user: Some("alice".to_string()),
+        };
+
+        manager
+            .register(
+                id,
+                SessionKind::Whep,
+                client.clone(),
+                cancel.clone(),
+                peer_connection().await,
+                Traffic::new(Instant::now()),
+            )
+            .await;
+        let list = manager.list().await;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, id);
+        assert_eq!(list[0].kind, SessionKind::Whep);
+        assert_eq!(list[0].client, client);
+        assert_eq!(list[0].connection_state, "new");
+        assert_eq!(list[0].selected_candidate_pair, None);
+
+        manager.remove(id).await;
+        assert!(manager.list().await.is_empty());
+        assert!(cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_close_all_cancels_sessions() {
+        let manager = SessionManager::new();
+        let cancel = CancellationToken::new();
+        manager
+            .register(
+                Uuid::new_v4(),
+                SessionKind::Intercom,
+                ClientInfo::default(),
+                cancel.clone(),
+                peer_connection().await,
+                Traffic::new(Instant::now()),
+            )
+            .await;
+
+        manager.close_all().await;
+        assert!(cancel.is_cancelled());
+        // Owners remove their sessions once torn down
+        assert_eq!(manager.list().await.len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_on_disconnect() {
+        let cancel = CancellationToken::new();
+        cancel_on_disconnect(RTCPeerConnectionState::Disconnected, &cancel);
+        assert!(!cancel.is_cancelled());
+        cancel_on_disconnect(RTCPeerConnectionState::Failed, &cancel);
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn test_traffic_bitrate() {
+        let start = Instant::now();
+        let traffic = Traffic::new(start);
+        traffic.add_sent(250_000);
+        traffic.add_received(5_000);
+
+        let bitrate = traffic.bitrate(start + Duration::from_secs(2));
+        assert_eq!(
+            bitrate,
+            Bitrate {
+                sent_kbps: 1000,
+                received_kbps: 20
+            }
+        );
+
+        // Within the window the previous sample is reused
+        traffic.add_sent(1_000_000);
+        assert_eq!(
+            traffic.bitrate(start + Duration::from_millis(2500)),
+            bitrate
+        );
+
+        // Next window only counts the new bytes
+        assert_eq!(
+            traffic.bitrate(start + Duration::from_secs(4)).sent_kbps,
+            4000
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Graceful shutdown
+//!
+//! On SIGTERM or Ctrl-C `AppState::shutdown` is cancelled. From then on new
+//! WebSocket, WHEP and WHIP sessions get `503 Service Unavailable`, intercom
+//! clients receive a `shutdown` message before their WebSocket closes, and the
+//! RTSP server, HLS packager, MJPEG viewers and DoorBird event monitor stop.
+//! `drain` then ends the remaining sessions, lets a push-to-talk transmission
+//! finish its request to the DoorBird, and waits for the DoorBird streams to
+//! disconnect, all bounded by `shutdown.timeout_secs`.
+//!
+//! Every DoorBird request carries the credentials; birdbox never opens a
+//! `getsession.cgi` session, so closing the connections is all the device needs.
+
+use crate::AppState;
+use std::future::Future;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Default limit on the drain, below Docker's 10s stop timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// How often `drain` checks whether everything has stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Resolves on SIGTERM or Ctrl-C
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to install Ctrl-C handler: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to install SIGTERM handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Ctrl-C received"),
+        _ = terminate => info!("SIGTERM received"),
+    }
+}
+
+/// End every session and wait until nothing is left talking to the DoorBird
+///
+/// Call after cancelling `AppState::shutdown`. Returns once all sessions are
+/// gone, push-to-talk is released and no fanout is connected; the caller bounds
+/// the wait.
+pub async fn drain(state: &AppState) {
+    // Intercom WebSockets close their own sessions after telling the client; this
+    // ends the rest (WHEP, WHIP and sessions waiting to be resumed)
+    state.sessions.close_all().await;
+    wait_until(|| async { state.sessions.is_empty().await }).await;
+    info!("All sessions closed");
+
+    // A stopped transmission still finishes its request to the DoorBird
+    wait_until(|| async {
+        !state.ptt_state.is_transmitting().await && crate::webrtc::active_transmissions() == 0
+    })
+    .await;
+
+    // Fanouts disconnect by themselves once their last subscriber is gone
+    wait_until(|| async {
+        if state.audio_fanout.subscriber_count().await > 0
+            || state.audio_fanout.is_connected().await
+        {
+            return false;
+        }
+        for (_, fanout) in state.video_tiers.fanouts() {
+            if fanout.subscriber_count().await > 0 || fanout.is_connected().await {
+                return false;
+            }
+        }
+        state.mjpeg_fanout.subscriber_count().await == 0 && !state.mjpeg_fanout.is_connected().await
+    })
+    .await;
+    info!("DoorBird streams closed");
+}
+
+/// Poll `done` until it returns true
+async fn wait_until<F, Fut>(mut done: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    while !done().await {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,230 @@
+//! Intercom signaling protocol
+//!
+//! JSON messages exchanged over the `/ws` WebSocket, tagged by `"type"`. The same
+//! `SignalMessage` enum is used in both directions:
+//! - client → server: `offer`, `candidate`, `start_ptt`, `stop_ptt`, `set_video_tier`
+//! - server → client: `hello`, `answer`, `candidate`, `ptt_granted`, `ptt_denied`,
+//!   `ptt_state`, `video_tier`, `error`, `shutdown`
+//!
+//! The server announces `PROTOCOL_VERSION` in `hello`. Requests that fail get an
+//! `error` reply with a machine-readable `code`, so clients can show the failure.
+
+use crate::ice_servers::IceServer;
+use crate::video_tiers::VideoTier;
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the signaling protocol, bumped on incompatible changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A signaling message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignalMessage {
+    /// First server message: protocol version, ICE servers and session resume details
+    Hello {
+        #[serde(rename = "protocolVersion")]
+        protocol_version: u32,
+        #[serde(rename = "iceServers")]
+        ice_servers: Vec<IceServer>,
+        #[serde(rename = "sessionId")]
+        session_id: Uuid,
+        #[serde(rename = "resumeToken")]
+        resume_token: String,
+        resumed: bool,
+    },
+    /// SDP offer from the client (initial, renegotiation or ICE restart)
+    Offer {
+        sdp: String,
+        #[serde(
+            rename = "iceRestart",
+            default,
+            skip_serializing_if = "std::ops::Not::not"
+        )]
+        ice_restart: bool,
+    },
+    /// SDP answer from the server
+    Answer { sdp: String },
+    /// Trickle ICE candidate (either direction)
+    Candidate {
+        candidate: String,
+        #[serde(rename = "sdpMid", default)]
+        sdp_mid: Option<String>,
+        #[serde(rename = "sdpMLineIndex", default)]
+        sdp_mline_index: Option<u16>,
+    },
+    /// Client asks for the push-to-talk lock
+    StartPtt,
+    /// Client stops talking
+    StopPtt,
+    /// Push-to-talk lock acquired
+    PttGranted,
+    /// Push-to-talk lock held by someone else
+    PttDenied { reason: String },
+    /// Whether anyone is talking, broadcast to all clients
+    PttState { transmitting: bool },
+    /// Client selects its preferred video tier
+    SetVideoTier { tier: VideoTier },
+    /// Preferred, streamed (`None` = audio only) and available video tiers
+    VideoTier {
+        tier: VideoTier,
+        active: Option<VideoTier>,
+        available: Vec<VideoTier>,
+        estimate_kbps: Option<u32>,
+    },
+    /// A request failed
+    Error { code: ErrorCode, message: String },
+    /// The server is stopping; the session ends and the client should reconnect
+    /// later with a new session
+    Shutdown { reason: String },
+}
+
+impl SignalMessage {
+    /// Encode as a WebSocket text message
+    pub fn to_ws(&self) -> Message {
+        // Serializing these types can't fail (string keys, no custom serializers)
+        Message::Text(serde_json::to_string(self).unwrap_or_default().into())
+    }
+}
+
+/// Error codes sent in `error` messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not valid JSON or not a known message
+    InvalidMessage,
+    /// A known message the server doesn't accept (e.g. `answer`)
+    UnexpectedMessage,
+    /// The SDP offer could not be applied
+    InvalidSdp,
+    /// The ICE candidate could not be added
+    InvalidCandidate,
+    /// Audio/video could not be set up for the session
+    MediaUnavailable,
+    /// Push-to-talk could not be started
+    PttFailed,
+}
+
+/// A failed request, replied to the client as an `error` message
+#[derive(Debug)]
+pub struct SignalError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl SignalError {
+    pub fn new(code: ErrorCode, error: impl std::fmt::Display) -> Self {
+        Self {
+            code,
+            message: error.to_string(),
+        }
+    }
+
+    pub fn to_message(&self) -> SignalMessage {
+        SignalMessage::Error {
+            code: self.code,
+            message: self.message.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for SignalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+/// Parse a client message
+pub fn parse(text: &str) -> Result<SignalMessage, SignalError> {
+    serde_json::from_str(text).map_err(|e| SignalError::new(ErrorCode::InvalidMessage, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_client_messages() {
+        assert_eq!(
+            parse(r#"{"type":"offer","sdp":"v=0"}"#).unwrap(),
+            SignalMessage::Offer {
+                sdp: "v=0".into(),
+                ice_restart: false
+            }
+        );
+        assert_eq!(
+            parse(
+                r#"{"type":"candidate","candidate":"candidate:1","sdpMid":"0","sdpMLineIndex":0}"#
+            )
+            .unwrap(),
+            SignalMessage::Candidate {
+                candidate: "candidate:1".into(),
+                sdp_mid: Some("0".into()),
+                sdp_mline_index: Some(0)
+            }
+        );
+        assert_eq!(
+            parse(r#"{"type":"set_video_tier","tier":"low"}"#).unwrap(),
+            SignalMessage::SetVideoTier {
+                tier: VideoTier::Low
+            }
+        );
+        assert_eq!(
+            parse(r#"{"type":"start_ptt"}"#).unwrap(),
+            SignalMessage::StartPtt
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown() {
+        for text in [
+            "not json",
+            r#"{"type":"dance"}"#,
+            r#"{"type":"set_video_tier","tier":"ultra"}"#,
+            r#"{"type":"offer"}"#,
+        ] {
+            assert_eq!(parse(text).unwrap_err().code, ErrorCode::InvalidMessage);
+        }
+    }
+
+    #[test]
+    fn test_server_messages_wire_format() {
+        let msg = SignalMessage::VideoTier {
+            tier: VideoTier::High,
+            active: None,
+            available: vec![VideoTier::High, VideoTier::Low],
+            estimate_kbps: Some(800),
+        };
+        assert_eq!(
+            serde_json::to_value(&msg).unwrap(),
+            json!({
+                "type": "video_tier",
+                "tier": "high",
+                "active": null,
+                "available": ["high", "low"],
+                "estimate_kbps": 800,
+            })
+        );
+
+        let msg = SignalError::new(ErrorCode::InvalidSdp, "bad offer").to_message();
+        assert_eq!(
+            serde_json::to_value(&msg).unwrap(),
+            json!({ "type": "error", "code": "invalid_sdp", "message": "bad offer" })
+        );
+
+        assert_eq!(
+            serde_json::to_value(SignalMessage::PttGranted).unwrap(),
+            json!({ "type": "ptt_granted" })
+        );
+
+        let msg = SignalMessage::Shutdown {
+            reason: "server stopping".into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&msg).unwrap(),
+            json!({ "type": "shutdown", "reason": "server stopping" })
+        );
+    }
+}
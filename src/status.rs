@@ -0,0 +1,109 @@
+//! Server status API and admin page
+//!
+//! `GET /api/status` reports what the server is doing right now: whether the
+//! DoorBird streams are connected and how many subscribers each fanout has, the
+//! active WebRTC sessions, who holds push-to-talk and the uptime. `GET /admin`
+//! serves a page that polls it.
+
+use crate::sessions::SessionSummary;
+use crate::video_tiers::VideoTier;
+use crate::AppState;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::Json;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Server status snapshot
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub fanouts: Fanouts,
+    pub ptt: PttStatus,
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// DoorBird stream fanouts
+#[derive(Debug, Serialize)]
+pub struct Fanouts {
+    pub audio: FanoutStatus,
+    /// One per video tier
+    pub video: Vec<VideoFanoutStatus>,
+    pub mjpeg: FanoutStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FanoutStatus {
+    /// Whether the upstream DoorBird stream is connected
+    pub connected: bool,
+    pub subscribers: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoFanoutStatus {
+    pub tier: VideoTier,
+    pub connected: bool,
+    pub subscribers: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PttStatus {
+    pub transmitting: bool,
+    /// Session holding the push-to-talk lock
+    pub session_id: Option<Uuid>,
+}
+
+/// `GET /api/status` - server status as JSON
+pub async fn status(State(state): State<AppState>) -> Json<Status> {
+    let mut video = Vec::new();
+    for (tier, fanout) in state.video_tiers.fanouts() {
+        video.push(VideoFanoutStatus {
+            tier,
+            connected: fanout.is_connected().await,
+            subscribers: fanout.subscriber_count().await,
+        });
+    }
+
+    Json(Status {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.started.elapsed().as_secs(),
+        fanouts: Fanouts {
+            audio: FanoutStatus {
+                connected: state.audio_fanout.is_connected().await,
+                subscribers: state.audio_fanout.subscriber_count().await,
+            },
+            video,
+            mjpeg: FanoutStatus {
+                connected: state.mjpeg_fanout.is_connected().await,
+                subscribers: state.mjpeg_fanout.subscriber_count().await,
+            },
+        },
+        ptt: {
+            let session_id = state.ptt_state.holder().await;
+            PttStatus {
+                transmitting: session_id.is_some(),
+                session_id,
+            }
+        },
+        sessions: state.sessions.list().await,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct AdminTemplate;
+
+/// `GET /admin` - live status page
+pub async fn admin() -> impl IntoResponse {
+    match AdminTemplate.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Template error: {}", err),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,814 @@
+//! Embedded TURN relay
+//!
+//! Lets viewers behind restrictive NATs or UDP-blocking networks reach birdbox
+//! without running coturn. Birdbox listens for TURN on one port over both UDP and
+//! TCP (RFC 6062 framing), and optionally over TLS (`turns:`, e.g. on 443) with a
+//! certificate from `turn.tls_cert` / `turn.tls_key`.
+//!
+//! The relay only reaches birdbox itself: permissions, channel bindings and Send
+//! indications for any peer other than birdbox's WebRTC UDP port (on its host, LAN
+//! and relay addresses) are refused with 403 Forbidden or dropped before the TURN
+//! server sees them, so valid credentials cannot be used to relay anywhere else.
+//!
+//! Clients never see a static password: each authenticated session (see `auth.rs`)
+//! gets short-lived credentials using the TURN REST API scheme (username = expiry
+//! timestamp, password = HMAC of the username with a server secret), which
+//! `LongTermAuthHandler` validates without any shared state.
+
+use crate::config;
+use crate::host_addrs;
+use crate::ice_servers::IceServer;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{watch, Mutex};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+use webrtc::stun::error_code::CODE_FORBIDDEN;
+use webrtc::stun::message::{Message, MessageType, Setter, CLASS_ERROR_RESPONSE};
+use webrtc::turn::auth::{generate_long_term_credentials, LongTermAuthHandler};
+use webrtc::turn::relay::relay_static::RelayAddressGeneratorStatic;
+use webrtc::turn::server::config::{ConnConfig, ServerConfig};
+use webrtc::turn::server::Server;
+use webrtc::util::vnet::net::Net;
+use webrtc::util::Conn;
+
+/// TURN realm used in authentication challenges
+const REALM: &str = "birdbox";
+
+/// Size of a STUN message header / ChannelData header prefix used for framing
+const FRAME_HEADER_SIZE: usize = 4;
+
+/// STUN message header size (type, length, magic cookie, transaction ID)
+const STUN_HEADER_SIZE: usize = 20;
+
+/// Largest frame accepted over TCP
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// How long a TLS client may take to complete its handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// STUN magic cookie (RFC 5389 §6)
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// STUN message types naming a peer to relay to (RFC 8656 §18)
+const CREATE_PERMISSION_REQUEST: u16 = 0x0008;
+const CHANNEL_BIND_REQUEST: u16 = 0x0009;
+const SEND_INDICATION: u16 = 0x0016;
+
+/// XOR-PEER-ADDRESS attribute type
+const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+
+/// Embedded TURN server settings
+#[derive(Debug, Clone)]
+pub struct TurnConfig {
+    /// UDP and TCP port to listen on
+    pub port: u16,
+    /// Host name or IP clients use to reach the TURN server
+    pub public_host: String,
+    /// IP of this machine used for relay addresses (must be able to reach the WebRTC port)
+    pub relay_ip: IpAddr,
+    /// Secret for minting and validating credentials
+    pub secret: String,
+    /// Lifetime of minted credentials
+    pub credential_ttl: Duration,
+    /// Built-in TURN over TLS listener
+    pub tls: Option<TurnTls>,
+    /// Optional `turns:` URL of an external TLS proxy in front of the TCP port
+    pub tls_url: Option<String>,
+    /// The only transport addresses clients may relay to (birdbox's WebRTC port)
+    pub allowed_peers: Vec<SocketAddr>,
+}
+
+/// TURN over TLS listener settings
+#[derive(Debug, Clone)]
+pub struct TurnTls {
+    pub port: u16,
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+impl TurnConfig {
+    /// Resolve the `[turn]` settings; `None` unless a port is set
+    pub fn from_config(turn: &config::Turn, webrtc: &config::WebRtc) -> Result<Option<Self>> {
+        let Some(port) = turn.port else {
+            return Ok(None);
+        };
+
+        let host_ip = webrtc.host_ip.first().copied();
+        let public_host = turn
+            .host
+            .clone()
+            .or_else(|| host_ip.map(|ip| ip.to_string()))
+            .context("BIRDBOX_TURN_HOST (or BIRDBOX_HOST_IP) must be set for the TURN server")?;
+
+        // Relay traffic only ever goes to birdbox itself, so prefer the LAN address
+        let relay_ip = turn
+            .relay_ip
+            .or_else(|| webrtc.host_ip_lan.first().copied())
+            .or(host_ip)
+            .context(
+                "BIRDBOX_TURN_RELAY_IP (or BIRDBOX_HOST_IP) must be set for the TURN server",
+            )?;
+
+        // Without a configured secret, credentials are only valid until restart
+        let secret = turn
+            .secret
+            .clone()
+            .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+
+        let tls = match (turn.tls_port, &turn.tls_cert, &turn.tls_key) {
+            (Some(port), Some(cert), Some(key)) => Some(TurnTls {
+                port,
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        };
+
+        // Relaying is only ever needed to birdbox's own WebRTC port, on any address
+        // clients were told about plus the relay address itself
+        let mut peer_ips = if webrtc.host_ip.is_empty() {
+            host_addrs::detect()
+        } else {
+            webrtc.host_ip.clone()
+        };
+        peer_ips.extend(&webrtc.host_ip_lan);
+        peer_ips.push(relay_ip);
+        peer_ips.sort();
+        peer_ips.dedup();
+        let allowed_peers = peer_ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, webrtc.udp_port))
+            .collect();
+
+        Ok(Some(Self {
+            port,
+            public_host,
+            relay_ip,
+            secret,
+            credential_ttl: Duration::from_secs(turn.credential_ttl_secs),
+            tls,
+            tls_url: turn.tls_url.clone(),
+            allowed_peers,
+        }))
+    }
+}
+
+/// Running embedded TURN server
+pub struct TurnServer {
+    config: TurnConfig,
+    _udp_server: Server,
+}
+
+impl TurnServer {
+    /// Start the UDP, TCP and (if configured) TLS listeners
+    pub async fn start(config: TurnConfig) -> Result<Arc<Self>> {
+        let bind_addr = SocketAddr::new([0, 0, 0, 0].into(), config.port);
+
+        let udp_socket = UdpSocket::bind(bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind TURN UDP socket to {}", bind_addr))?;
+        let udp_server = Server::new(server_config(&config, Arc::new(udp_socket)))
+            .await
+            .context("Failed to start TURN server")?;
+
+        let tcp_listener = TcpListener::bind(bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind TURN TCP listener to {}", bind_addr))?;
+        spawn_stream_listener(tcp_listener, config.clone(), None);
+
+        if let Some(tls) = &config.tls {
+            let acceptor = tls_acceptor(tls)?;
+            let tls_addr = SocketAddr::new([0, 0, 0, 0].into(), tls.port);
+            let tls_listener = TcpListener::bind(tls_addr)
+                .await
+                .with_context(|| format!("Failed to bind TURN TLS listener to {}", tls_addr))?;
+            spawn_stream_listener(tls_listener, config.clone(), Some(acceptor));
+        }
+
+        info!(
+            "🌐 Embedded TURN server on port {} (UDP+TCP){}, advertised as {}, relaying via {} to {:?} only",
+            config.port,
+            config
+                .tls
+                .as_ref()
+                .map(|tls| format!(" and {} (TLS)", tls.port))
+                .unwrap_or_default(),
+            config.public_host,
+            config.relay_ip,
+            config.allowed_peers
+        );
+
+        Ok(Arc::new(Self {
+            config,
+            _udp_server: udp_server,
+        }))
+    }
+
+    /// Mint short-lived credentials for one client session
+    pub fn ice_server(&self) -> Result<IceServer> {
+        let (username, credential) =
+            generate_long_term_credentials(&self.config.secret, self.config.credential_ttl)
+                .map_err(|e| anyhow::anyhow!("Failed to generate TURN credentials: {}", e))?;
+
+        Ok(IceServer {
+            urls: turn_urls(&self.config),
+            username: Some(username),
+            credential: Some(credential),
+        })
+    }
+}
+
+/// URLs clients use to reach the embedded server
+fn turn_urls(config: &TurnConfig) -> Vec<String> {
+    // IPv6 literals must be bracketed in TURN URIs
+    let host = if config.public_host.contains(':') && !config.public_host.starts_with('[') {
+        format!("[{}]", config.public_host)
+    } else {
+        config.public_host.clone()
+    };
+
+    let mut urls = vec![
+        format!("turn:{}:{}?transport=udp", host, config.port),
+        format!("turn:{}:{}?transport=tcp", host, config.port),
+    ];
+    if let Some(tls) = &config.tls {
+        urls.push(format!("turns:{}:{}?transport=tcp", host, tls.port));
+    }
+    urls.extend(config.tls_url.clone());
+    urls
+}
+
+/// Load the certificate and key for the TLS listener
+fn tls_acceptor(tls: &TurnTls) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TURN TLS certificate {}", tls.cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .with_context(|| format!("Failed to read TURN TLS key {}", tls.key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server_config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TURN TLS")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid TURN TLS certificate or key")?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Accept TURN clients over TCP, or TLS if `acceptor` is set
+fn spawn_stream_listener(listener: TcpListener, config: TurnConfig, acceptor: Option<TlsAcceptor>) {
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("TURN TCP accept error: {:#}", e);
+                    continue;
+                }
+            };
+            let config = config.clone();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let local_addr = match stream.local_addr() {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        debug!("TURN TCP client {} error: {:#}", peer, e);
+                        return;
+                    }
+                };
+                let result = match acceptor {
+                    None => serve_stream_client(config, stream, local_addr, peer).await,
+                    Some(acceptor) => {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => {
+                                serve_stream_client(config, stream, local_addr, peer).await
+                            }
+                            Ok(Err(e)) => {
+                                Err(anyhow::Error::from(e).context("TLS handshake failed"))
+                            }
+                            Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                        }
+                    }
+                };
+                if let Err(e) = result {
+                    debug!("TURN TCP client {} error: {:#}", peer, e);
+                }
+            });
+        }
+    });
+}
+
+fn server_config(config: &TurnConfig, conn: Arc<dyn Conn + Send + Sync>) -> ServerConfig {
+    ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn: Arc::new(PeerFilterConn {
+                inner: conn,
+                allowed_peers: config.allowed_peers.clone(),
+            }),
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: config.relay_ip,
+                address: "0.0.0.0".to_string(),
+                net: Arc::new(Net::new(None)),
+            }),
+        }],
+        realm: REALM.to_string(),
+        auth_handler: Arc::new(LongTermAuthHandler::new(config.secret.clone())),
+        channel_bind_timeout: Duration::from_secs(0),
+        alloc_close_notify: None,
+    }
+}
+
+/// Serve one TURN-over-TCP (or TLS) client with its own server instance until it disconnects
+async fn serve_stream_client<S>(
+    config: TurnConfig,
+    stream: S,
+    local_addr: SocketAddr,
+    peer: SocketAddr,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    debug!("TURN TCP client connected: {}", peer);
+    let (reader, writer) = tokio::io::split(stream);
+    let (closed_tx, mut closed_rx) = watch::channel(false);
+
+    let conn = Arc::new(TcpTurnConn {
+        reader: Mutex::new(reader),
+        writer: Mutex::new(writer),
+        local_addr,
+        peer,
+        closed_tx,
+    });
+    let server = Server::new(server_config(&config, conn))
+        .await
+        .context("Failed to start TURN server for TCP client")?;
+
+    // Allocations belong to this connection and end with it
+    let _ = closed_rx.wait_for(|closed| *closed).await;
+    debug!("TURN TCP client disconnected: {}", peer);
+    server
+        .close()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to close TURN server: {}", e))
+}
+
+/// Length of the TURN frame starting with `header` on a TCP stream (RFC 6062 / RFC 8656 §12.5)
+///
+/// STUN messages carry their body length; ChannelData messages are padded to a
+/// multiple of four bytes over TCP.
+fn frame_len(header: [u8; FRAME_HEADER_SIZE]) -> Option<usize> {
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    match header[0] >> 6 {
+        0b00 => Some(STUN_HEADER_SIZE + length),
+        0b01 => Some(FRAME_HEADER_SIZE + length.div_ceil(4) * 4),
+        _ => None,
+    }
+}
+
+/// Adapts a TCP (or TLS) connection to the packet-oriented `Conn` the TURN server reads from
+struct TcpTurnConn<S> {
+    reader: Mutex<ReadHalf<S>>,
+    writer: Mutex<WriteHalf<S>>,
+    local_addr: SocketAddr,
+    peer: SocketAddr,
+    closed_tx: watch::Sender<bool>,
+}
+
+impl<S> TcpTurnConn<S> {
+    fn closed(&self, reason: impl std::fmt::Display) -> webrtc::util::Error {
+        self.closed_tx.send_replace(true);
+        webrtc::util::Error::Other(format!("TURN TCP connection closed: {}", reason))
+    }
+}
+
+#[async_trait]
+impl<S> Conn for TcpTurnConn<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    async fn connect(&self, _addr: SocketAddr) -> webrtc::util::Result<()> {
+        Err(webrtc::util::Error::Other(
+            "connect not supported".to_string(),
+        ))
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        self.recv_from(buf).await.map(|(n, _)| n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc::util::Result<(usize, SocketAddr)> {
+        let mut reader = self.reader.lock().await;
+
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        reader
+            .read_exact(&mut header)
+            .await
+            .map_err(|e| self.closed(e))?;
+        let len = frame_len(header)
+            .filter(|len| *len <= MAX_FRAME_SIZE)
+            .ok_or_else(|| self.closed("invalid frame"))?;
+
+        let mut frame = vec![0u8; len];
+        frame[..FRAME_HEADER_SIZE].copy_from_slice(&header);
+        reader
+            .read_exact(&mut frame[FRAME_HEADER_SIZE..])
+            .await
+            .map_err(|e| self.closed(e))?;
+
+        // Hand over ChannelData without the TCP padding
+        let payload_len = if header[0] >> 6 == 0b01 {
+            FRAME_HEADER_SIZE + u16::from_be_bytes([header[2], header[3]]) as usize
+        } else {
+            len
+        };
+        if payload_len > buf.len() {
+            return Err(webrtc::util::Error::Other("buffer too small".to_string()));
+        }
+        buf[..payload_len].copy_from_slice(&frame[..payload_len]);
+        Ok((payload_len, self.peer))
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc::util::Result<usize> {
+        self.send_to(buf, self.peer).await
+    }
+
+    async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> webrtc::util::Result<usize> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(buf).await.map_err(|e| self.closed(e))?;
+
+        // ChannelData must be padded to a multiple of four bytes over TCP
+        let padding = if buf.first().is_some_and(|b| b >> 6 == 0b01) {
+            (4 - buf.len() % 4) % 4
+        } else {
+            0
+        };
+        if padding > 0 {
+            writer
+                .write_all(&[0u8; 3][..padding])
+                .await
+                .map_err(|e| self.closed(e))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn local_addr(&self) -> webrtc::util::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.peer)
+    }
+
+    async fn close(&self) -> webrtc::util::Result<()> {
+        self.closed_tx.send_replace(true);
+        let _ = self.writer.lock().await.shutdown().await;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+/// What to do with a message from a TURN client
+#[derive(Debug, PartialEq)]
+enum PeerCheck {
+    /// Hand it to the TURN server
+    Allow,
+    /// Discard it (indications get no response)
+    Drop,
+    /// Answer with this error response instead
+    Reject(Vec<u8>),
+}
+
+/// Check the peers a CreatePermission, ChannelBind or Send names against `allowed`
+///
+/// Anything else, including ChannelData and malformed messages, is left to the
+/// TURN server. A message whose peer addresses can't be parsed is refused.
+fn check_peers(msg: &[u8], allowed: &[SocketAddr]) -> PeerCheck {
+    if msg.len() < STUN_HEADER_SIZE
+        || u32::from_be_bytes([msg[4], msg[5], msg[6], msg[7]]) != STUN_MAGIC_COOKIE
+    {
+        return PeerCheck::Allow;
+    }
+    let msg_type = u16::from_be_bytes([msg[0], msg[1]]);
+    if !matches!(
+        msg_type,
+        CREATE_PERMISSION_REQUEST | CHANNEL_BIND_REQUEST | SEND_INDICATION
+    ) {
+        return PeerCheck::Allow;
+    }
+
+    let allowed = xor_peer_addresses(msg).is_some_and(|peers| {
+        peers.iter().all(|peer| {
+            allowed.iter().any(|a| {
+                a.port() == peer.port() && a.ip().to_canonical() == peer.ip().to_canonical()
+            })
+        })
+    });
+    if allowed {
+        PeerCheck::Allow
+    } else if msg_type == SEND_INDICATION {
+        PeerCheck::Drop
+    } else {
+        forbidden_response(msg).map_or(PeerCheck::Drop, PeerCheck::Reject)
+    }
+}
+
+/// All XOR-PEER-ADDRESS attributes of a STUN message; `None` if malformed
+fn xor_peer_addresses(msg: &[u8]) -> Option<Vec<SocketAddr>> {
+    let length = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    let body = msg.get(STUN_HEADER_SIZE..STUN_HEADER_SIZE + length)?;
+    // Magic cookie followed by the transaction ID
+    let key = &msg[4..STUN_HEADER_SIZE];
+
+    let mut peers = Vec::new();
+    let mut offset = 0;
+    while offset < body.len() {
+        let header = body.get(offset..offset + 4)?;
+        let attr_type = u16::from_be_bytes([header[0], header[1]]);
+        let attr_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let value = body.get(offset + 4..offset + 4 + attr_len)?;
+        if attr_type == ATTR_XOR_PEER_ADDRESS {
+            peers.push(decode_xor_address(value, key)?);
+        }
+        offset += 4 + attr_len.div_ceil(4) * 4;
+    }
+    Some(peers)
+}
+
+/// Decode an XOR-PEER-ADDRESS value (RFC 5389 §15.2)
+fn decode_xor_address(value: &[u8], key: &[u8]) -> Option<SocketAddr> {
+    let port = u16::from_be_bytes([value.get(2)? ^ key[0], value.get(3)? ^ key[1]]);
+    let xored = |len: usize| -> Option<Vec<u8>> {
+        let address = value.get(4..4 + len).filter(|_| value.len() == 4 + len)?;
+        Some(address.iter().zip(key).map(|(a, k)| a ^ k).collect())
+    };
+    let ip: IpAddr = match value.get(1)? {
+        0x01 => Ipv4Addr::from(<[u8; 4]>::try_from(xored(4)?).ok()?).into(),
+        0x02 => Ipv6Addr::from(<[u8; 16]>::try_from(xored(16)?).ok()?).into(),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// 403 Forbidden error response to a STUN request
+fn forbidden_response(request: &[u8]) -> Option<Vec<u8>> {
+    let mut request_msg = Message::new();
+    request_msg.raw = request.to_vec();
+    request_msg.decode().ok()?;
+
+    let setters: Vec<Box<dyn Setter>> = vec![
+        Box::new(request_msg.transaction_id),
+        Box::new(MessageType::new(
+            request_msg.typ.method,
+            CLASS_ERROR_RESPONSE,
+        )),
+        Box::new(CODE_FORBIDDEN),
+    ];
+    let mut response = Message::new();
+    response.build(&setters).ok()?;
+    Some(response.raw)
+}
+
+/// Keeps clients from relaying anywhere but birdbox's WebRTC port
+///
+/// The TURN server would create permissions and channels for any peer a client
+/// with valid credentials names, which makes it an open relay. This sits between
+/// the listener and the server and answers requests naming another peer itself.
+struct PeerFilterConn {
+    inner: Arc<dyn Conn + Send + Sync>,
+    allowed_peers: Vec<SocketAddr>,
+}
+
+#[async_trait]
+impl Conn for PeerFilterConn {
+    async fn connect(&self, addr: SocketAddr) -> webrtc::util::Result<()> {
+        self.inner.connect(addr).await
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        self.recv_from(buf).await.map(|(n, _)| n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc::util::Result<(usize, SocketAddr)> {
+        loop {
+            let (n, addr) = self.inner.recv_from(buf).await?;
+            match check_peers(&buf[..n], &self.allowed_peers) {
+                PeerCheck::Allow => return Ok((n, addr)),
+                PeerCheck::Drop => {
+                    debug!("TURN: dropped a Send to a foreign peer from {}", addr);
+                }
+                PeerCheck::Reject(response) => {
+                    debug!("TURN: refused to relay to a foreign peer for {}", addr);
+                    self.inner.send_to(&response, addr).await?;
+                }
+            }
+        }
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc::util::Result<usize> {
+        self.inner.send(buf).await
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc::util::Result<usize> {
+        self.inner.send_to(buf, target).await
+    }
+
+    fn local_addr(&self) -> webrtc::util::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    async fn close(&self) -> webrtc::util::Result<()> {
+        self.inner.close().await
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::stun::agent::TransactionId;
+    use webrtc::stun::error_code::ErrorCodeAttribute;
+    use webrtc::stun::message::{
+        Getter, MessageClass, Method, CLASS_INDICATION, CLASS_REQUEST, METHOD_ALLOCATE,
+        METHOD_CHANNEL_BIND, METHOD_CREATE_PERMISSION, METHOD_SEND,
+    };
+    use webrtc::turn::proto::peeraddr::PeerAddress;
+
+    fn config(public_host: &str, tls_url: Option<&str>) -> TurnConfig {
+        TurnConfig {
+            port: 3478,
+            public_host: public_host.to_string(),
+            relay_ip: "192.0.2.10".parse().unwrap(),
+            secret: "secret".to_string(),
+            credential_ttl: Duration::from_secs(60),
+            tls: None,
+            tls_url: tls_url.map(str::to_string),
+            allowed_peers: vec!["192.0.2.10:50000".parse().unwrap()],
+        }
+    }
+
+    /// STUN message naming `peers`, as a TURN client encodes it
+    fn message(method: Method, class: MessageClass, peers: &[&str]) -> Vec<u8> {
+        let mut setters: Vec<Box<dyn Setter>> = vec![
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(method, class)),
+        ];
+        for peer in peers {
+            let peer: SocketAddr = peer.parse().unwrap();
+            setters.push(Box::new(PeerAddress {
+                ip: peer.ip(),
+                port: peer.port(),
+            }));
+        }
+        let mut msg = Message::new();
+        msg.build(&setters).unwrap();
+        msg.raw
+    }
+
+    #[test]
+    fn test_frame_len_stun() {
+        // Binding request with a 12-byte body
+        assert_eq!(frame_len([0x00, 0x01, 0x00, 0x0c]), Some(32));
+    }
+
+    #[test]
+    fn test_frame_len_channel_data_is_padded() {
+        assert_eq!(frame_len([0x40, 0x00, 0x00, 0x05]), Some(12));
+        assert_eq!(frame_len([0x40, 0x00, 0x00, 0x08]), Some(12));
+    }
+
+    #[test]
+    fn test_frame_len_rejects_unknown() {
+        assert_eq!(frame_len([0x80, 0x00, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn test_turn_urls() {
+        assert_eq!(
+            turn_urls(&config(
+                "turn.example.com",
+                Some("turns:turn.example.com:443")
+            )),
+            vec![
+                "turn:turn.example.com:3478?transport=udp",
+                "turn:turn.example.com:3478?transport=tcp",
+                "turns:turn.example.com:443",
+            ]
+        );
+        assert_eq!(
+            turn_urls(&config("2001:db8::1", None))[0],
+            "turn:[2001:db8::1]:3478?transport=udp"
+        );
+    }
+
+    #[test]
+    fn test_turn_urls_with_tls_listener() {
+        let mut config = config("turn.example.com", None);
+        config.tls = Some(TurnTls {
+            port: 443,
+            cert: "cert.pem".into(),
+            key: "key.pem".into(),
+        });
+        assert_eq!(
+            turn_urls(&config)[2],
+            "turns:turn.example.com:443?transport=tcp"
+        );
+    }
+
+    #[test]
+    fn test_xor_peer_addresses() {
+        let msg = message(
+            METHOD_CREATE_PERMISSION,
+            CLASS_REQUEST,
+            &["192.0.2.10:50000", "[2001:db8::7]:3478"],
+        );
+        assert_eq!(
+            xor_peer_addresses(&msg),
+            Some(vec![
+                "192.0.2.10:50000".parse().unwrap(),
+                "[2001:db8::7]:3478".parse().unwrap()
+            ])
+        );
+        // Truncated attribute
+        let mut truncated = msg.clone();
+        truncated[3] += 4;
+        truncated.extend([0x00, 0x12, 0x00, 0x08]);
+        assert_eq!(xor_peer_addresses(&truncated), None);
+    }
+
+    #[test]
+    fn test_check_peers_allows_birdbox() {
+        let allowed = config("turn.example.com", None).allowed_peers;
+        for (method, class) in [
+            (METHOD_CREATE_PERMISSION, CLASS_REQUEST),
+            (METHOD_CHANNEL_BIND, CLASS_REQUEST),
+            (METHOD_SEND, CLASS_INDICATION),
+        ] {
+            let msg = message(method, class, &["192.0.2.10:50000"]);
+            assert_eq!(check_peers(&msg, &allowed), PeerCheck::Allow);
+        }
+        // Messages without peers and ChannelData go to the server untouched
+        let allocate = message(METHOD_ALLOCATE, CLASS_REQUEST, &[]);
+        assert_eq!(check_peers(&allocate, &allowed), PeerCheck::Allow);
+        assert_eq!(
+            check_peers(&[0x40, 0x00, 0x00, 0x04, 1, 2, 3, 4], &allowed),
+            PeerCheck::Allow
+        );
+    }
+
+    #[test]
+    fn test_check_peers_rejects_other_peers() {
+        let allowed = config("turn.example.com", None).allowed_peers;
+
+        // Right host, wrong port (permissions alone would allow any port)
+        let send = message(METHOD_SEND, CLASS_INDICATION, &["192.0.2.10:22"]);
+        assert_eq!(check_peers(&send, &allowed), PeerCheck::Drop);
+
+        let request = message(
+            METHOD_CREATE_PERMISSION,
+            CLASS_REQUEST,
+            &["192.0.2.10:50000", "198.51.100.1:50000"],
+        );
+        let PeerCheck::Reject(response) = check_peers(&request, &allowed) else {
+            panic!("CreatePermission to a foreign peer was not rejected");
+        };
+        let mut msg = Message::new();
+        msg.raw = response;
+        msg.decode().unwrap();
+        assert_eq!(
+            msg.typ,
+            MessageType::new(METHOD_CREATE_PERMISSION, CLASS_ERROR_RESPONSE)
+        );
+        assert_eq!(msg.transaction_id.0, request[8..20]);
+        let mut error = ErrorCodeAttribute::default();
+        error.get_from(&msg).unwrap();
+        assert!(error.code == CODE_FORBIDDEN);
+
+        let bind = message(METHOD_CHANNEL_BIND, CLASS_REQUEST, &["[2001:db8::7]:50000"]);
+        assert!(matches!(check_peers(&bind, &allowed), PeerCheck::Reject(_)));
+    }
+}
@@ -7,11 +7,12 @@
 //! - Automatically disconnects after a grace period when all subscribers leave
 //! - Passes raw H.264 packets without transcoding
 
-use crate::h264_extractor::{H264Extractor, H264Packet};
+use crate::h264_extractor::{H264Extractor, H264Packet, VideoStreamInfo};
+use crate::metrics::METRICS;
 use anyhow::{Context, Result};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, watch, RwLock};
 use tokio::time::sleep;
 use tracing::{debug, error, info};
 
@@ -47,6 +48,7 @@ pub struct VideoFanout {
     rtsp_url: String,
     rtsp_transport: String,
     broadcast_tx: broadcast::Sender<H264Packet>,
+    stream_info_tx: watch::Sender<Option<VideoStreamInfo>>,
     state: Arc<RwLock<FanoutState>>,
 }
 
@@ -64,6 +66,7 @@ impl VideoFanout {
             rtsp_url,
             rtsp_transport: rtsp_transport.to_string(),
             broadcast_tx,
+            stream_info_tx: watch::Sender::new(None),
             state: Arc::new(RwLock::new(FanoutState {
                 connection_state: ConnectionState::Disconnected,
                 subscriber_count: 0,
@@ -125,6 +128,7 @@ impl VideoFanout {
 
             // Connect and stream
             info!("Connecting to DoorBird video stream...");
+            METRICS.fanout_connects.inc(&[("fanout", "video")]);
             {
                 let mut state = self.state.write().await;
                 state.connection_state = ConnectionState::Connecting;
@@ -136,6 +140,7 @@ impl VideoFanout {
                 }
                 Err(e) => {
                     error!("DoorBird video stream error: {:#}", e);
+                    METRICS.fanout_errors.inc(&[("fanout", "video")]);
                     // Wait before retry
                     sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
                 }
@@ -179,6 +184,7 @@ impl VideoFanout {
         let rtsp_url = self.rtsp_url.clone();
         let rtsp_transport = self.rtsp_transport.clone();
         let broadcast_tx = self.broadcast_tx.clone();
+        let stream_info_tx = self.stream_info_tx.clone();
         let state_clone = Arc::clone(&self.state);
 
         // Run packet extraction in a spawn_blocking task to avoid Send issues
@@ -193,6 +199,9 @@ impl VideoFanout {
             };
 
             info!("Successfully connected to DoorBird video stream");
+            if let Some(info) = extractor.stream_info() {
+                stream_info_tx.send_replace(Some(info));
+            }
 
             // Process video packets
             loop {
@@ -243,10 +252,14 @@ impl VideoFanout {
         Ok(())
     }
 
+    /// Resolution of the stream, known after the first successful connection
+    pub fn stream_info(&self) -> Option<VideoStreamInfo> {
+        *self.stream_info_tx.borrow()
+    }
+
     /// Get current subscriber count
     ///
     /// Useful for debugging, monitoring endpoints, or metrics collection.
-    #[allow(dead_code)]
     pub async fn subscriber_count(&self) -> usize {
         let state = self.state.read().await;
         state.subscriber_count
@@ -255,7 +268,6 @@ impl VideoFanout {
     /// Check if currently connected to DoorBird
     ///
     /// Useful for debugging, monitoring endpoints, or health checks.
-    #[allow(dead_code)]
     pub async fn is_connected(&self) -> bool {
         let state = self.state.read().await;
         state.connection_state == ConnectionState::Connected
@@ -0,0 +1,87 @@
+//! Video quality tiers (simulcast) for DoorBird video streaming
+//!
+//! The DoorBird exposes the same camera at several RTSP resolutions. This module
+//! groups one `VideoFanout` per resolution so that each WebRTC session can pick
+//! (and switch) the tier it receives:
+//! - `High` is always available and uses the best resolution the device supports
+//! - `Low` uses the device default resolution and is only available when it differs
+//!   from the high tier and simulcast is enabled
+//!
+//! Each fanout connects to the DoorBird lazily, so a tier nobody is watching costs
+//! nothing and the device only sees a second RTSP connection while both are in use.
+
+use crate::video_fanout::VideoFanout;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Video quality tier a viewer can receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoTier {
+    /// Device default resolution (lower bitrate)
+    Low,
+    /// Best resolution supported by the device
+    High,
+}
+
+impl VideoTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoTier::Low => "low",
+            VideoTier::High => "high",
+        }
+    }
+}
+
+/// Set of video fanouts, one per available quality tier
+pub struct VideoTiers {
+    high: Arc<VideoFanout>,
+    low: Option<Arc<VideoFanout>>,
+}
+
+impl VideoTiers {
+    /// Creates a tier set
+    ///
+    /// # Arguments
+    /// * `high` - Fanout for the best supported resolution
+    /// * `low` - Optional fanout for the device default resolution
+    pub fn new(high: Arc<VideoFanout>, low: Option<Arc<VideoFanout>>) -> Arc<Self> {
+        Arc::new(Self { high, low })
+    }
+
+    /// Resolve a requested tier to one that is actually available
+    ///
+    /// Falls back to `High` when the low tier isn't configured.
+    pub fn resolve(&self, tier: VideoTier) -> VideoTier {
+        match tier {
+            VideoTier::Low if self.low.is_some() => VideoTier::Low,
+            _ => VideoTier::High,
+        }
+    }
+
+    /// Get the fanout serving a tier (after resolving fallbacks)
+    pub fn get(&self, tier: VideoTier) -> Arc<VideoFanout> {
+        match (self.resolve(tier), &self.low) {
+            (VideoTier::Low, Some(low)) => Arc::clone(low),
+            _ => Arc::clone(&self.high),
+        }
+    }
+
+    /// List of tiers that can be selected, best first
+    pub fn available(&self) -> Vec<VideoTier> {
+        let mut tiers = vec![VideoTier::High];
+        if self.low.is_some() {
+            tiers.push(VideoTier::Low);
+        }
+        tiers
+    }
+
+    /// Iterate over all configured fanouts with their tier
+    pub fn fanouts(&self) -> Vec<(VideoTier, Arc<VideoFanout>)> {
+        let mut fanouts = vec![(VideoTier::High, Arc::clone(&self.high))];
+        if let Some(low) = &self.low {
+            fanouts.push((VideoTier::Low, Arc::clone(low)));
+        }
+        fanouts
+    }
+}
@@ -1,54 +1,61 @@
 use crate::audio_fanout::AudioFanout;
 use crate::audio_transcode::ReverseAudioTranscoder;
+use crate::bandwidth::{feedback_from_rtcp, BandwidthConfig, BandwidthController, RtcpFeedback};
+use crate::config;
+use crate::h264_extractor::H264Packet;
+use crate::host_addrs;
+use crate::ice_servers::{self, IceServer};
+use crate::jitter_buffer::{
+    JitterBuffer, JitterStats, Playout, RtpAudio, FRAME_DURATION, MAX_PACKET_FRAMES,
+};
+use crate::metrics::{Track, METRICS};
+use crate::sessions::{cancel_on_disconnect, Traffic};
+use crate::signaling::SignalMessage;
 use crate::video_fanout::VideoFanout;
-use anyhow::Result;
+use crate::video_tiers::{VideoTier, VideoTiers};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 use axum::extract::ws::Message;
 use bytes::Bytes;
 use futures_util::stream::StreamExt;
-use std::net::IpAddr;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::sync::{mpsc::UnboundedSender, Mutex};
+use tokio::sync::{broadcast, mpsc, mpsc::UnboundedSender, watch, Mutex};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 use uuid::Uuid;
+use webrtc::api::interceptor_registry::{configure_rtcp_reports, configure_twcc};
 use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
 use webrtc::api::APIBuilder;
 use webrtc::api::API;
 use webrtc::ice::udp_mux::*;
 use webrtc::ice::udp_network::UDPNetwork;
 use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::interceptor::nack::{generator::Generator, responder::Responder};
 use webrtc::interceptor::registry::Registry;
 use webrtc::media::Sample;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::RTCPFeedback;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
 use webrtc::track::track_local::TrackLocal;
-
-/// Auto-detect the local LAN IP address
-/// Returns the first non-loopback IPv4 address found on any network interface
-fn get_local_ip() -> Option<String> {
-    use std::net::UdpSocket as StdUdpSocket;
-
-    // Trick: Create a UDP socket connected to a public IP (doesn't actually send data)
-    // The OS will select the appropriate local interface for routing
-    let socket = StdUdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    let local_addr = socket.local_addr().ok()?;
-
-    match local_addr.ip() {
-        IpAddr::V4(ip) if !ip.is_loopback() => Some(ip.to_string()),
-        _ => None,
-    }
-}
+use webrtc::track::track_remote::TrackRemote;
+use webrtc::util::Conn;
 
 /// Bind a UDP socket with SO_REUSEADDR to allow quick rebinding after close
-async fn bind_udp_socket(addr: &str) -> Result<UdpSocket> {
+///
+/// Binding to the IPv6 wildcard clears `IPV6_V6ONLY`, so one socket serves both
+/// IPv4 and IPv6 clients.
+fn bind_udp_socket(addr: SocketAddr) -> Result<UdpSocket> {
     use socket2::{Domain, Protocol, Socket, Type};
-    use std::net::SocketAddr;
 
-    let addr: SocketAddr = addr.parse()?;
     let domain = if addr.is_ipv4() {
         Domain::IPV4
     } else {
@@ -68,28 +75,255 @@ async fn bind_udp_socket(addr: &str) -> Result<UdpSocket> {
     #[cfg(unix)]
     socket.set_reuse_port(true)?;
 
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+
     socket.bind(&addr.into())?;
     socket.set_nonblocking(true)?;
 
     Ok(UdpSocket::from_std(socket.into())?)
 }
 
+/// Bind the WebRTC UDP socket, with a hint about the usual causes on failure
+fn bind_mux_socket(addr: SocketAddr) -> Result<UdpSocket> {
+    let socket = bind_udp_socket(addr).with_context(|| {
+        format!(
+            "Failed to bind WebRTC UDP socket to {}. Check if the port is already in use \
+            or if BIRDBOX_BIND_IP is not an address of this host",
+            addr
+        )
+    })?;
+    info!(
+        "🌐 Bound WebRTC UDP socket to {} (shared across all sessions)",
+        addr
+    );
+    Ok(socket)
+}
+
+/// Prefix of the readiness probe datagrams sent to the mux socket
+const MUX_PROBE_MAGIC: &[u8; 8] = b"bbxprobe";
+
+/// How often an unanswered probe datagram is sent again
+const MUX_PROBE_RESEND: Duration = Duration::from_millis(200);
+
+/// Probe datagrams read by the mux socket (see `WebRtcInfra::probe_udp_mux`)
+#[derive(Default)]
+struct MuxProbe {
+    /// Token of the last probe sent
+    sent: AtomicU64,
+    /// Highest probe token the mux has read
+    seen: watch::Sender<u64>,
+}
+
+impl MuxProbe {
+    /// Note a datagram read from the socket if it is a probe
+    fn record(&self, datagram: &[u8]) {
+        if let Some(token) = datagram.strip_prefix(MUX_PROBE_MAGIC) {
+            if let Ok(token) = <[u8; 8]>::try_from(token) {
+                let token = u64::from_be_bytes(token);
+                self.seen.send_if_modified(|seen| {
+                    let newer = token > *seen;
+                    *seen = (*seen).max(token);
+                    newer
+                });
+            }
+        }
+    }
+}
+
+/// UDP socket for the ICE mux that hides IPv4-mapped IPv6 addresses
+///
+/// On a dual-stack socket IPv4 peers show up as `::ffff:a.b.c.d`, which would
+/// never match the IPv4 candidates ICE knows them by. Addresses are unmapped on
+/// receive and mapped again on send. Readiness probes are noted on receive; the
+/// mux then drops them like any datagram of an unknown peer.
+struct MuxSocket {
+    socket: UdpSocket,
+    dual_stack: bool,
+    probe: Arc<MuxProbe>,
+}
+
+impl MuxSocket {
+    fn new(socket: UdpSocket, probe: Arc<MuxProbe>) -> Result<Self> {
+        let local_addr = socket.local_addr()?;
+        Ok(Self {
+            dual_stack: local_addr.is_ipv6() && local_addr.ip().is_unspecified(),
+            socket,
+            probe,
+        })
+    }
+
+    fn unmap(addr: SocketAddr) -> SocketAddr {
+        match addr {
+            SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+                Some(v4) => SocketAddr::new(v4.into(), v6.port()),
+                None => addr,
+            },
+            SocketAddr::V4(_) => addr,
+        }
+    }
+
+    fn map(&self, addr: SocketAddr) -> SocketAddr {
+        match addr {
+            SocketAddr::V4(v4) if self.dual_stack => {
+                SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port())
+            }
+            _ => addr,
+        }
+    }
+}
+
+#[async_trait]
+impl Conn for MuxSocket {
+    async fn connect(&self, addr: SocketAddr) -> webrtc::util::Result<()> {
+        Ok(self.socket.connect(self.map(addr)).await?)
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        Ok(self.socket.recv(buf).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc::util::Result<(usize, SocketAddr)> {
+        let (n, addr) = self.socket.recv_from(buf).await?;
+        self.probe.record(&buf[..n]);
+        Ok((n, Self::unmap(addr)))
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc::util::Result<usize> {
+        Ok(self.socket.send(buf).await?)
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc::util::Result<usize> {
+        Ok(self.socket.send_to(buf, self.map(target)).await?)
+    }
+
+    fn local_addr(&self) -> webrtc::util::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.socket.peer_addr().ok().map(Self::unmap)
+    }
+
+    async fn close(&self) -> webrtc::util::Result<()> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+/// Default number of sent RTP packets kept per track for NACK retransmission
+/// (~10s of 1080p video at typical DoorBird bitrates)
+pub const DEFAULT_NACK_BUFFER_PACKETS: u16 = 1024;
+
+/// Largest NACK send buffer supported by the responder interceptor
+pub const MAX_NACK_BUFFER_PACKETS: u16 = 1 << 15;
+
+/// NACK send buffer size for the configured number of packets per track
+///
+/// The responder stores packets in a ring buffer whose size must be a power of two,
+/// so other values are rounded up. Returns the log2 of the size.
+fn nack_buffer_log2_size(packets: u16) -> u8 {
+    let size = packets
+        .checked_next_power_of_two()
+        .unwrap_or(MAX_NACK_BUFFER_PACKETS)
+        .min(MAX_NACK_BUFFER_PACKETS);
+    if size != packets {
+        info!(
+            "NACK buffer size rounded from {} to {} packets (must be a power of two)",
+            packets, size
+        );
+    }
+    size.trailing_zeros() as u8
+}
+
+/// Register NACK feedback and interceptors with a custom responder buffer size
+///
+/// Same as webrtc-rs' `configure_nack`, except the responder (which keeps recently
+/// sent packets so it can retransmit them when the browser reports a loss) is
+/// sized from configuration instead of the library default.
+fn configure_nack(
+    mut registry: Registry,
+    media_engine: &mut MediaEngine,
+    responder_log2_size: u8,
+) -> Registry {
+    media_engine.register_feedback(
+        RTCPFeedback {
+            typ: "nack".to_owned(),
+            parameter: "".to_owned(),
+        },
+        RTPCodecType::Video,
+    );
+    media_engine.register_feedback(
+        RTCPFeedback {
+            typ: "nack".to_owned(),
+            parameter: "pli".to_owned(),
+        },
+        RTPCodecType::Video,
+    );
+
+    registry.add(Box::new(
+        Responder::builder().with_log2_size(responder_log2_size),
+    ));
+    registry.add(Box::new(Generator::builder()));
+    registry
+}
+
 /// Shared WebRTC infrastructure - created once at startup and shared across all sessions
 pub struct WebRtcInfra {
     api: API,
+    /// Adaptive video tier settings applied to new sessions (reloadable)
+    bandwidth_config: std::sync::RwLock<BandwidthConfig>,
+    /// STUN/TURN servers handed to clients for NAT traversal (reloadable)
+    ice_servers: std::sync::RwLock<Vec<IceServer>>,
     // Keep the UDP mux alive to prevent "buffer: closed" errors
     // The mux owns the UDP socket, so keeping the mux alive keeps the socket alive
     _udp_mux: Arc<UDPMuxDefault>,
+    /// Local address of the mux socket
+    udp_addr: SocketAddr,
+    /// Readiness probes read by the mux socket
+    mux_probe: Arc<MuxProbe>,
 }
 
 impl WebRtcInfra {
     /// Initialize the shared WebRTC infrastructure with a persistent UDP mux
-    pub async fn new() -> Result<Arc<Self>> {
+    pub async fn new(config: &config::WebRtc, ice: &config::Ice) -> Result<Arc<Self>> {
         // MediaEngine with Opus and H.264 codec support
         let mut media_engine = MediaEngine::default();
         media_engine.register_default_codecs()?;
 
-        let registry = Registry::new();
+        // Standard interceptors: NACK generation/response, RTCP sender/receiver reports
+        // and TWCC. TWCC is registered in both directions: the sender side adds the
+        // transport-wide sequence number extension to what we send, so the browser
+        // returns TWCC feedback for our video. Receiver reports, TWCC feedback and REMB
+        // from the browser feed each session's bandwidth controller.
+        //
+        // The NACK responder retransmits video packets the browser reports lost, so
+        // short bursts of Wi-Fi loss recover without waiting for the next keyframe.
+        let nack_log2_size = nack_buffer_log2_size(config.nack_buffer_packets);
+        info!(
+            "📶 NACK retransmission buffer: {} packets per track",
+            1u32 << nack_log2_size
+        );
+        let registry = configure_nack(Registry::new(), &mut media_engine, nack_log2_size);
+        let registry = configure_rtcp_reports(registry);
+        let registry = configure_twcc(registry, &mut media_engine)?;
+
+        // Adaptive video tier configuration
+        let bandwidth_config = config.bandwidth();
+        info!(
+            "📶 Adaptive video: {} (high tier >= {} kbps, video >= {} kbps)",
+            if bandwidth_config.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            bandwidth_config.high_min_kbps,
+            bandwidth_config.video_min_kbps
+        );
 
         // Configure NAT 1:1 mapping and UDP mux for Docker deployment
         let mut setting_engine = webrtc::api::setting_engine::SettingEngine::default();
@@ -112,96 +346,79 @@ impl WebRtcInfra {
         // - BIRDBOX_HOST_IP: Public/primary IP for external clients
         // - BIRDBOX_HOST_IP_LAN: LAN IP for internal clients (optional)
         // - If both set, both IPs advertised → WebRTC auto-selects best route
+        // - Each accepts an IPv4 or IPv6 address, or a comma-separated list
         // ═══════════════════════════════════════════════════════════════════════════
 
         // Determine which IP(s) to advertise for WebRTC ICE candidates
-        let host_ip = if let Ok(ip) = std::env::var("BIRDBOX_HOST_IP") {
-            info!("🌐 Using BIRDBOX_HOST_IP from environment: {}", ip);
-            ip
+        let host_ips = if !config.host_ip.is_empty() {
+            info!("🌐 Using configured host IP(s): {:?}", config.host_ip);
+            config.host_ip.clone()
         } else {
-            // Auto-detect LAN IP when BIRDBOX_HOST_IP not set (for non-Docker deployments)
-            match get_local_ip() {
-                Some(ip) => {
-                    info!("🌐 Auto-detected local IP: {}", ip);
-                    ip
-                }
-                None => {
-                    info!("🌐 Could not auto-detect local IP, binding to all interfaces");
-                    "0.0.0.0".to_string()
-                }
+            // Auto-detect from the network interfaces (for non-Docker deployments)
+            let ips = host_addrs::detect();
+            if ips.is_empty() {
+                info!("🌐 Could not auto-detect local IP, using discovered candidates");
+            } else {
+                info!("🌐 Auto-detected local IP(s): {:?}", ips);
             }
+            ips
         };
 
-        // Check for optional LAN IP for dual-network setups
-        let host_ip_lan = std::env::var("BIRDBOX_HOST_IP_LAN").ok();
+        // Optional LAN IP(s) for dual-network setups
+        let host_ips_lan = &config.host_ip_lan;
 
         // Use UDP mux to multiplex all WebRTC traffic over a single UDP port
-        let udp_port = std::env::var("BIRDBOX_UDP_PORT")
-            .ok()
-            .and_then(|s| s.parse::<u16>().ok())
-            .unwrap_or(50000);
-
-        // Build list of IPs to advertise as ICE candidates
-        let mut advertised_ips = Vec::new();
+        let udp_port = config.udp_port;
 
-        // Determine bind address: default to 0.0.0.0 for maximum compatibility
-        // This allows connections from localhost, LAN, and external networks
+        // Determine bind address: default to all interfaces, IPv4 and IPv6, for maximum
+        // compatibility. This allows connections from localhost, LAN and external networks.
         // Advanced users can override with BIRDBOX_BIND_IP to restrict to a specific interface
-        let bind_addr = if let Ok(bind_ip) = std::env::var("BIRDBOX_BIND_IP") {
-            info!("🌐 Using BIRDBOX_BIND_IP from environment: {}", bind_ip);
-            format!("{}:{}", bind_ip, udp_port)
+        let udp_socket = if let Some(bind_ip) = config.bind_ip {
+            info!("🌐 Using configured bind IP: {}", bind_ip);
+            bind_mux_socket(SocketAddr::new(bind_ip, udp_port))?
         } else {
-            // Default: bind to all interfaces (localhost + LAN + external)
-            format!("0.0.0.0:{}", udp_port)
-        };
-
-        let udp_socket = match bind_udp_socket(&bind_addr).await {
-            Ok(socket) => {
-                info!(
-                    "🌐 Bound WebRTC UDP socket to {} (shared across all sessions)",
-                    bind_addr
-                );
-                socket
-            }
-            Err(e) => {
-                anyhow::bail!(
-                    "Failed to bind WebRTC UDP socket to {}: {}. \
-                    Check if the port is already in use or if BIRDBOX_BIND_IP is set to an invalid address.",
-                    bind_addr, e
-                );
+            // Default: dual-stack wildcard, falling back to IPv4 on hosts without IPv6
+            let dual_stack = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), udp_port);
+            match bind_udp_socket(dual_stack) {
+                Ok(socket) => {
+                    info!(
+                        "🌐 Bound WebRTC UDP socket to {} (IPv4 + IPv6, shared across all sessions)",
+                        dual_stack
+                    );
+                    socket
+                }
+                Err(e) => {
+                    warn!("IPv6 unavailable ({}), binding IPv4 only", e);
+                    bind_mux_socket(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), udp_port))?
+                }
             }
         };
 
         // Determine which IPs to advertise based on configuration
-        // In dual-IP mode, advertise both; in single-IP mode, advertise one
-        if let Some(lan_ip) = &host_ip_lan {
-            // Dual-IP mode: advertise both LAN and public IPs
-            // Filter out localhost (127.0.0.1) from NAT mapping as it's not a valid external IP
-            if lan_ip != "127.0.0.1" && lan_ip != "::1" {
-                advertised_ips.push(lan_ip.clone());
-            }
-            if &host_ip != lan_ip && host_ip != "0.0.0.0" && host_ip != "127.0.0.1" {
-                advertised_ips.push(host_ip.clone());
-            }
+        // In dual-IP mode, advertise both; in single-IP mode, advertise one per address
+        // family. Loopback and wildcard addresses are never advertised.
+        let advertised_ips = host_addrs::advertised(&host_ips, host_ips_lan);
+        if advertised_ips.is_empty() {
+            info!("🌐 No specific IPs to advertise (will use discovered candidates)");
+        } else if !host_ips_lan.is_empty() {
             info!(
                 "🌐 Dual-network mode: advertising {} ICE candidate(s): {:?}",
                 advertised_ips.len(),
                 advertised_ips
             );
-        } else if host_ip != "0.0.0.0" && host_ip != "127.0.0.1" {
-            // Single-IP mode: advertise only BIRDBOX_HOST_IP
-            advertised_ips.push(host_ip.clone());
-            info!(
-                "🌐 Single-network mode: advertising ICE candidate: {}",
-                host_ip
-            );
         } else {
             info!(
-                "🌐 No specific IPs to advertise (binding to 0.0.0.0, will use discovered candidates)"
+                "🌐 Single-network mode: advertising ICE candidate(s): {:?}",
+                advertised_ips
             );
         }
 
-        let udp_mux = UDPMuxDefault::new(UDPMuxParams::new(udp_socket));
+        let udp_addr = udp_socket.local_addr()?;
+        let mux_probe = Arc::new(MuxProbe::default());
+        let udp_mux = UDPMuxDefault::new(UDPMuxParams::new(MuxSocket::new(
+            udp_socket,
+            mux_probe.clone(),
+        )?));
         setting_engine.set_udp_network(UDPNetwork::Muxed(udp_mux.clone()));
 
         // Disable mDNS to prevent .local candidates when we have specific IPs to advertise
@@ -209,30 +426,19 @@ impl WebRtcInfra {
             setting_engine
                 .set_ice_multicast_dns_mode(webrtc::ice::mdns::MulticastDnsMode::Disabled);
             info!("🌐 Disabled mDNS candidates (using specific IPs only)");
-        }
-
-        // Set NAT 1:1 mapping to advertise our configured IP(s)
-        // This tells WebRTC to advertise these IPs as host candidates
-        // In dual-IP mode, both IPs are advertised and the client will try both
-        // Skip NAT mapping if only localhost IPs are configured (for local testing)
-        let has_non_localhost_ips = advertised_ips
-            .iter()
-            .any(|ip| ip != "127.0.0.1" && ip != "::1");
 
-        if !advertised_ips.is_empty() && has_non_localhost_ips {
+            // Set NAT 1:1 mapping to advertise our configured IP(s)
+            // This tells WebRTC to advertise these IPs as host candidates
+            // In dual-IP mode, both IPs are advertised and the client will try both.
+            // Local IPv6 addresses of a family without a mapping are advertised as-is.
             info!(
                 "🌐 Setting NAT 1:1 mapping to advertise {} IP(s) as ICE candidates",
                 advertised_ips.len()
             );
             setting_engine.set_nat_1to1_ips(
-                advertised_ips,
+                advertised_ips.iter().map(IpAddr::to_string).collect(),
                 webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType::Host,
             );
-        } else if !advertised_ips.is_empty() {
-            info!(
-                "🌐 Skipping NAT 1:1 mapping for localhost-only configuration ({} IPs)",
-                advertised_ips.len()
-            );
         }
 
         // Note: No ICE candidate pool configuration needed
@@ -244,16 +450,198 @@ impl WebRtcInfra {
             .with_setting_engine(setting_engine)
             .build();
 
+        // STUN/TURN servers for clients behind UDP-blocking networks
+        let ice_servers = ice_servers::from_config(ice);
+
         Ok(Arc::new(Self {
             api,
+            bandwidth_config: std::sync::RwLock::new(bandwidth_config),
+            ice_servers: std::sync::RwLock::new(ice_servers),
             _udp_mux: udp_mux,
+            udp_addr,
+            mux_probe,
         }))
     }
+
+    /// STUN/TURN servers clients should use (sent in the signaling handshake)
+    pub fn ice_servers(&self) -> Vec<IceServer> {
+        self.ice_servers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn bandwidth_config(&self) -> BandwidthConfig {
+        *self
+            .bandwidth_config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply reloaded adaptive video and ICE server settings
+    ///
+    /// Running sessions keep their settings; new sessions (and resumed ones
+    /// fetching ICE servers) get the new ones.
+    pub fn reconfigure(&self, config: &config::WebRtc, ice: &config::Ice) {
+        *self
+            .bandwidth_config
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = config.bandwidth();
+        *self.ice_servers.write().unwrap_or_else(|e| e.into_inner()) =
+            ice_servers::from_config(ice);
+    }
+
+    /// Address the shared WebRTC UDP socket is bound to
+    pub fn udp_addr(&self) -> SocketAddr {
+        self.udp_addr
+    }
+
+    /// Check that the UDP mux still reads its socket: send it a datagram from this
+    /// host and wait until the mux's read loop has received it
+    ///
+    /// The mux stops reading for good after a socket error, without closing, so the
+    /// socket being bound says nothing. Returns how long the datagram took.
+    pub async fn probe_udp_mux(&self, timeout: Duration) -> Result<Duration> {
+        let started = Instant::now();
+        let token = self.mux_probe.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut seen = self.mux_probe.seen.subscribe();
+
+        let (target_ip, local_ip): (IpAddr, IpAddr) = match self.udp_addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                (Ipv4Addr::LOCALHOST.into(), Ipv4Addr::UNSPECIFIED.into())
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                (Ipv6Addr::LOCALHOST.into(), Ipv6Addr::UNSPECIFIED.into())
+            }
+            IpAddr::V4(ip) => (ip.into(), Ipv4Addr::UNSPECIFIED.into()),
+            IpAddr::V6(ip) => (ip.into(), Ipv6Addr::UNSPECIFIED.into()),
+        };
+        let target = SocketAddr::new(target_ip, self.udp_addr.port());
+        let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0))
+            .await
+            .context("Failed to bind probe socket")?;
+        let mut datagram = MUX_PROBE_MAGIC.to_vec();
+        datagram.extend_from_slice(&token.to_be_bytes());
+
+        let probe = async {
+            let mut resend = tokio::time::interval(MUX_PROBE_RESEND);
+            loop {
+                tokio::select! {
+                    _ = resend.tick() => {
+                        socket
+                            .send_to(&datagram, target)
+                            .await
+                            .with_context(|| format!("Failed to send probe to {}", target))?;
+                    }
+                    read = seen.wait_for(|seen| *seen >= token) => {
+                        read.context("Probe state dropped")?;
+                        return Ok::<_, anyhow::Error>(());
+                    }
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, probe).await {
+            Ok(result) => result.map(|()| started.elapsed()),
+            Err(_) => anyhow::bail!("probe datagram not read within {} ms", timeout.as_millis()),
+        }
+    }
+
+    /// Create a peer connection on the shared UDP mux
+    pub async fn new_peer_connection(&self) -> Result<RTCPeerConnection> {
+        // The server has a known IP and only needs host candidates. Configured
+        // STUN/TURN servers are for clients: a client that can't reach our UDP port
+        // relays through TURN, and the relay talks to our host candidate.
+        let cfg = RTCConfiguration {
+            ice_servers: vec![],
+            ..RTCConfiguration::default()
+        };
+
+        Ok(self.api.new_peer_connection(cfg).await?)
+    }
+}
+
+/// Longest time to wait for ICE gathering when answering without trickle ICE
+const GATHERING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set a remote offer and return an answer containing all gathered candidates
+pub async fn answer_with_gathered_candidates(
+    pc: &RTCPeerConnection,
+    sdp: String,
+) -> Result<RTCSessionDescription> {
+    let offer = RTCSessionDescription::offer(sdp)?;
+    pc.set_remote_description(offer).await?;
+    let answer = pc.create_answer(None).await?;
+
+    // Must be obtained before setting the local description, which starts gathering
+    let mut gathering_complete = pc.gathering_complete_promise().await;
+    pc.set_local_description(answer).await?;
+    if tokio::time::timeout(GATHERING_TIMEOUT, gathering_complete.recv())
+        .await
+        .is_err()
+    {
+        warn!(
+            "ICE gathering did not complete within {:?}, answering with candidates so far",
+            GATHERING_TIMEOUT
+        );
+    }
+
+    pc.local_description()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("missing local description"))
+}
+
+/// How long a stopped PTT transmission may take to finish its request
+const PTT_STOP_GRACE: Duration = Duration::from_secs(2);
+
+/// One 20ms frame of G.711 μ-law at 8kHz
+const ULAW_FRAME_BYTES: usize = 160;
+
+/// μ-law encoding of a zero sample
+const ULAW_SILENCE: u8 = 0xFF;
+
+/// Transcoded PTT audio allowed to queue up before the oldest is dropped (the
+/// longest Opus packet and a frame)
+const PTT_MAX_PENDING_BYTES: usize = (MAX_PACKET_FRAMES as usize + 1) * ULAW_FRAME_BYTES;
+
+/// Number of PTT transmission tasks still running
+static ACTIVE_TRANSMISSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of audio transmissions to the DoorBird still in progress
+///
+/// A transmission outlives its `PttTransmitHandle` while the request finishes.
+pub fn active_transmissions() -> usize {
+    ACTIVE_TRANSMISSIONS.load(Ordering::Relaxed)
+}
+
+/// Counts a transmission task (push-to-talk or announcement) in
+/// `ACTIVE_TRANSMISSIONS` until dropped
+pub struct ActiveTransmission;
+
+impl ActiveTransmission {
+    pub fn start() -> Self {
+        ACTIVE_TRANSMISSIONS.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for ActiveTransmission {
+    fn drop(&mut self) {
+        ACTIVE_TRANSMISSIONS.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 /// PTT transmission handle - when dropped, stops transmission
-struct PttTransmitHandle {
+pub struct PttTransmitHandle {
     stop_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    /// Cancelled once the transmission task has ended, for whatever reason
+    done: CancellationToken,
+}
+
+impl PttTransmitHandle {
+    /// Token cancelled when the transmission ends (stopped, DoorBird error or busy)
+    pub fn done(&self) -> CancellationToken {
+        self.done.clone()
+    }
 }
 
 impl Drop for PttTransmitHandle {
@@ -266,35 +654,47 @@ impl Drop for PttTransmitHandle {
 
 pub struct WebRtcSession {
     pub pc: Arc<RTCPeerConnection>,
-    pub ws_out: UnboundedSender<Message>,
+    /// Signaling messages to the client (`None` for WHEP, which has no side channel)
+    ws_out: Option<UnboundedSender<Message>>,
     #[allow(dead_code)]
     ptt_state: Arc<crate::PttState>,
     doorbird_client: doorbird::Client,
     session_id: Uuid,
     /// Channel for sending Opus audio from client to PTT transcoder
-    ptt_audio_tx: Arc<Mutex<Option<tokio::sync::mpsc::UnboundedSender<Bytes>>>>,
+    ptt_audio_tx: Arc<Mutex<Option<tokio::sync::mpsc::UnboundedSender<RtpAudio>>>>,
     /// Handle for current PTT transmission (if active)
     ptt_handle: Arc<Mutex<Option<PttTransmitHandle>>>,
+    /// Available video tiers
+    video_tiers: Arc<VideoTiers>,
+    /// Video tier requested by the client (caps the adaptive choice)
+    preferred_tier_tx: watch::Sender<VideoTier>,
+    /// Video tier actually streamed (`None` = audio only), chosen by the bandwidth controller
+    active_tier_rx: watch::Receiver<Option<VideoTier>>,
+    /// Media bytes sent and received, for status reporting
+    traffic: Arc<Traffic>,
+    /// Ends the session and all of its tasks
+    cancel: CancellationToken,
+}
+
+impl Drop for WebRtcSession {
+    fn drop(&mut self) {
+        // Never leave streaming tasks (and their fanout subscriptions) behind
+        self.cancel.cancel();
+    }
 }
 
 impl WebRtcSession {
     pub async fn new(
         infra: Arc<WebRtcInfra>,
-        ws_out: UnboundedSender<Message>,
+        ws_out: Option<UnboundedSender<Message>>,
         audio_fanout: Arc<AudioFanout>,
-        video_fanout: Arc<VideoFanout>,
+        video_tiers: Arc<VideoTiers>,
         ptt_state: Arc<crate::PttState>,
         doorbird_client: doorbird::Client,
         session_id: Uuid,
     ) -> Result<Self> {
-        // No STUN/TURN servers needed for client-server architecture
-        // where server has known IP and client connects directly
-        let cfg = RTCConfiguration {
-            ice_servers: vec![], // Explicitly disable STUN/TURN servers
-            ..RTCConfiguration::default()
-        };
-
-        let pc = Arc::new(infra.api.new_peer_connection(cfg).await?);
+        let pc = Arc::new(infra.new_peer_connection().await?);
+        let cancel = CancellationToken::new();
 
         // ICE candidates from server -> client
         let ws_out_clone = ws_out.clone();
@@ -308,13 +708,14 @@ impl WebRtcSession {
                                 "server ICE candidate: {} (mid: {:?}, mline: {:?})",
                                 json.candidate, json.sdp_mid, json.sdp_mline_index
                             );
-                            let msg = serde_json::json!({
-                                "type": "candidate",
-                                "candidate": json.candidate,
-                                "sdpMid": json.sdp_mid,
-                                "sdpMLineIndex": json.sdp_mline_index,
-                            });
-                            let _ = ws_out.send(Message::Text(msg.to_string().into()));
+                            let msg = SignalMessage::Candidate {
+                                candidate: json.candidate,
+                                sdp_mid: json.sdp_mid,
+                                sdp_mline_index: json.sdp_mline_index,
+                            };
+                            if let Some(ws_out) = &ws_out {
+                                let _ = ws_out.send(msg.to_ws());
+                            }
                         }
                         Err(e) => error!("candidate to_json failed: {:#}", e),
                     }
@@ -324,9 +725,12 @@ impl WebRtcSession {
             })
         }));
 
-        // Log connection state changes
+        // Log and publish connection state changes; a failed or closed connection
+        // ends the session
+        let state_cancel = cancel.clone();
         pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
             info!("peer connection state changed: {:?}", s);
+            cancel_on_disconnect(s, &state_cancel);
             Box::pin(async {})
         }));
 
@@ -359,12 +763,23 @@ impl WebRtcSession {
             .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
             .await?;
 
-        // Read RTCP in background as required to avoid congestion
+        // Read RTCP in background as required to avoid congestion, forwarding
+        // bandwidth feedback to the session's controller
+        let (rtcp_feedback_tx, rtcp_feedback_rx) = mpsc::unbounded_channel::<RtcpFeedback>();
+        let audio_feedback_tx = rtcp_feedback_tx.clone();
+        let rtcp_cancel = cancel.clone();
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 1500];
             loop {
-                match sender.read(&mut buf).await {
-                    Ok(_) => {}
+                let result = tokio::select! {
+                    result = sender.read_rtcp() => result,
+                    _ = rtcp_cancel.cancelled() => break,
+                };
+                match result {
+                    Ok((packets, _)) => {
+                        for feedback in feedback_from_rtcp(&packets) {
+                            let _ = audio_feedback_tx.send(feedback);
+                        }
+                    }
                     Err(err) => {
                         error!("rtcp read error: {:#}", err);
                         break;
@@ -374,67 +789,30 @@ impl WebRtcSession {
         });
 
         // Set up handler to read incoming audio from client for PTT
-        let ptt_audio_tx: Arc<Mutex<Option<tokio::sync::mpsc::UnboundedSender<Bytes>>>> =
+        let ptt_audio_tx: Arc<Mutex<Option<tokio::sync::mpsc::UnboundedSender<RtpAudio>>>> =
             Arc::new(Mutex::new(None));
         let ptt_audio_tx_clone = ptt_audio_tx.clone();
+        let track_cancel = cancel.clone();
+        let traffic = Traffic::new(Instant::now());
+        let track_traffic = traffic.clone();
 
         // Set up on_track handler to receive audio from client when they start transmitting
         pc.on_track(Box::new(move |track, _receiver, _transceiver| {
             let ptt_audio_tx = ptt_audio_tx_clone.clone();
+            let cancel = track_cancel.clone();
+            let traffic = track_traffic.clone();
             Box::pin(async move {
                 info!(
                     "Received remote audio track from client: kind={}",
                     track.kind()
                 );
 
-                tokio::spawn(async move {
-                    info!("Starting to read incoming audio from client");
-                    let mut packet_count = 0;
-                    loop {
-                        match track.read_rtp().await {
-                            Ok((rtp_packet, _)) => {
-                                packet_count += 1;
-                                if packet_count % 50 == 0 {
-                                    info!("Received {} RTP packets from client", packet_count);
-                                }
-
-                                // Extract Opus payload
-                                let opus_data = Bytes::copy_from_slice(&rtp_packet.payload);
-
-                                // Send to PTT transcoder if active
-                                let tx_opt = ptt_audio_tx.lock().await;
-                                if let Some(tx) = tx_opt.as_ref() {
-                                    if tx.send(opus_data).is_err() {
-                                        // Channel closed, stop reading
-                                        info!(
-                                            "PTT audio channel closed after {} packets",
-                                            packet_count
-                                        );
-                                        break;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                if packet_count > 0 {
-                                    info!(
-                                        "Stopped reading audio after {} packets: {:#}",
-                                        packet_count, e
-                                    );
-                                }
-                                break;
-                            }
-                        }
-                    }
-                    info!(
-                        "Stopped reading incoming audio track (received {} packets total)",
-                        packet_count
-                    );
-                });
+                start_remote_audio_reader_task(track, ptt_audio_tx, traffic, cancel);
             })
         }));
 
         // Start audio streaming from DoorBird fanout
-        start_audio_stream_task(track.clone(), audio_fanout);
+        start_audio_stream_task(track.clone(), audio_fanout, traffic.clone(), cancel.clone());
 
         // Prepare video track (H.264) for sending to client
         let video_track = Arc::new(TrackLocalStaticSample::new(
@@ -455,12 +833,21 @@ impl WebRtcSession {
             .add_track(Arc::clone(&video_track) as Arc<dyn TrackLocal + Send + Sync>)
             .await?;
 
-        // Read RTCP for video track in background
+        // Read RTCP for video track in background (REMB and loss reports are mostly
+        // about video, since it dominates the bitrate)
+        let rtcp_cancel = cancel.clone();
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 1500];
             loop {
-                match video_sender.read(&mut buf).await {
-                    Ok(_) => {}
+                let result = tokio::select! {
+                    result = video_sender.read_rtcp() => result,
+                    _ = rtcp_cancel.cancelled() => break,
+                };
+                match result {
+                    Ok((packets, _)) => {
+                        for feedback in feedback_from_rtcp(&packets) {
+                            let _ = rtcp_feedback_tx.send(feedback);
+                        }
+                    }
                     Err(err) => {
                         error!("video rtcp read error: {:#}", err);
                         break;
@@ -469,8 +856,32 @@ impl WebRtcSession {
             }
         });
 
-        // Start video streaming from DoorBird fanout
-        start_video_stream_task(video_track.clone(), video_fanout);
+        // Start video streaming from DoorBird fanout, defaulting to the best tier.
+        // The bandwidth controller moves the session between tiers (or to audio only)
+        // within the limit set by the client's preferred tier.
+        let (preferred_tier_tx, preferred_tier_rx) = watch::channel(VideoTier::High);
+        let (active_tier_tx, active_tier_rx) = watch::channel(Some(VideoTier::High));
+        start_video_stream_task(
+            video_track.clone(),
+            video_tiers.clone(),
+            active_tier_rx.clone(),
+            traffic.clone(),
+            cancel.clone(),
+        );
+        start_bandwidth_controller_task(
+            BandwidthController::new(
+                infra.bandwidth_config(),
+                video_tiers.available().contains(&VideoTier::Low),
+                Instant::now(),
+            ),
+            rtcp_feedback_rx,
+            preferred_tier_rx,
+            active_tier_tx,
+            video_tiers.clone(),
+            ws_out.clone(),
+            session_id,
+            cancel.clone(),
+        );
 
         Ok(Self {
             pc,
@@ -480,9 +891,73 @@ impl WebRtcSession {
             session_id,
             ptt_audio_tx,
             ptt_handle: Arc::new(Mutex::new(None)),
+            video_tiers,
+            preferred_tier_tx,
+            active_tier_rx,
+            traffic,
+            cancel,
         })
     }
 
+    /// Send a signaling message to the client; WHEP sessions have no signaling
+    /// channel, so nothing is sent for them
+    pub fn send_signal(&self, msg: &SignalMessage) {
+        if let Some(ws_out) = &self.ws_out {
+            let _ = ws_out.send(msg.to_ws());
+        }
+    }
+
+    /// Token cancelled when the session ends (peer connection failed or closed,
+    /// or `close` called)
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Tear the session down: stop all session tasks and PTT, and close the peer
+    /// connection
+    ///
+    /// Closing the peer connection only removes its ICE connection from the
+    /// shared UDP mux; the mux stays open for other sessions.
+    pub async fn close(&self) {
+        self.cancel.cancel();
+        self.stop_ptt().await;
+        if let Err(e) = self.pc.close().await {
+            warn!(
+                "error closing peer connection for session {}: {:#}",
+                self.session_id, e
+            );
+        }
+    }
+
+    /// Media bytes sent and received by this session
+    pub fn traffic(&self) -> Arc<Traffic> {
+        self.traffic.clone()
+    }
+
+    /// Set the video tier this session prefers
+    ///
+    /// The same video track is kept, so no SDP renegotiation is needed; the stream
+    /// task resubscribes to the other fanout and resumes at its next keyframe.
+    /// The bandwidth controller may still stream a lower tier if the link can't
+    /// sustain the requested one; it replies to the client with a `video_tier`
+    /// message. Returns the tier actually requested (falls back if unavailable).
+    pub fn set_video_tier(&self, tier: VideoTier) -> VideoTier {
+        let tier = self.video_tiers.resolve(tier);
+        // Always notify so the controller replies with the current state
+        self.preferred_tier_tx.send_replace(tier);
+        tier
+    }
+
+    /// Signaling message describing the preferred, active and available video tiers
+    pub fn video_tier_message(&self) -> SignalMessage {
+        video_tier_message(
+            &self.video_tiers,
+            *self.preferred_tier_tx.borrow(),
+            *self.active_tier_rx.borrow(),
+            None,
+        )
+    }
+
     pub async fn set_remote_offer_and_create_answer(
         &self,
         sdp: String,
@@ -499,6 +974,17 @@ impl WebRtcSession {
         Ok(local)
     }
 
+    /// Answer an offer without trickle ICE
+    ///
+    /// Waits for ICE gathering to finish so the returned SDP contains all server
+    /// candidates. Used by WHEP/WHIP, where the answer is a single HTTP response.
+    pub async fn set_remote_offer_and_create_complete_answer(
+        &self,
+        sdp: String,
+    ) -> Result<RTCSessionDescription> {
+        answer_with_gathered_candidates(&self.pc, sdp).await
+    }
+
     pub async fn add_ice_candidate(
         &self,
         candidate: String,
@@ -520,7 +1006,7 @@ impl WebRtcSession {
         info!("Starting PTT for session {}", self.session_id);
 
         // Create channel for audio data
-        let (audio_tx, mut audio_rx) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
+        let (audio_tx, audio_rx) = tokio::sync::mpsc::unbounded_channel::<RtpAudio>();
 
         // Set the channel so on_track can send to it
         {
@@ -528,123 +1014,289 @@ impl WebRtcSession {
             *tx_lock = Some(audio_tx);
         }
 
-        // Create stop signal
-        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel();
+        // Start transcoding and transmitting, and store the handle
+        let handle = spawn_ptt_transmitter(self.doorbird_client.clone(), self.session_id, audio_rx);
+        {
+            let mut handle_lock = self.ptt_handle.lock().await;
+            *handle_lock = Some(handle);
+        }
 
-        // Store handle
+        Ok(())
+    }
+
+    /// Stop push-to-talk audio transmission
+    pub async fn stop_ptt(&self) {
+        info!("Stopping PTT for session {}", self.session_id);
+
+        // Clear the audio channel
+        {
+            let mut tx_lock = self.ptt_audio_tx.lock().await;
+            *tx_lock = None;
+        }
+
+        // Drop the handle (triggers stop signal)
         {
             let mut handle_lock = self.ptt_handle.lock().await;
-            *handle_lock = Some(PttTransmitHandle {
-                stop_tx: Some(stop_tx),
-            });
+            *handle_lock = None;
         }
+    }
+}
 
-        // Spawn task to transcode and transmit audio
-        let doorbird_client = self.doorbird_client.clone();
-        let session_id = self.session_id;
+/// Read Opus RTP packets from a remote (client) audio track
+///
+/// Packets are forwarded to the PTT transmitter while `audio_tx` holds a channel;
+/// while it is empty (not transmitting) packets are discarded.
+pub fn start_remote_audio_reader_task(
+    track: Arc<TrackRemote>,
+    audio_tx: Arc<Mutex<Option<UnboundedSender<RtpAudio>>>>,
+    traffic: Arc<Traffic>,
+    cancel: CancellationToken,
+) {
+    tokio::spawn(async move {
+        info!("Starting to read incoming audio from client");
+        let mut packet_count = 0;
+        loop {
+            let result = tokio::select! {
+                result = track.read_rtp() => result,
+                _ = cancel.cancelled() => break,
+            };
+            match result {
+                Ok((rtp_packet, _)) => {
+                    packet_count += 1;
+                    traffic.add_received(rtp_packet.payload.len());
+                    METRICS.track_packet(Track::Ptt, rtp_packet.payload.len());
+                    if packet_count % 50 == 0 {
+                        info!("Received {} RTP packets from client", packet_count);
+                    }
 
-        tokio::spawn(async move {
-            info!("PTT transmission task started for session {}", session_id);
+                    // Keep the sequence number and timestamp for the jitter buffer
+                    let packet = RtpAudio {
+                        sequence_number: rtp_packet.header.sequence_number,
+                        timestamp: rtp_packet.header.timestamp,
+                        payload: Bytes::copy_from_slice(&rtp_packet.payload),
+                    };
 
-            // Create reverse transcoder
-            let mut transcoder = match ReverseAudioTranscoder::new() {
-                Ok(t) => t,
+                    // Send to PTT transmitter if active
+                    let tx_opt = audio_tx.lock().await;
+                    if let Some(tx) = tx_opt.as_ref() {
+                        if tx.send(packet).is_err() {
+                            // Channel closed, stop reading
+                            info!("PTT audio channel closed after {} packets", packet_count);
+                            break;
+                        }
+                    }
+                }
                 Err(e) => {
-                    error!("Failed to create reverse transcoder: {:#}", e);
-                    return;
+                    if packet_count > 0 {
+                        info!(
+                            "Stopped reading audio after {} packets: {:#}",
+                            packet_count, e
+                        );
+                    }
+                    break;
                 }
-            };
+            }
+        }
+        info!(
+            "Stopped reading incoming audio track (received {} packets total)",
+            packet_count
+        );
+    });
+}
+
+/// Spawn the PTT pipeline: Opus packets from `audio_rx` go through a jitter buffer,
+/// are transcoded to G.711 μ-law and streamed to the DoorBird until the returned
+/// handle is dropped or the channel closes
+///
+/// The stream is paced at one 20ms frame (160 bytes) per tick, i.e. exactly 8000
+/// bytes/sec: lost packets are concealed and silence fills in while buffering.
+/// When the transmission is stopped, the audio still buffered is played out first.
+///
+/// The caller is responsible for holding the `PttState` lock.
+pub fn spawn_ptt_transmitter(
+    doorbird_client: doorbird::Client,
+    session_id: Uuid,
+    mut audio_rx: mpsc::UnboundedReceiver<RtpAudio>,
+) -> PttTransmitHandle {
+    // Create stop signal
+    let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel();
+    let finish = CancellationToken::new();
+    let done = CancellationToken::new();
+    let task_done = done.clone();
+
+    tokio::spawn(async move {
+        let _done = task_done.drop_guard();
+        let _active = ActiveTransmission::start();
+        info!("PTT transmission task started for session {}", session_id);
+
+        // Create reverse transcoder
+        let mut transcoder = match ReverseAudioTranscoder::new() {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Failed to create reverse transcoder: {:#}", e);
+                return;
+            }
+        };
 
-            // Create stream of G.711 μ-law data
-            let (ulaw_tx, ulaw_rx) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
-
-            // Spawn transcoding task
-            let transcode_task = tokio::spawn(async move {
-                let mut opus_count = 0;
-                let mut ulaw_count = 0;
-                while let Some(opus_data) = audio_rx.recv().await {
-                    opus_count += 1;
-                    // Transcode Opus to G.711 μ-law
-                    match transcoder.process_chunk(&opus_data) {
-                        Ok(ulaw_frames) => {
-                            for frame in ulaw_frames {
-                                ulaw_count += 1;
-                                if ulaw_tx.send(Bytes::from(frame)).is_err() {
-                                    // Channel closed
-                                    info!(
-                                        "µ-law channel closed after {} opus packets, {} µ-law frames",
-                                        opus_count, ulaw_count
-                                    );
-                                    break;
-                                }
+        // Create stream of G.711 μ-law data
+        let (ulaw_tx, ulaw_rx) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
+
+        // Spawn transcoding task: packets are buffered as they arrive and one frame
+        // is played out per tick
+        let transcode_finish = finish.clone();
+        let transcode_task = tokio::spawn(async move {
+            let mut jitter_buffer = JitterBuffer::new();
+            // Transcoded audio waiting for its tick
+            let mut pending: VecDeque<u8> = VecDeque::new();
+            let mut ticker = tokio::time::interval(FRAME_DURATION);
+            let mut recorded_stats = JitterStats::default();
+            let mut opus_count = 0;
+            let mut ulaw_count = 0;
+            let mut closing = false;
+            let mut input_open = true;
+            loop {
+                tokio::select! {
+                    packet = audio_rx.recv(), if input_open => {
+                        match packet {
+                            Some(packet) => {
+                                opus_count += 1;
+                                jitter_buffer.push(packet, Instant::now());
+                            }
+                            None => {
+                                // Play out what is buffered, then end
+                                input_open = false;
+                                jitter_buffer.finish();
                             }
-                        }
-                        Err(e) => {
-                            warn!("Transcoding error: {:#}", e);
                         }
                     }
-                }
+                    _ = transcode_finish.cancelled(), if !closing => {
+                        // Packets already queued are still received
+                        closing = true;
+                        audio_rx.close();
+                    }
+                    _ = ticker.tick() => {
+                        // Transcode Opus to G.711 μ-law
+                        let transcoded = match jitter_buffer.pop() {
+                            Playout::Frame(opus_data) => transcoder.process_chunk(&opus_data),
+                            Playout::Lost(next_packet) => {
+                                transcoder.conceal(next_packet.as_deref())
+                            }
+                            Playout::Waiting => Ok(Vec::new()),
+                        };
+                        match transcoded {
+                            Ok(ulaw_frames) => pending.extend(ulaw_frames.into_iter().flatten()),
+                            Err(e) => warn!("Transcoding error: {:#}", e),
+                        }
+                        // Counted as they happen, the task is aborted if the request fails
+                        record_jitter_stats(jitter_buffer.stats(), &mut recorded_stats);
+                        if pending.len() > PTT_MAX_PENDING_BYTES {
+                            pending.drain(..pending.len() - PTT_MAX_PENDING_BYTES);
+                        }
 
-                // Flush any remaining data
-                if let Ok(ulaw_frames) = transcoder.flush() {
-                    for frame in ulaw_frames {
+                        // Exactly one frame per tick, padded with silence
+                        let take = pending.len().min(ULAW_FRAME_BYTES);
+                        let mut frame: Vec<u8> = pending.drain(..take).collect();
+                        frame.resize(ULAW_FRAME_BYTES, ULAW_SILENCE);
                         ulaw_count += 1;
-                        let _ = ulaw_tx.send(Bytes::from(frame));
+                        if ulaw_tx.send(Bytes::from(frame)).is_err() {
+                            // Channel closed
+                            info!(
+                                "µ-law channel closed after {} opus packets, {} µ-law frames",
+                                opus_count, ulaw_count
+                            );
+                            break;
+                        }
+                        if !input_open && jitter_buffer.is_empty() && pending.is_empty() {
+                            break;
+                        }
                     }
                 }
+            }
 
-                info!(
-                    "Transcoding task finished: {} opus packets -> {} µ-law frames",
-                    opus_count, ulaw_count
-                );
-            });
-
-            // Create stream for DoorBird
-            let ulaw_stream = tokio_stream::wrappers::UnboundedReceiverStream::new(ulaw_rx);
-            let result_stream = ulaw_stream.map(Ok::<Bytes, anyhow::Error>);
+            // Flush any remaining data
+            if let Ok(ulaw_frames) = transcoder.flush() {
+                pending.extend(ulaw_frames.into_iter().flatten());
+            }
+            if !pending.is_empty() {
+                ulaw_count += 1;
+                let _ = ulaw_tx.send(Bytes::from(Vec::from(pending)));
+            }
 
-            // Transmit to DoorBird (this blocks until stream ends or error)
-            let transmit_result = tokio::select! {
-                result = doorbird_client.audio_transmit(result_stream) => {
-                    result
-                }
-                _ = &mut stop_rx => {
-                    info!("PTT transmission stopped by user");
-                    Ok(())
-                }
-            };
+            let stats = jitter_buffer.stats();
+            record_jitter_stats(stats, &mut recorded_stats);
+            info!(
+                "Transcoding task finished: {} opus packets -> {} µ-law frames ({} lost, {} late, {} dropped)",
+                opus_count, ulaw_count, stats.lost, stats.late, stats.dropped
+            );
+        });
 
-            // Stop transcoding task
-            transcode_task.abort();
+        // Create stream for DoorBird
+        let ulaw_stream = tokio_stream::wrappers::UnboundedReceiverStream::new(ulaw_rx);
+        let result_stream = ulaw_stream.map(Ok::<Bytes, anyhow::Error>);
 
-            match transmit_result {
-                Ok(_) => info!("PTT transmission completed for session {}", session_id),
-                Err(e) => error!("PTT transmission error for session {}: {:#}", session_id, e),
+        // Transmit to DoorBird (this blocks until stream ends or error)
+        let transmit = doorbird_client.audio_transmit(result_stream);
+        tokio::pin!(transmit);
+        let transmit_result = tokio::select! {
+            result = &mut transmit => {
+                result
             }
-        });
-
-        Ok(())
-    }
+            _ = &mut stop_rx => {
+                info!("PTT transmission stopped by user");
+                // The transcoder plays out the buffered audio and then drops its
+                // sender, which ends the request body, so the DoorBird sees a complete
+                // transmission rather than a reset
+                finish.cancel();
+                match tokio::time::timeout(PTT_STOP_GRACE, &mut transmit).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        warn!(
+                            "PTT transmission for session {} did not end within {:?}, dropping it",
+                            session_id, PTT_STOP_GRACE
+                        );
+                        Ok(())
+                    }
+                }
+            }
+        };
 
-    /// Stop push-to-talk audio transmission
-    pub async fn stop_ptt(&self) {
-        info!("Stopping PTT for session {}", self.session_id);
+        // Stop transcoding task
+        transcode_task.abort();
 
-        // Clear the audio channel
-        {
-            let mut tx_lock = self.ptt_audio_tx.lock().await;
-            *tx_lock = None;
+        match transmit_result {
+            Ok(_) => info!("PTT transmission completed for session {}", session_id),
+            Err(e) => error!("PTT transmission error for session {}: {:#}", session_id, e),
         }
+    });
 
-        // Drop the handle (triggers stop signal)
-        {
-            let mut handle_lock = self.ptt_handle.lock().await;
-            *handle_lock = None;
+    PttTransmitHandle {
+        stop_tx: Some(stop_tx),
+        done,
+    }
+}
+
+/// Add the jitter buffer counters that changed since `recorded` to the metrics
+fn record_jitter_stats(stats: JitterStats, recorded: &mut JitterStats) {
+    for (reason, now, before) in [
+        ("lost", stats.lost, recorded.lost),
+        ("late", stats.late, recorded.late),
+        ("dropped", stats.dropped, recorded.dropped),
+    ] {
+        if now > before {
+            METRICS
+                .ptt_jitter_frames
+                .add(&[("reason", reason)], now - before);
         }
     }
+    *recorded = stats;
 }
 
-fn start_audio_stream_task(track: Arc<TrackLocalStaticSample>, audio_fanout: Arc<AudioFanout>) {
+fn start_audio_stream_task(
+    track: Arc<TrackLocalStaticSample>,
+    audio_fanout: Arc<AudioFanout>,
+    traffic: Arc<Traffic>,
+    cancel: CancellationToken,
+) {
     tokio::spawn(async move {
         info!("WebRTC audio track subscribed to DoorBird fanout");
 
@@ -652,7 +1304,11 @@ fn start_audio_stream_task(track: Arc<TrackLocalStaticSample>, audio_fanout: Arc
         let mut audio_rx = audio_fanout.subscribe().await;
 
         loop {
-            match audio_rx.recv().await {
+            let result = tokio::select! {
+                result = audio_rx.recv() => result,
+                _ = cancel.cancelled() => break,
+            };
+            match result {
                 Ok(opus_sample) => {
                     // Create WebRTC sample from Opus data
                     let sample = Sample {
@@ -666,12 +1322,17 @@ fn start_audio_stream_task(track: Arc<TrackLocalStaticSample>, audio_fanout: Arc
                         error!("track write_sample failed: {:#}", e);
                         break;
                     }
+                    traffic.add_sent(sample.data.len());
+                    METRICS.track_packet(Track::Audio, sample.data.len());
                 }
-                Err(e) => {
-                    error!("audio fanout receive error: {:#}", e);
-                    // On broadcast error, try to resubscribe
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    audio_rx = audio_fanout.subscribe().await;
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    // The receiver skips ahead to the oldest buffered sample
+                    warn!("audio fanout lagged, skipped {} samples", skipped);
+                    METRICS.broadcast_lag(Track::Audio, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    error!("audio fanout closed");
+                    break;
                 }
             }
         }
@@ -682,42 +1343,198 @@ fn start_audio_stream_task(track: Arc<TrackLocalStaticSample>, audio_fanout: Arc
     });
 }
 
-fn start_video_stream_task(track: Arc<TrackLocalStaticSample>, video_fanout: Arc<VideoFanout>) {
-    tokio::spawn(async move {
-        info!("WebRTC video track subscribed to DoorBird fanout");
+/// Build the `video_tier` signaling message
+fn video_tier_message(
+    video_tiers: &VideoTiers,
+    preferred: VideoTier,
+    active: Option<VideoTier>,
+    estimate_kbps: Option<u32>,
+) -> SignalMessage {
+    SignalMessage::VideoTier {
+        tier: preferred,
+        active,
+        available: video_tiers.available(),
+        estimate_kbps,
+    }
+}
 
-        // Subscribe to the video fanout
-        let mut video_rx = video_fanout.subscribe().await;
+/// Run the per-session bandwidth controller
+///
+/// Consumes RTCP feedback from the sender read loops and the client's preferred
+/// tier, and publishes the tier to stream (or `None` for audio only).
+fn start_bandwidth_controller_task(
+    mut controller: BandwidthController,
+    mut feedback_rx: mpsc::UnboundedReceiver<RtcpFeedback>,
+    mut preferred_rx: watch::Receiver<VideoTier>,
+    active_tx: watch::Sender<Option<VideoTier>>,
+    video_tiers: Arc<VideoTiers>,
+    ws_out: Option<UnboundedSender<Message>>,
+    session_id: Uuid,
+    cancel: CancellationToken,
+) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
 
         loop {
-            match video_rx.recv().await {
-                Ok(h264_packet) => {
-                    // Create WebRTC sample from H.264 packet
-                    // Use a fixed duration for low latency - DoorBird typically streams at 10-12 fps
-                    // Using 83ms (~12fps) as duration, actual timing handled by WebRTC
-                    let sample = Sample {
-                        data: h264_packet.data,
-                        duration: std::time::Duration::from_millis(83),
-                        ..Default::default()
-                    };
+            let changed = tokio::select! {
+                _ = cancel.cancelled() => break,
+                feedback = feedback_rx.recv() => match feedback {
+                    Some(feedback) => {
+                        controller.on_feedback(feedback, Instant::now());
+                        None
+                    }
+                    // RTCP readers stopped: peer connection is gone
+                    None => break,
+                },
+                changed = preferred_rx.changed() => {
+                    if changed.is_err() {
+                        // Session dropped
+                        break;
+                    }
+                    let preferred = *preferred_rx.borrow_and_update();
+                    // Always report back, even if the level doesn't change
+                    controller.set_preferred(preferred, Instant::now());
+                    Some(controller.level())
+                }
+                _ = tick.tick() => controller.evaluate(Instant::now()),
+            };
 
-                    // Write to WebRTC track immediately
-                    if let Err(e) = track.write_sample(&sample).await {
-                        error!("video track write_sample failed: {:#}", e);
+            if let Some(level) = changed {
+                let active = level.video_tier().map(|t| video_tiers.resolve(t));
+                if *active_tx.borrow() != active {
+                    info!(
+                        "Session {} video level -> {:?} (estimate: {:?} kbps, loss: {:.1}%)",
+                        session_id,
+                        level,
+                        controller.estimate_kbps(),
+                        controller.loss() * 100.0
+                    );
+                    let _ = active_tx.send(active);
+                }
+                if let Some(ws_out) = &ws_out {
+                    let msg = video_tier_message(
+                        &video_tiers,
+                        *preferred_rx.borrow(),
+                        active,
+                        controller.estimate_kbps(),
+                    );
+                    let _ = ws_out.send(msg.to_ws());
+                }
+            }
+        }
+    });
+}
+
+/// Receive from the current video subscription, or wait forever while audio only
+async fn recv_video(
+    subscription: &mut Option<(Arc<VideoFanout>, broadcast::Receiver<H264Packet>)>,
+) -> Result<H264Packet, broadcast::error::RecvError> {
+    match subscription {
+        Some((_, rx)) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn start_video_stream_task(
+    track: Arc<TrackLocalStaticSample>,
+    video_tiers: Arc<VideoTiers>,
+    mut tier_rx: watch::Receiver<Option<VideoTier>>,
+    traffic: Arc<Traffic>,
+    cancel: CancellationToken,
+) {
+    tokio::spawn(async move {
+        // Subscribe to the video fanout for the initial tier
+        let mut tier = *tier_rx.borrow_and_update();
+        let mut subscription = None;
+        if let Some(t) = tier {
+            let fanout = video_tiers.get(t);
+            let rx = fanout.subscribe().await;
+            subscription = Some((fanout, rx));
+            info!(
+                "WebRTC video track subscribed to DoorBird fanout ({} tier)",
+                t.as_str()
+            );
+        }
+
+        // After a tier switch, drop packets until the new stream's next keyframe so the
+        // decoder never sees P-frames referencing the other resolution
+        let mut awaiting_keyframe = false;
+
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                changed = tier_rx.changed() => {
+                    if changed.is_err() {
+                        // Session dropped
                         break;
                     }
+                    let new_tier = *tier_rx.borrow_and_update();
+                    if new_tier == tier {
+                        continue;
+                    }
+
+                    // Leave the old fanout (lets it disconnect if nobody else watches)
+                    if let Some((fanout, _)) = subscription.take() {
+                        fanout.unsubscribe().await;
+                    }
+                    if let Some(t) = new_tier {
+                        let fanout = video_tiers.get(t);
+                        let rx = fanout.subscribe().await;
+                        subscription = Some((fanout, rx));
+                        awaiting_keyframe = true;
+                    }
+                    info!(
+                        "WebRTC video track switched from {} to {}",
+                        tier.map_or("audio only", |t| t.as_str()),
+                        new_tier.map_or("audio only", |t| t.as_str())
+                    );
+                    tier = new_tier;
                 }
-                Err(e) => {
-                    error!("video fanout receive error: {:#}", e);
-                    // On broadcast error, try to resubscribe
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    video_rx = video_fanout.subscribe().await;
+                result = recv_video(&mut subscription) => match result {
+                    Ok(h264_packet) => {
+                        if awaiting_keyframe {
+                            if !h264_packet.is_keyframe {
+                                continue;
+                            }
+                            awaiting_keyframe = false;
+                        }
+
+                        // Create WebRTC sample from H.264 packet
+                        // Use a fixed duration for low latency - DoorBird typically streams at 10-12 fps
+                        // Using 83ms (~12fps) as duration, actual timing handled by WebRTC
+                        let sample = Sample {
+                            data: h264_packet.data,
+                            duration: std::time::Duration::from_millis(83),
+                            ..Default::default()
+                        };
+
+                        // Write to WebRTC track immediately
+                        if let Err(e) = track.write_sample(&sample).await {
+                            error!("video track write_sample failed: {:#}", e);
+                            break;
+                        }
+                        traffic.add_sent(sample.data.len());
+                        METRICS.track_packet(Track::Video, sample.data.len());
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // The receiver skips ahead; wait for a keyframe so the decoder
+                        // doesn't see P-frames with missing references
+                        warn!("video fanout lagged, skipped {} packets", skipped);
+                        METRICS.broadcast_lag(Track::Video, skipped);
+                        awaiting_keyframe = true;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        error!("video fanout closed");
+                        break;
+                    }
                 }
             }
         }
 
         // Unsubscribe when done
-        video_fanout.unsubscribe().await;
+        if let Some((fanout, _)) = subscription.take() {
+            fanout.unsubscribe().await;
+        }
         info!("WebRTC video track unsubscribed from DoorBird fanout");
     });
 }
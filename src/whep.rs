@@ -0,0 +1,322 @@
+//! WHEP (WebRTC-HTTP Egress Protocol) playback endpoint
+//!
+//! Lets standard WebRTC players (Home Assistant's WebRTC card, go2rtc-style players,
+//! OBS) pull the doorbell feed without our custom WebSocket signaling:
+//! - `POST /whep` with an SDP offer creates a session and returns the SDP answer
+//!   (`201 Created`, `Location: /whep/{id}`)
+//! - `PATCH /whep/{id}` with an `application/trickle-ice-sdpfrag` body adds client
+//!   ICE candidates
+//! - `DELETE /whep/{id}` tears the session down
+//!
+//! Each WHEP session is a regular `WebRtcSession`, so it shares the fanouts, video
+//! tiers and bandwidth adaptation with browser viewers.
+
+use crate::ice_servers;
+use crate::sessions::{ClientInfo, SessionKind};
+use crate::webrtc::WebRtcSession;
+use crate::AppState;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Active WHEP sessions by ID
+pub type WhepSessions = RwLock<HashMap<Uuid, Arc<WebRtcSession>>>;
+
+/// ICE candidate parsed from a trickle ICE SDP fragment (RFC 8840)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentCandidate {
+    /// Candidate attribute value, e.g. "candidate:1 1 udp 2122260223 192.168.1.2 54321 typ host"
+    pub candidate: String,
+    /// Media stream identification the candidate belongs to (from the preceding `a=mid:`)
+    pub sdp_mid: Option<String>,
+    /// Index of the media section the candidate belongs to
+    pub sdp_mline_index: Option<u16>,
+}
+
+/// Parse the candidates out of an `application/trickle-ice-sdpfrag` body
+///
+/// Candidates are attributed to the most recent `m=` / `a=mid:` line. Other
+/// attributes (`a=ice-ufrag`, `a=ice-pwd`, `a=end-of-candidates`) are ignored.
+pub fn parse_sdp_fragment(fragment: &str) -> Vec<FragmentCandidate> {
+    let mut candidates = Vec::new();
+    let mut mid: Option<String> = None;
+    let mut mline_index: Option<u16> = None;
+
+    for line in fragment.lines().map(str::trim) {
+        if line.starts_with("m=") {
+            mline_index = Some(mline_index.map_or(0, |i| i + 1));
+            mid = None;
+        } else if let Some(value) = line.strip_prefix("a=mid:") {
+            mid = Some(value.to_string());
+        } else if let Some(value) = line.strip_prefix("a=") {
+            if value.starts_with("candidate:") {
+                candidates.push(FragmentCandidate {
+                    candidate: value.to_string(),
+                    sdp_mid: mid.clone(),
+                    sdp_mline_index: mline_index,
+                });
+            }
+        }
+    }
+
+    candidates
+}
+
+/// CORS policy for the WHEP/WHIP and HLS endpoints, so browser-based players on the
+/// configured origins (`http.cors_origins`) can fetch HLS and read the `Location` header
+///
+/// Other sites get no CORS headers, so a page a viewer happens to visit cannot
+/// open sessions or grab the push-to-talk lock through their browser.
+pub fn cors_layer(origins: &[String]) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(
+            origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        ))
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers(Any)
+        .expose_headers([header::LOCATION, header::LINK])
+}
+
+/// Check that a request carries the expected content type
+pub fn has_content_type(headers: &HeaderMap, expected: &str) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case(expected)
+        })
+        .unwrap_or(false)
+}
+
+/// `POST /whep` - create a playback session from an SDP offer
+pub async fn create_session(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if state.shutdown.is_cancelled() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server shutting down").into_response();
+    }
+    if !has_content_type(&headers, "application/sdp") {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/sdp",
+        )
+            .into_response();
+    }
+    let offer = match String::from_utf8(body.to_vec()) {
+        Ok(sdp) => sdp,
+        Err(_) => return (StatusCode::BAD_REQUEST, "SDP offer is not UTF-8").into_response(),
+    };
+
+    let client = ClientInfo::new(addr, &headers, state.auth.user(addr, &headers));
+    let session_id = Uuid::new_v4();
+    info!("New WHEP session {}", session_id);
+
+    // WHEP has no side channel for server messages: candidates are in the answer,
+    // and the session ends (player sees the connection close) rather than being
+    // told about ICE restarts or shutdown
+    let session = match WebRtcSession::new(
+        state.webrtc_infra.clone(),
+        None,
+        state.audio_fanout.clone(),
+        state.video_tiers.clone(),
+        state.ptt_state.clone(),
+        state.doorbird_client.clone(),
+        session_id,
+    )
+    .await
+    {
+        Ok(s) => Arc::new(s),
+        Err(e) => {
+            error!("failed to create WHEP session: {:#}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to create session",
+            )
+                .into_response();
+        }
+    };
+
+    let answer = match session
+        .set_remote_offer_and_create_complete_answer(offer)
+        .await
+    {
+        Ok(answer) => answer,
+        Err(e) => {
+            warn!("WHEP session {} rejected offer: {:#}", session_id, e);
+            session.close().await;
+            return (StatusCode::BAD_REQUEST, format!("invalid SDP offer: {}", e)).into_response();
+        }
+    };
+
+    state
+        .whep_sessions
+        .write()
+        .await
+        .insert(session_id, session.clone());
+    state
+        .sessions
+        .register(
+            session_id,
+            SessionKind::Whep,
+            client.clone(),
+            session.cancellation_token(),
+            session.pc.clone(),
+            session.traffic(),
+        )
+        .await;
+    spawn_teardown(state.clone(), session_id, session.cancellation_token());
+    drop(session);
+
+    let location = format!("/whep/{}", session_id);
+    let mut response = (StatusCode::CREATED, answer.sdp).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/sdp"),
+    );
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        headers.insert(header::LOCATION, location);
+    }
+    // STUN/TURN servers the client may use (RFC 9725 §4.6)
+    for link in ice_servers::link_headers(&state.client_ice_servers(&client)) {
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.append(header::LINK, link);
+        }
+    }
+    response
+}
+
+/// `PATCH /whep/{id}` - trickle ICE candidates from the client
+pub async fn update_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(session) = state.whep_sessions.read().await.get(&session_id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !has_content_type(&headers, "application/trickle-ice-sdpfrag") {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/trickle-ice-sdpfrag",
+        )
+            .into_response();
+    }
+
+    let fragment = String::from_utf8_lossy(&body);
+    for c in parse_sdp_fragment(&fragment) {
+        if let Err(e) = session
+            .add_ice_candidate(c.candidate, c.sdp_mid, c.sdp_mline_index)
+            .await
+        {
+            warn!("WHEP session {} rejected candidate: {:#}", session_id, e);
+            return (StatusCode::BAD_REQUEST, format!("invalid candidate: {}", e)).into_response();
+        }
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// `DELETE /whep/{id}` - end a playback session
+pub async fn delete_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+) -> Response {
+    let Some(session) = state.whep_sessions.write().await.remove(&session_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    info!("WHEP session {} deleted by client", session_id);
+    session.close().await;
+    StatusCode::OK.into_response()
+}
+
+/// Tear down a WHEP session once it is cancelled (peer connection failed or
+/// closed, `DELETE`, or shutdown)
+///
+/// Players frequently disappear without sending `DELETE`.
+fn spawn_teardown(state: AppState, session_id: Uuid, cancel: CancellationToken) {
+    tokio::spawn(async move {
+        cancel.cancelled().await;
+
+        if let Some(session) = state.whep_sessions.write().await.remove(&session_id) {
+            info!("WHEP session {} ended, cleaning up", session_id);
+            session.close().await;
+        }
+        state.sessions.remove(session_id).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fragment_with_mids() {
+        let fragment = "a=ice-ufrag:EsAw\r\n\
+            a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r\n\
+            m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+            a=mid:0\r\n\
+            a=candidate:1387637174 1 udp 2122260223 192.0.2.1 61764 typ host generation 0\r\n\
+            m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+            a=mid:1\r\n\
+            a=candidate:3471623853 1 udp 2122194687 198.51.100.2 61765 typ host generation 0\r\n\
+            a=end-of-candidates\r\n";
+
+        let candidates = parse_sdp_fragment(fragment);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].sdp_mid.as_deref(), Some("0"));
+        assert_eq!(candidates[0].sdp_mline_index, Some(0));
+        assert!(candidates[0].candidate.starts_with("candidate:1387637174"));
+        assert_eq!(candidates[1].sdp_mid.as_deref(), Some("1"));
+        assert_eq!(candidates[1].sdp_mline_index, Some(1));
+    }
+
+    #[test]
+    fn test_parse_fragment_without_media_lines() {
+        let fragment = "a=candidate:1 1 udp 2122260223 192.0.2.1 61764 typ host\n";
+        let candidates = parse_sdp_fragment(fragment);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].sdp_mid, None);
+        assert_eq!(candidates[0].sdp_mline_index, None);
+    }
+
+    #[test]
+    fn test_content_type_ignores_parameters() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("Application/SDP; charset=utf-8"),
+        );
+        assert!(has_content_type(&headers, "application/sdp"));
+        assert!(!has_content_type(
+            &headers,
+            "application/trickle-ice-sdpfrag"
+        ));
+    }
+}
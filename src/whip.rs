@@ -0,0 +1,365 @@
+//! WHIP (WebRTC-HTTP Ingestion Protocol) endpoint for push-to-talk audio
+//!
+//! Lets non-browser clients (a kitchen tablet app, GStreamer `whipsink`, OBS) talk
+//! through the doorbell speaker:
+//! - `POST /whip` with an SDP offer containing an Opus audio track acquires the
+//!   push-to-talk lock and returns the SDP answer (`201 Created`,
+//!   `Location: /whip/{id}`), or `409 Conflict` if someone else is talking
+//! - `PATCH /whip/{id}` adds trickle ICE candidates
+//! - `DELETE /whip/{id}` stops talking and releases the lock
+//!
+//! Received audio goes through the same pipeline as browser PTT (`JitterBuffer` →
+//! `ReverseAudioTranscoder` → `Client::audio_transmit`), and the same `PttState`
+//! lock, so browser viewers see the line as busy while a WHIP client talks.
+//!
+//! A publisher that disappears without `DELETE` must not hold the speaker: the
+//! session also ends if ICE doesn't connect within `CONNECT_TIMEOUT`, if no audio
+//! arrives for `IDLE_TIMEOUT`, or if the DoorBird transmission ends.
+
+use crate::ice_servers;
+use crate::jitter_buffer::RtpAudio;
+use crate::sessions::{cancel_on_disconnect, ClientInfo, SessionKind, Traffic};
+use crate::webrtc::{
+    answer_with_gathered_candidates, spawn_ptt_transmitter, start_remote_audio_reader_task,
+    PttTransmitHandle,
+};
+use crate::whep::{has_content_type, parse_sdp_fragment};
+use crate::{AppState, PttState};
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+
+/// Time a publisher has to connect after its offer is answered
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time without audio after which a connected publisher is considered gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A WHIP publisher currently talking through the doorbell
+pub struct WhipSession {
+    pc: Arc<RTCPeerConnection>,
+    /// Keeps the DoorBird transmission running; dropping it stops PTT
+    _transmit_handle: PttTransmitHandle,
+    /// Ends the session and its audio reader
+    cancel: CancellationToken,
+    /// Audio bytes received, for status reporting
+    traffic: Arc<Traffic>,
+}
+
+/// Active WHIP sessions by ID
+pub type WhipSessions = RwLock<HashMap<Uuid, WhipSession>>;
+
+/// `POST /whip` - start talking from an SDP offer
+pub async fn create_session(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if state.shutdown.is_cancelled() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server shutting down").into_response();
+    }
+    if !has_content_type(&headers, "application/sdp") {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/sdp",
+        )
+            .into_response();
+    }
+    let offer = match String::from_utf8(body.to_vec()) {
+        Ok(sdp) => sdp,
+        Err(_) => return (StatusCode::BAD_REQUEST, "SDP offer is not UTF-8").into_response(),
+    };
+
+    let client = ClientInfo::new(addr, &headers, state.auth.user(addr, &headers));
+    let session_id = Uuid::new_v4();
+    info!("New WHIP session {}", session_id);
+
+    // Single talker lock, shared with browser push-to-talk
+    if !state.ptt_state.try_acquire(session_id).await {
+        return (StatusCode::CONFLICT, "another user is already talking").into_response();
+    }
+
+    match start_session(&state, session_id, offer).await {
+        Ok((whip_session, answer_sdp)) => {
+            let cancel = whip_session.cancel.clone();
+            let pc = whip_session.pc.clone();
+            let traffic = whip_session.traffic.clone();
+            state
+                .whip_sessions
+                .write()
+                .await
+                .insert(session_id, whip_session);
+            state
+                .sessions
+                .register(
+                    session_id,
+                    SessionKind::Whip,
+                    client.clone(),
+                    cancel.clone(),
+                    pc,
+                    traffic,
+                )
+                .await;
+            spawn_teardown(state.clone(), session_id, cancel);
+
+            let location = format!("/whip/{}", session_id);
+            let mut response = (StatusCode::CREATED, answer_sdp).into_response();
+            let headers = response.headers_mut();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/sdp"),
+            );
+            if let Ok(location) = HeaderValue::from_str(&location) {
+                headers.insert(header::LOCATION, location);
+            }
+            // STUN/TURN servers the client may use (RFC 9725 §4.6)
+            for link in ice_servers::link_headers(&state.client_ice_servers(&client)) {
+                if let Ok(link) = HeaderValue::from_str(&link) {
+                    headers.append(header::LINK, link);
+                }
+            }
+            response
+        }
+        Err(e) => {
+            warn!("WHIP session {} failed to start: {:#}", session_id, e);
+            state.ptt_state.release(session_id).await;
+            (
+                StatusCode::BAD_REQUEST,
+                format!("failed to start session: {}", e),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Create the peer connection, answer the offer and start the PTT pipeline
+async fn start_session(
+    state: &AppState,
+    session_id: Uuid,
+    offer: String,
+) -> anyhow::Result<(WhipSession, String)> {
+    let pc = Arc::new(state.webrtc_infra.new_peer_connection().await?);
+
+    // We only ever receive audio
+    pc.add_transceiver_from_kind(
+        RTPCodecType::Audio,
+        Some(RTCRtpTransceiverInit {
+            direction: RTCRtpTransceiverDirection::Recvonly,
+            send_encodings: vec![],
+        }),
+    )
+    .await?;
+
+    // A failed or closed connection ends the session
+    let cancel = CancellationToken::new();
+    let state_cancel = cancel.clone();
+    pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+        info!(
+            "WHIP session {} connection state changed: {:?}",
+            session_id, s
+        );
+        cancel_on_disconnect(s, &state_cancel);
+        Box::pin(async {})
+    }));
+
+    // Audio from the publisher feeds the transmitter for the lifetime of the session
+    let (audio_tx, audio_rx) = tokio::sync::mpsc::unbounded_channel::<RtpAudio>();
+    let audio_tx = Arc::new(Mutex::new(Some(audio_tx)));
+    let track_cancel = cancel.clone();
+    let traffic = Traffic::new(Instant::now());
+    let track_traffic = traffic.clone();
+    pc.on_track(Box::new(move |track, _receiver, _transceiver| {
+        let audio_tx = audio_tx.clone();
+        let cancel = track_cancel.clone();
+        let traffic = track_traffic.clone();
+        Box::pin(async move {
+            if track.kind() != RTPCodecType::Audio {
+                warn!(
+                    "WHIP session {} ignoring {} track",
+                    session_id,
+                    track.kind()
+                );
+                return;
+            }
+            info!("WHIP session {} receiving audio", session_id);
+            start_remote_audio_reader_task(track, audio_tx, traffic, cancel);
+        })
+    }));
+
+    let answer = match answer_with_gathered_candidates(&pc, offer).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            let _ = pc.close().await;
+            return Err(e);
+        }
+    };
+
+    let transmit_handle =
+        spawn_ptt_transmitter(state.doorbird_client.clone(), session_id, audio_rx);
+    spawn_watchdog(
+        session_id,
+        pc.clone(),
+        traffic.clone(),
+        transmit_handle.done(),
+        cancel.clone(),
+    );
+
+    Ok((
+        WhipSession {
+            pc,
+            _transmit_handle: transmit_handle,
+            cancel,
+            traffic,
+        },
+        answer.sdp,
+    ))
+}
+
+/// End the session if the publisher never connects, stops sending audio, or the
+/// DoorBird transmission ends
+fn spawn_watchdog(
+    session_id: Uuid,
+    pc: Arc<RTCPeerConnection>,
+    traffic: Arc<Traffic>,
+    transmit_done: CancellationToken,
+    cancel: CancellationToken,
+) {
+    tokio::spawn(async move {
+        let mut connected = false;
+        let mut received = 0;
+        loop {
+            let timeout = if connected {
+                IDLE_TIMEOUT
+            } else {
+                CONNECT_TIMEOUT
+            };
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = transmit_done.cancelled() => {
+                    warn!("WHIP session {} ended: DoorBird transmission stopped", session_id);
+                    break;
+                }
+                _ = tokio::time::sleep(timeout) => {}
+            }
+
+            if !connected {
+                if pc.connection_state() != RTCPeerConnectionState::Connected {
+                    warn!(
+                        "WHIP session {} did not connect within {:?}",
+                        session_id, CONNECT_TIMEOUT
+                    );
+                    break;
+                }
+                connected = true;
+                received = traffic.received();
+            } else {
+                let now_received = traffic.received();
+                if now_received == received {
+                    warn!(
+                        "WHIP session {} sent no audio for {:?}",
+                        session_id, IDLE_TIMEOUT
+                    );
+                    break;
+                }
+                received = now_received;
+            }
+        }
+        cancel.cancel();
+    });
+}
+
+/// `PATCH /whip/{id}` - trickle ICE candidates from the publisher
+pub async fn update_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(pc) = state
+        .whip_sessions
+        .read()
+        .await
+        .get(&session_id)
+        .map(|s| s.pc.clone())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !has_content_type(&headers, "application/trickle-ice-sdpfrag") {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected Content-Type: application/trickle-ice-sdpfrag",
+        )
+            .into_response();
+    }
+
+    let fragment = String::from_utf8_lossy(&body);
+    for c in parse_sdp_fragment(&fragment) {
+        let init = RTCIceCandidateInit {
+            candidate: c.candidate,
+            sdp_mid: c.sdp_mid,
+            sdp_mline_index: c.sdp_mline_index,
+            username_fragment: None,
+        };
+        if let Err(e) = pc.add_ice_candidate(init).await {
+            warn!("WHIP session {} rejected candidate: {:#}", session_id, e);
+            return (StatusCode::BAD_REQUEST, format!("invalid candidate: {}", e)).into_response();
+        }
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// `DELETE /whip/{id}` - stop talking
+pub async fn delete_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+) -> Response {
+    let Some(session) = state.whip_sessions.write().await.remove(&session_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    info!("WHIP session {} deleted by client", session_id);
+    close_session(session, &state.ptt_state, session_id).await;
+    StatusCode::OK.into_response()
+}
+
+/// Stop transmitting, close the peer connection and release the PTT lock
+async fn close_session(session: WhipSession, ptt_state: &PttState, session_id: Uuid) {
+    session.cancel.cancel();
+    if let Err(e) = session.pc.close().await {
+        error!("error closing WHIP session {}: {:#}", session_id, e);
+    }
+    // Dropping the session drops the transmit handle, ending the DoorBird POST
+    drop(session);
+    ptt_state.release(session_id).await;
+}
+
+/// Tear down a WHIP session once it is cancelled (peer connection failed or
+/// closed, `DELETE`, or shutdown)
+fn spawn_teardown(state: AppState, session_id: Uuid, cancel: CancellationToken) {
+    tokio::spawn(async move {
+        cancel.cancelled().await;
+
+        if let Some(session) = state.whip_sessions.write().await.remove(&session_id) {
+            info!("WHIP session {} ended, cleaning up", session_id);
+            close_session(session, &state.ptt_state, session_id).await;
+        }
+        state.sessions.remove(session_id).await;
+    });
+}